@@ -0,0 +1,255 @@
+//! `sixty_asm!{}` assembles 6502 source with `sixty::assembler` at compile time, to write
+//! test programs inline:
+//!
+//! ```
+//! use sixty_asm::sixty_asm;
+//!
+//! let program: Vec<u8> = sixty_asm! {
+//!     loop:   inx
+//!             bne loop    // Rust comments, they're skipped by the Rust lexer
+//!             rts
+//! };
+//! assert_eq!(program, vec![0xe8, 0xd0, 0xfd, 0x60]);
+//! let memory = sixty_asm! {
+//!     #[memory]
+//!     #[variant(Nmos6502)]
+//!             lda #0x42
+//!             rts
+//! };
+//! let source = sixty_asm!(r"
+//!             .org $1E    ; the source as is
+//!             lda #$1E
+//! ");
+//! assert_eq!(source[0x1e..], [0xa9, 0x1e]);
+//! ```
+//!
+//! It expands to the `Vec<u8>` of `Program::to_vec()`, or with `#[memory]` to a `Memory`
+//! created from it. The CPU is the 65C02 unless `#[variant(...)]` names another
+//! `CpuVariant`. Errors are compile errors pointing at the offending line:
+//!
+//! ```compile_fail
+//! # use sixty_asm::sixty_asm;
+//! let program: Vec<u8> = sixty_asm! { lda #256 };
+//! ```
+//!
+//! ```compile_fail
+//! # use sixty_asm::sixty_asm;
+//! let program: Vec<u8> = sixty_asm!(r"jmp nowhere");
+//! ```
+//!
+//! Without quotes, the source goes through the Rust lexer first, which splits it into tokens
+//! and drops the line breaks. The lines are put back together from the positions of the
+//! tokens. Hex numbers that the lexer reads as a broken float exponent, like `$1E`, have to
+//! be written `0x1E`, and `;` comments must be valid Rust tokens, so `//` comments are
+//! better. A string literal, best a raw one, keeps the source exactly as written instead.
+//! A span can't point inside a string, so its errors point at the whole literal and give
+//! the line of the Rust file in the message.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use sixty::assembler::assemble;
+use sixty::cpu::CpuVariant;
+use sixty::error::SixtyError;
+
+#[proc_macro]
+pub fn sixty_asm(input: TokenStream) -> TokenStream {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let mut memory = false;
+    let mut variant = CpuVariant::Cmos65C02;
+    let mut start = 0;
+    // The #[...] options
+    while let (Some(TokenTree::Punct(hash)), Some(TokenTree::Group(group)))
+            = (tokens.get(start), tokens.get(start + 1)) {
+        if hash.as_char() != '#' || group.delimiter() != Delimiter::Bracket {
+            break;
+        }
+        let option = group.stream().to_string().replace(' ', "");
+        match option.as_str() {
+            "memory" => memory = true,
+            "variant(Nmos6502)" => variant = CpuVariant::Nmos6502,
+            "variant(Cmos65C02)" => variant = CpuVariant::Cmos65C02,
+            "variant(Ricoh2A03)" => variant = CpuVariant::Ricoh2A03,
+            "variant(Mos6507)" => variant = CpuVariant::Mos6507,
+            "variant(Mos6510)" => variant = CpuVariant::Mos6510,
+            _ => return error(group.span(), &format!("unknown option #[{}]", option)),
+        }
+        start += 2;
+    }
+
+    let lines = match &tokens[start..] {
+        [TokenTree::Literal(literal)] => match string_lines(literal) {
+            Some(lines) => lines,
+            None => return error(literal.span(), "expected 6502 source or a string"),
+        },
+        tokens => lines(tokens),
+    };
+    let source: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+    match assemble(&source.join("\n"), variant) {
+        Ok(program) => {
+            let bytes: Vec<String> = program.to_vec().iter().map(|b| format!("{:#04x}u8", b)).collect();
+            let vec = format!("::std::vec![{}]", bytes.join(", "));
+            let expression = if memory {
+                format!("::sixty::memory::Memory::new_with_vec({}, ::std::option::Option::None)", vec)
+            } else {
+                vec
+            };
+            match expression.parse() {
+                Ok(tokens) => tokens,
+                Err(e) => error(Span::call_site(), &format!("couldn't expand the program: {}", e)),
+            }
+        },
+        Err(SixtyError::Assembly { line, message }) => {
+            let line = &lines[line - 1];
+            match line.rust_line {
+                Some(rust_line) =>
+                    error(line.span, &format!("{} in `{}` on line {}", message, line.text.trim(), rust_line)),
+                None => error(line.span, &format!("{} in `{}`", message, line.text)),
+            }
+        },
+        Err(e) => error(Span::call_site(), &e.to_string()),
+    }
+}
+
+/// A line of the source and where to report its errors
+struct Line {
+    span: Span,
+    /// For the lines of a string literal, whose span covers the whole string
+    rust_line: Option<usize>,
+    text: String,
+}
+
+/// A token and where it is in the Rust source
+struct Token {
+    line: usize,
+    start: usize,
+    end: usize,
+    text: String,
+    span: Span,
+}
+
+/// Rebuild the lines of source from the tokens, with the span of the first token of each
+fn lines(tokens: &[TokenTree]) -> Vec<Line> {
+    let mut flat = Vec::new();
+    flatten(tokens, &mut flat);
+    let mut result: Vec<(usize, Span, String)> = Vec::new();
+    let mut previous_end = 0;
+    for token in flat {
+        match result.last_mut() {
+            Some((line, _, text)) if *line == token.line => {
+                if token.start > previous_end {
+                    text.push(' ');
+                }
+                text.push_str(&token.text);
+            },
+            _ => result.push((token.line, token.span, token.text)),
+        }
+        previous_end = token.end;
+    }
+    result.into_iter().map(|(_, span, text)| Line { span, rust_line: None, text }).collect()
+}
+
+/// The lines of a string literal, raw or with escapes, or `None` if it isn't a string
+fn string_lines(literal: &Literal) -> Option<Vec<Line>> {
+    let span = literal.span();
+    let first_line = span.start().line();
+    let line = |offset: usize, text: String| Line { span, rust_line: Some(first_line + offset), text };
+    let literal = literal.to_string();
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let contents = raw.get(hashes + 1..raw.len().checked_sub(hashes + 1)?)?;
+        return Some(contents.split('\n').enumerate()
+            .map(|(offset, text)| line(offset, text.to_string()))
+            .collect());
+    }
+    let contents = literal.strip_prefix('"')?.strip_suffix('"')?;
+    // The escapes, \n included, and the line continuations move the lines of the source
+    // away from the lines of the literal
+    let (mut result, mut text, mut offset, mut start) = (Vec::new(), String::new(), 0, 0);
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'x' => {
+                    let digits: String = chars.by_ref().take(2).collect();
+                    u8::from_str_radix(&digits, 16).ok()? as char
+                },
+                'u' => {
+                    let digits: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                    std::char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?
+                },
+                '\n' => {
+                    offset += 1;
+                    while let Some(c) = chars.next_if(|c| c.is_whitespace()) {
+                        if c == '\n' {
+                            offset += 1;
+                        }
+                    }
+                    continue;
+                },
+                c => c,
+            },
+            '\n' => {
+                offset += 1;
+                '\n'
+            },
+            c => c,
+        };
+        if c == '\n' {
+            result.push(line(start, std::mem::take(&mut text)));
+            start = offset;
+        } else {
+            text.push(c);
+        }
+    }
+    result.push(line(start, text));
+    Some(result)
+}
+
+fn flatten(tokens: &[TokenTree], result: &mut Vec<Token>) {
+    let token = |span: Span, text: String| Token {
+        line: span.start().line(),
+        start: span.start().column(),
+        end: span.end().column(),
+        text,
+        span,
+    };
+    for tree in tokens {
+        match tree {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::None => ("", ""),
+                };
+                if ! open.is_empty() {
+                    result.push(token(group.span_open(), open.to_string()));
+                }
+                flatten(&group.stream().into_iter().collect::<Vec<_>>(), result);
+                if ! close.is_empty() {
+                    result.push(token(group.span_close(), close.to_string()));
+                }
+            },
+            TokenTree::Punct(punct) => result.push(token(punct.span(), punct.as_char().to_string())),
+            _ => result.push(token(tree.span(), tree.to_string())),
+        }
+    }
+}
+
+/// `compile_error!(message)`, reported at `span`
+fn error(span: Span, message: &str) -> TokenStream {
+    let mut punct = Punct::new('!', Spacing::Alone);
+    punct.set_span(span);
+    let mut literal = Literal::string(message);
+    literal.set_span(span);
+    let mut group = Group::new(Delimiter::Parenthesis, TokenTree::Literal(literal).into());
+    group.set_span(span);
+    vec![
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(punct),
+        TokenTree::Group(group),
+    ].into_iter().collect()
+}
@@ -0,0 +1,464 @@
+//! What each instruction does to the registers and flags once its operand is known, shared
+//! by the 6502, the 65C02 and the 65C816. The 6502s always run with the 8 bit registers of
+//! the emulation mode of the 65C816, so the width of an operation only changes in native
+//! mode, when the M and X flags are clear.
+//!
+//! The bus cycles that fetch the operands are in `cycle.rs` for the 6502s and in
+//! `w65c816.rs` for the 65C816.
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::decoder::Mnemonic::{self, *};
+
+impl<B: Bus> Cpu<B> {
+    /// The 16 bit accumulator, B:A
+    pub fn accumulator(&self) -> u16 {
+        (self.b as u16) << 8 | self.a as u16
+    }
+
+    pub fn x_register(&self) -> u16 {
+        (self.xh as u16) << 8 | self.x as u16
+    }
+
+    pub fn y_register(&self) -> u16 {
+        (self.yh as u16) << 8 | self.y as u16
+    }
+
+    /// The 16 bit stack pointer, always in page 1 in emulation mode
+    pub fn stack_register(&self) -> u16 {
+        if self.e {
+            0x100 | (self.stack_pointer & 0xff) as u16
+        } else {
+            self.stack_pointer as u16
+        }
+    }
+
+    pub(crate) fn set_stack_register(&mut self, s: u16) {
+        self.stack_pointer = if self.e { (s & 0xff) as usize } else { s as usize };
+    }
+
+    /// 16 bit accumulator and memory accesses
+    pub(crate) fn wide_a(&self) -> bool {
+        ! self.e && ! self.p.m()
+    }
+
+    /// 16 bit index registers
+    pub(crate) fn wide_index(&self) -> bool {
+        ! self.e && ! self.p.x()
+    }
+
+    pub(crate) fn get_a(&self) -> u16 {
+        if self.wide_a() { self.accumulator() } else { self.a as u16 }
+    }
+
+    /// In 8 bit mode, B is left alone
+    pub(crate) fn set_a(&mut self, v: u16) {
+        self.a = v as u8;
+        if self.wide_a() {
+            self.b = (v >> 8) as u8;
+        }
+    }
+
+    pub(crate) fn get_x(&self) -> u16 {
+        if self.wide_index() { self.x_register() } else { self.x as u16 }
+    }
+
+    pub(crate) fn set_x(&mut self, v: u16) {
+        self.x = v as u8;
+        self.xh = if self.wide_index() { (v >> 8) as u8 } else { 0 };
+    }
+
+    pub(crate) fn get_y(&self) -> u16 {
+        if self.wide_index() { self.y_register() } else { self.y as u16 }
+    }
+
+    pub(crate) fn set_y(&mut self, v: u16) {
+        self.y = v as u8;
+        self.yh = if self.wide_index() { (v >> 8) as u8 } else { 0 };
+    }
+
+    pub(crate) fn set_nz(&mut self, v: u16, wide: bool) {
+        if wide {
+            self.p.set_z(v == 0);
+            self.p.set_n(v & 0x8000 != 0);
+        } else {
+            self.p.set_nz_flags(v as u8);
+        }
+    }
+
+    /// The instructions without an operand: flags, transfers, increments and decrements of
+    /// the index registers, and the accumulator versions of the shifts, INC and DEC
+    pub(crate) fn implied(&mut self, mnemonic: Mnemonic) {
+        let (wide_a, wide_index) = (self.wide_a(), self.wide_index());
+        match mnemonic {
+            CLC => self.p.set_c(false),
+            SEC => self.p.set_c(true),
+            CLI => self.p.set_i(false),
+            SEI => self.p.set_i(true),
+            CLD => self.p.set_d(false),
+            SED => self.p.set_d(true),
+            CLV => self.p.set_v(false),
+            NOP => {},
+            TXS => self.set_stack_register(self.x_register()),
+            TAX | TSX | INX | DEX => {
+                let result = match mnemonic {
+                    // With 16 bit index registers, TAX and TAY copy B too
+                    TAX if wide_index => self.accumulator(),
+                    TAX => self.a as u16,
+                    TSX => self.stack_register(),
+                    INX => self.get_x().wrapping_add(1),
+                    _ => self.get_x().wrapping_sub(1),
+                };
+                self.set_x(result);
+                self.set_nz(self.get_x(), wide_index);
+            },
+            TAY | INY | DEY => {
+                let result = match mnemonic {
+                    TAY if wide_index => self.accumulator(),
+                    TAY => self.a as u16,
+                    INY => self.get_y().wrapping_add(1),
+                    _ => self.get_y().wrapping_sub(1),
+                };
+                self.set_y(result);
+                self.set_nz(self.get_y(), wide_index);
+            },
+            TXA | TYA => {
+                self.set_a(if mnemonic == TXA { self.x_register() } else { self.y_register() });
+                self.set_nz(self.get_a(), wide_a);
+            },
+            ASL | LSR | ROL | ROR => {
+                let result = self.shift(mnemonic, self.get_a(), wide_a);
+                self.set_a(result);
+            },
+            INC | DEC => {
+                let result = self.increment(mnemonic == INC, self.get_a(), wide_a);
+                self.set_a(result);
+            },
+            _ => unreachable!("{} is not an implied instruction", mnemonic),
+        }
+    }
+
+    /// The instructions that read their operand, immediate or from memory. The index
+    /// registers instructions use the width of the index registers, the others the width of
+    /// the accumulator.
+    pub(crate) fn read_operation(&mut self, mnemonic: Mnemonic, immediate: bool, v: u16) {
+        let (wide_a, wide_index) = (self.wide_a(), self.wide_index());
+        match mnemonic {
+            ADC => self.adc(v),
+            SBC => self.sbc(v),
+            CMP => self.compare(self.get_a(), v, wide_a),
+            CPX => self.compare(self.get_x(), v, wide_index),
+            CPY => self.compare(self.get_y(), v, wide_index),
+            BIT => {
+                let top = if wide_a { 0x8000 } else { 0x80 };
+                self.p.set_z(v & self.get_a() == 0);
+                // The immediate version only affects the Z flag
+                if ! immediate {
+                    self.p.set_n(v & top != 0);
+                    self.p.set_v(v & (top >> 1) != 0);
+                }
+            },
+            AND | ORA | EOR | LDA => {
+                let a = self.get_a();
+                self.set_a(match mnemonic {
+                    AND => a & v,
+                    ORA => a | v,
+                    EOR => a ^ v,
+                    _ => v,
+                });
+                self.set_nz(self.get_a(), wide_a);
+            },
+            LDX => {
+                self.set_x(v);
+                self.set_nz(self.get_x(), wide_index);
+            },
+            LDY => {
+                self.set_y(v);
+                self.set_nz(self.get_y(), wide_index);
+            },
+            NOP => {},
+            // The undocumented opcodes of the NMOS 6502, always 8 bit
+            ARR => self.arr(v as u8),
+            ALR => {
+                self.a &= v as u8;
+                self.a = self.shift(LSR, self.a as u16, false) as u8;
+            },
+            SBX => {
+                let ax = self.a & self.x;
+                self.p.set_c(ax >= v as u8);
+                self.x = ax.wrapping_sub(v as u8);
+                self.p.set_nz_flags(self.x);
+            },
+            ANC => {
+                self.a &= v as u8;
+                self.p.set_nz_flags(self.a);
+                self.p.set_c(self.p.n());
+            },
+            LAX => {
+                self.a = v as u8;
+                self.x = v as u8;
+                self.p.set_nz_flags(self.a);
+            },
+            _ => unreachable!("{} is not a read instruction", mnemonic),
+        }
+    }
+
+    /// The read-modify-write instructions: returns the value to write back. `opcode` selects
+    /// the bit of RMB and SMB.
+    pub(crate) fn modify_operation(&mut self, mnemonic: Mnemonic, opcode: u8, v: u16) -> u16 {
+        let wide_a = self.wide_a();
+        match mnemonic {
+            ASL | LSR | ROL | ROR => self.shift(mnemonic, v, wide_a),
+            INC | DEC => self.increment(mnemonic == INC, v, wide_a),
+            TSB | TRB => {
+                let a = self.get_a();
+                self.p.set_z(v & a == 0);
+                if mnemonic == TSB { v | a } else { v & ! a }
+            },
+            // The undocumented opcodes of the NMOS 6502 combine a shift with an operation
+            // on the accumulator
+            SLO | RLA | SRE | RRA => {
+                let shift = match mnemonic {
+                    SLO => ASL,
+                    RLA => ROL,
+                    SRE => LSR,
+                    _ => ROR,
+                };
+                let result = self.shift(shift, v, false);
+                match mnemonic {
+                    SLO => self.a |= result as u8,
+                    RLA => self.a &= result as u8,
+                    SRE => self.a ^= result as u8,
+                    _ => self.adc(result),
+                }
+                if mnemonic != RRA {
+                    self.p.set_nz_flags(self.a);
+                }
+                result
+            },
+            DCP => {
+                let result = (v as u8).wrapping_sub(1);
+                self.compare(self.a as u16, result as u16, false);
+                result as u16
+            },
+            ISC => {
+                let result = (v as u8).wrapping_add(1);
+                self.sbc(result as u16);
+                result as u16
+            },
+            // RMB are the opcodes $07-$77, SMB $87-$F7
+            RMB0 | RMB1 | RMB2 | RMB3 | RMB4 | RMB5 | RMB6 | RMB7 => v & ! (1 << (opcode >> 4)),
+            SMB0 | SMB1 | SMB2 | SMB3 | SMB4 | SMB5 | SMB6 | SMB7 => v | 1 << ((opcode >> 4) - 8),
+            _ => unreachable!("{} is not a read-modify-write instruction", mnemonic),
+        }
+    }
+
+    /// ASL, LSR, ROL or ROR of `v`, 8 or 16 bit wide
+    pub(crate) fn shift(&mut self, mnemonic: Mnemonic, v: u16, wide: bool) -> u16 {
+        let top = if wide { 0x8000 } else { 0x80 };
+        let mask = if wide { 0xffff } else { 0xff };
+        let carry = self.p.c();
+        let result = match mnemonic {
+            ASL => (v << 1) & mask,
+            ROL => ((v << 1) | carry as u16) & mask,
+            LSR => v >> 1,
+            _ => (v >> 1) | if carry { top } else { 0 },
+        };
+        self.p.set_c(match mnemonic {
+            ASL | ROL => v & top != 0,
+            _ => v & 1 != 0,
+        });
+        self.set_nz(result, wide);
+        result
+    }
+
+    fn increment(&mut self, up: bool, v: u16, wide: bool) -> u16 {
+        let mask = if wide { 0xffff } else { 0xff };
+        let result = if up { v.wrapping_add(1) } else { v.wrapping_sub(1) } & mask;
+        self.set_nz(result, wide);
+        result
+    }
+
+    pub(crate) fn compare(&mut self, register: u16, v: u16, wide: bool) {
+        self.p.set_c(register >= v);
+        self.set_nz(register.wrapping_sub(v), wide);
+    }
+
+    /// ARR is an AND followed by a ROR, with its own rules for the flags.
+    fn arr(&mut self, v: u8) {
+        let and = self.a & v;
+        let result = (and >> 1) | (self.p.c() as u8) << 7;
+        if self.p.d() && self.variant.has_decimal_mode() {
+            self.p.set_n(self.p.c());
+            self.p.set_z(result == 0);
+            self.p.set_v((and ^ result) & 0x40 != 0);
+            let mut result = result;
+            let (high, low) = (and >> 4, and & 0x0f);
+            if low + (low & 1) > 5 {
+                result = (result & 0xf0) | (result.wrapping_add(6) & 0x0f);
+            }
+            let carry = high + (high & 1) > 5;
+            if carry {
+                result = result.wrapping_add(0x60);
+            }
+            self.p.set_c(carry);
+            self.a = result;
+        } else {
+            self.p.set_nz_flags(result);
+            self.p.set_c(result & 0x40 != 0);
+            self.p.set_v(((result >> 6) ^ (result >> 5)) & 1 != 0);
+            self.a = result;
+        }
+    }
+
+    fn adc(&mut self, v: u16) {
+        if self.wide_a() {
+            self.adc16(v);
+            return;
+        }
+        let v = v as u8;
+        let decimal = self.p.d() && self.variant.has_decimal_mode();
+        if decimal && ! self.variant.is_cmos() {
+            self.adc_nmos_decimal(v);
+        } else if decimal {
+            let mut l = (self.a & 0x0f) + (v & 0x0f) + self.p.c() as u8;
+            if l > 9 { l += 6 }
+            let mut h = (self.a >> 4) + (v >> 4) + if l > 15 { 1 } else { 0 };
+            if h > 9 { h += 6 };
+            let result = l & 0x0f | (h << 4);
+
+            self.p.set_c(h > 15);
+            self.p.set_z(result == 0);
+            self.p.set_v(false);  // BCD never sets overflow flag
+            self.p.set_n((result & 0x80) != 0);  // N flag is valid on CMOS 6502/65816
+
+            self.a = result;
+        } else {
+            self.add(v);
+        }
+    }
+
+    /// The NMOS 6502 computes N and V from the intermediate result, before the high
+    /// nibble gets adjusted, and Z from the binary sum.
+    fn adc_nmos_decimal(&mut self, v: u8) {
+        let carry = self.p.c() as u16;
+        let (a, v) = (self.a as u16, v as u16);
+        let mut l = (a & 0x0f) + (v & 0x0f) + carry;
+        if l >= 0x0a {
+            l = ((l + 0x06) & 0x0f) + 0x10;
+        }
+        let mut result = (a & 0xf0) + (v & 0xf0) + l;
+        self.p.set_z((a + v + carry) & 0xff == 0);
+        self.p.set_n(result & 0x80 != 0);
+        self.p.set_v((! (a ^ v) & (a ^ result) & 0x80) != 0);
+        if result >= 0xa0 {
+            result += 0x60;
+        }
+        self.p.set_c(result >= 0x100);
+        self.a = result as u8;
+    }
+
+    /// The NMOS 6502 sets all the flags from the binary subtraction, only the accumulator
+    /// is decimal adjusted.
+    fn sbc_nmos_decimal(&mut self, v: u8) {
+        let borrow = ! self.p.c() as i16;
+        let (a, v16) = (self.a as i16, v as i16);
+        let mut l = (a & 0x0f) - (v16 & 0x0f) - borrow;
+        if l < 0 {
+            l = ((l - 0x06) & 0x0f) - 0x10;
+        }
+        let mut result = (a & 0xf0) - (v16 & 0xf0) + l;
+        if result < 0 {
+            result -= 0x60;
+        }
+        self.add(v ^ 0xff);
+        self.a = result as u8;
+    }
+
+    fn add(&mut self, v: u8) {
+        let result: u16 = self.a as u16 + v as u16 + self.p.c() as u16;
+        // NOTE: Parentheses are important here! Remove them and carry6 is incorrectly calculated
+        let carry6 = (self.a & 0x7f) + (v & 0x7f) + self.p.c() as u8;
+        self.p.set_c(result & 0x100 != 0);
+        self.p.set_v(self.p.c() ^ (carry6 & 0x80 != 0));
+        let result2 = result as u8;
+        self.p.set_nz_flags(result2);
+        self.a = result2;
+    }
+
+    fn sbc(&mut self, v: u16) {
+        if self.wide_a() {
+            self.sbc16(v);
+            return;
+        }
+        let v = v as u8;
+        let decimal = self.p.d() && self.variant.has_decimal_mode();
+        if decimal && ! self.variant.is_cmos() {
+            self.sbc_nmos_decimal(v);
+        } else if decimal {
+            let mut l: i16 = (self.a as i16 & 0x0f) - (v as i16 & 0x0f)
+                - if self.p.c() { 0 } else { 1 };
+            if (l & 0x10) != 0 { l -= 6 };
+            let mut h: i16 = (self.a  as i16 >> 4) - (v as i16 >> 4)
+                - if (l & 0x10) != 0 { 1 } else { 0 };
+            if (h & 0x10) != 0 { h -= 6 }
+            let result = (l & 0x0f | (h << 4)) & 0xff;
+
+            self.p.set_c((h & 0xff) < 15);
+            self.p.set_z(result == 0);
+            self.p.set_v(false);  // BCD never sets overflow flag
+            self.p.set_n((result & 0x80) != 0);
+
+            self.a = result as u8;
+        } else {
+            self.add(v ^ 0xff);
+        }
+    }
+
+    /// The 16 bit accumulator of the 65C816
+    fn adc16(&mut self, v: u16) {
+        let (a, v32, carry) = (self.accumulator() as u32, v as u32, self.p.c() as u32);
+        let (result, carry) = if self.p.d() {
+            let (mut result, mut carry) = (0, carry);
+            for shift in [0, 4, 8, 12].iter() {
+                let mut digit = ((a >> shift) & 0xf) + ((v32 >> shift) & 0xf) + carry;
+                carry = (digit > 9) as u32;
+                if carry == 1 {
+                    digit += 6;
+                }
+                result |= (digit & 0xf) << shift;
+            }
+            (result, carry == 1)
+        } else {
+            let result = a + v32 + carry;
+            (result & 0xffff, result > 0xffff)
+        };
+        self.p.set_c(carry);
+        self.p.set_v(! (a ^ v32) & (a ^ result) & 0x8000 != 0);
+        self.set_nz(result as u16, true);
+        self.a = result as u8;
+        self.b = (result >> 8) as u8;
+    }
+
+    fn sbc16(&mut self, v: u16) {
+        if ! self.p.d() {
+            self.adc16(! v);
+            return;
+        }
+        let (a, v32) = (self.accumulator() as i32, v as i32);
+        let (mut result, mut borrow) = (0, ! self.p.c() as i32);
+        for shift in [0, 4, 8, 12].iter() {
+            let mut digit = ((a >> shift) & 0xf) - ((v32 >> shift) & 0xf) - borrow;
+            borrow = (digit < 0) as i32;
+            if borrow == 1 {
+                digit += 10;
+            }
+            result |= (digit & 0xf) << shift;
+        }
+        self.p.set_c(borrow == 0);
+        self.p.set_v((a ^ v32) & (a ^ result) & 0x8000 != 0);
+        self.set_nz(result as u16, true);
+        self.a = result as u8;
+        self.b = (result >> 8) as u8;
+    }
+}
@@ -0,0 +1,700 @@
+//! A two-pass assembler for 6502 and 65C02 source. It encodes instructions with the same
+//! opcode, addressing type and size tables as the CPU and the disassembler, so the three
+//! always agree.
+//!
+//! The syntax is the usual one:
+//!
+//! ```text
+//! count = 3               ; a constant
+//!         .org $0800
+//! start:  ldx #count      ; labels end with a colon
+//! loop:   lda table-1,x
+//!         sta $10         ; zero page when the address fits in a byte
+//!         dex
+//!         bne loop
+//!         rts
+//! table:  .byte 1, 2, "ab"
+//!         .word start, * + 2
+//!         .res 4
+//! ```
+//!
+//! Expressions have numbers in decimal, `$hex` (or `0xhex`) and `%binary`, characters like `'a'`, labels,
+//! `*` for the address of the current line, the unary operators `-`, `~`, `<` (low byte) and
+//! `>` (high byte) and the binary operators `* / + - << >> & ^ |`, with parentheses. A
+//! label used before its definition is assumed to be a 16 bit address, unless the
+//! instruction only has a zero page form for it, and `a:` in front of an operand, like in
+//! `lda a:$12`, forces absolute addressing. The 65C816 isn't supported,
+//! `assemble()` returns `SixtyError::Unsupported` for it.
+
+use crate::constants::AddressingType;
+use crate::cpu::CpuVariant;
+use crate::error::SixtyError;
+use std::collections::HashMap;
+
+/// The output of `assemble()`
+#[derive(Debug)]
+pub struct Program {
+    /// The bytes and their address, each `.org` starts a new segment
+    pub segments: Vec<(usize, Vec<u8>)>,
+    /// The value of every label and constant
+    pub labels: HashMap<String, usize>,
+}
+
+impl Program {
+    /// The segments laid out from address 0, with the gaps filled with 0, ready for
+    /// `Memory::new_with_vec()`
+    pub fn to_vec(&self) -> Vec<u8> {
+        let end = self.segments.iter().map(|(address, bytes)| address + bytes.len()).max();
+        let mut result = vec![0; end.unwrap_or(0)];
+        for (address, bytes) in &self.segments {
+            result[*address..address + bytes.len()].copy_from_slice(bytes);
+        }
+        result
+    }
+}
+
+/// Assemble `source` for `variant`, which can't be the 65C816
+pub fn assemble(source: &str, variant: CpuVariant) -> Result<Program, SixtyError> {
+    if variant == CpuVariant::W65C816 {
+        return Err(SixtyError::Unsupported { variant, feature: "The assembler" });
+    }
+    let mut assembler = Assembler {
+        variant,
+        labels: HashMap::new(),
+        modes: HashMap::new(),
+        segments: Vec::new(),
+        pc: 0,
+        final_pass: false,
+    };
+    assembler.pass(source)?;
+    assembler.final_pass = true;
+    assembler.pass(source)?;
+    Ok(Program { segments: assembler.segments, labels: assembler.labels })
+}
+
+/// The syntax of an operand, before its addressing type is chosen
+enum Operand<'a> {
+    Implied,
+    Accumulator,
+    Immediate(&'a str),
+    Direct(&'a str),
+    IndexedX(&'a str),
+    IndexedY(&'a str),
+    IndirectX(&'a str),
+    IndirectY(&'a str),
+    Indirect(&'a str),
+    /// BBR and BBS: a zero page address and a branch target
+    BitBranch(&'a str, &'a str),
+}
+
+struct Assembler {
+    variant: CpuVariant,
+    labels: HashMap<String, usize>,
+    /// The addressing type picked for each line in the first pass. The second pass must
+    /// keep it, even if a label defined later would now fit in the zero page, so that the
+    /// sizes and therefore the labels don't change.
+    modes: HashMap<usize, AddressingType>,
+    segments: Vec<(usize, Vec<u8>)>,
+    pc: usize,
+    final_pass: bool,
+}
+
+impl Assembler {
+    fn pass(&mut self, source: &str) -> Result<(), SixtyError> {
+        self.pc = 0;
+        self.segments = vec![(0, Vec::new())];
+        for (index, line) in source.lines().enumerate() {
+            self.line(index, strip_comment(line))
+                .map_err(|message| SixtyError::Assembly { line: index + 1, message })?;
+        }
+        self.segments.retain(|(_, bytes)| ! bytes.is_empty());
+        Ok(())
+    }
+
+    fn line(&mut self, index: usize, line: &str) -> Result<(), String> {
+        let mut rest = line.trim();
+        let name_end = rest.find(|c: char| ! is_label_char(c)).unwrap_or(rest.len());
+        let (name, after) = rest.split_at(name_end);
+        let after = after.trim_start();
+        if ! name.is_empty() && ! name.starts_with(|c: char| c.is_ascii_digit()) {
+            if let Some(value) = after.strip_prefix('=') {
+                let value = self.evaluate(value)?
+                    .ok_or_else(|| format!("{} must be defined before it's used", name))?;
+                return self.define(name, value as usize);
+            }
+            if let Some(statement) = after.strip_prefix(':') {
+                self.define(name, self.pc)?;
+                rest = statement.trim();
+            }
+        }
+        if rest.is_empty() {
+            Ok(())
+        } else if let Some(directive) = rest.strip_prefix('.') {
+            self.directive(directive)
+        } else {
+            self.instruction(index, rest)
+        }
+    }
+
+    fn define(&mut self, name: &str, value: usize) -> Result<(), String> {
+        if ! self.final_pass && self.labels.insert(name.to_string(), value).is_some() {
+            return Err(format!("{} is defined twice", name));
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, text: &str) -> Result<(), String> {
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        let (name, arguments) = (text[..end].to_ascii_lowercase(), text[end..].trim());
+        match name.as_str() {
+            "org" => {
+                let address = self.known(arguments, ".org")?;
+                if ! (0..=0xffff).contains(&address) {
+                    return Err(format!("${:X} is outside of the address space", address));
+                }
+                self.pc = address as usize;
+                match self.segments.last_mut() {
+                    Some((start, bytes)) if bytes.is_empty() => *start = self.pc,
+                    _ => self.segments.push((self.pc, Vec::new())),
+                }
+            },
+            "byte" | "word" => {
+                let mut parser = Parser::new(arguments, self);
+                let mut bytes = Vec::new();
+                loop {
+                    if let Some(string) = parser.string()? {
+                        bytes.extend_from_slice(string.as_bytes());
+                    } else {
+                        let value = parser.expression()?;
+                        if name == "byte" {
+                            bytes.push(to_byte(value)?);
+                        } else {
+                            let word = to_word(value)?;
+                            bytes.extend_from_slice(&[word as u8, (word >> 8) as u8]);
+                        }
+                    }
+                    if ! parser.eat(",") {
+                        break;
+                    }
+                }
+                parser.end()?;
+                bytes.iter().try_for_each(|b| self.emit(*b))?;
+            },
+            "res" => {
+                let mut parts = arguments.splitn(2, ',');
+                let count = self.known(parts.next().unwrap_or(""), ".res")?;
+                let fill = match parts.next() {
+                    Some(fill) => to_byte(self.evaluate(fill)?)?,
+                    None => 0,
+                };
+                if count < 0 {
+                    return Err("negative .res count".to_string());
+                }
+                (0..count).try_for_each(|_| self.emit(fill))?;
+            },
+            _ => return Err(format!("unknown directive .{}", name)),
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, index: usize, text: &str) -> Result<(), String> {
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        let name = text[..end].to_ascii_uppercase();
+        let operand = text[end..].trim();
+        // a:$12 forces absolute addressing for an address in the zero page
+        let (absolute, operand) = match operand.get(..2) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("a:") => (true, operand[2..].trim_start()),
+            _ => (false, operand),
+        };
+        let operand = parse_operand(&name, operand);
+        let names = self.variant.opcode_names();
+        if ! names.contains(&name.as_str()) {
+            return Err(format!("unknown instruction {}", name));
+        }
+        let pc = self.pc;
+        let (mode, values) = match self.modes.get(&index) {
+            Some(mode) if self.final_pass => (*mode, self.operand_values(&operand)?),
+            _ => {
+                let values = self.operand_values(&operand)?;
+                let mode = self.choose_mode(&name, &operand, values[0], absolute)?;
+                self.modes.insert(index, mode);
+                (mode, values)
+            },
+        };
+        let opcode = opcode(self.variant, &name, mode)
+            .ok_or_else(|| format!("{} doesn't support this addressing mode", name))?;
+        let size = self.variant.sizes()[opcode as usize];
+        self.emit(opcode)?;
+        let value = values[0].unwrap_or(0);
+        match mode {
+            AddressingType::NONE | AddressingType::REGISTER_A => {},
+            AddressingType::RELATIVE => {
+                if size == 3 {
+                    self.emit(to_zero_page(value)?)?;
+                }
+                let target = values[values.len() - 1].unwrap_or(pc as i64 + size as i64);
+                let offset = target - (pc + size) as i64;
+                if self.final_pass && ! (-128..=127).contains(&offset) {
+                    return Err(format!("branch target out of range by {} bytes",
+                        if offset < 0 { -128 - offset } else { offset - 127 }));
+                }
+                self.emit(offset as u8)?;
+            },
+            AddressingType::IMMEDIATE => self.emit(to_byte(values[0])?)?,
+            _ if size == 2 => self.emit(to_zero_page(value)?)?,
+            _ => {
+                let word = to_word(values[0])?;
+                self.emit(word as u8)?;
+                self.emit((word >> 8) as u8)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// The values of the expressions of the operand, `None` for the ones that use a label
+    /// that isn't defined yet
+    fn operand_values(&self, operand: &Operand) -> Result<Vec<Option<i64>>, String> {
+        match operand {
+            Operand::Implied | Operand::Accumulator => Ok(vec![None]),
+            Operand::Immediate(e) | Operand::Direct(e) | Operand::IndexedX(e)
+                | Operand::IndexedY(e) | Operand::IndirectX(e) | Operand::IndirectY(e)
+                | Operand::Indirect(e) => Ok(vec![self.evaluate(e)?]),
+            Operand::BitBranch(zp, target) => Ok(vec![self.evaluate(zp)?, self.evaluate(target)?]),
+        }
+    }
+
+    fn choose_mode(&self, name: &str, operand: &Operand, value: Option<i64>, absolute: bool)
+            -> Result<AddressingType, String> {
+        use AddressingType::*;
+        let zero_page = ! absolute && matches!(value, Some(0..=0xff));
+        // The modes to try, in order of preference
+        let candidates: &[AddressingType] = match operand {
+            Operand::Implied => &[NONE, REGISTER_A],
+            Operand::Accumulator => &[REGISTER_A, NONE],
+            Operand::Immediate(_) => &[IMMEDIATE],
+            Operand::Direct(_) if zero_page => &[RELATIVE, ZP, ABSOLUTE],
+            Operand::Direct(_) => &[RELATIVE, ABSOLUTE],
+            Operand::IndexedX(_) if zero_page => &[ZP_X, ABSOLUTE_X],
+            // A label defined later has to be in the zero page for STY and STX
+            Operand::IndexedX(_) if ! absolute && value.is_none() => &[ABSOLUTE_X, ZP_X],
+            Operand::IndexedX(_) => &[ABSOLUTE_X],
+            Operand::IndexedY(_) if zero_page => &[ZP_Y, ABSOLUTE_Y],
+            Operand::IndexedY(_) if ! absolute && value.is_none() => &[ABSOLUTE_Y, ZP_Y],
+            Operand::IndexedY(_) => &[ABSOLUTE_Y],
+            Operand::IndirectX(_) => &[INDIRECT_X, AIX],
+            Operand::IndirectY(_) => &[INDIRECT_Y],
+            Operand::Indirect(_) => &[ZPI, INDIRECT],
+            Operand::BitBranch(_, _) => &[RELATIVE],
+        };
+        candidates.iter().copied()
+            .find(|mode| opcode(self.variant, name, *mode).is_some())
+            .ok_or_else(|| format!("{} doesn't support this addressing mode", name))
+    }
+
+    /// Like `.org`, the bytes have to stay in the first 64K
+    fn emit(&mut self, byte: u8) -> Result<(), String> {
+        if self.pc > 0xffff {
+            return Err(format!("${:X} is outside of the address space", self.pc));
+        }
+        if let Some((_, bytes)) = self.segments.last_mut() {
+            bytes.push(byte);
+        }
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn evaluate(&self, text: &str) -> Result<Option<i64>, String> {
+        let mut parser = Parser::new(text, self);
+        let result = parser.expression()?;
+        parser.end()?;
+        Ok(result)
+    }
+
+    /// An expression that has to be known in the first pass because it changes addresses
+    fn known(&self, text: &str, directive: &str) -> Result<i64, String> {
+        self.evaluate(text)?
+            .ok_or_else(|| format!("{} needs labels defined before it", directive))
+    }
+}
+
+/// The opcode for `name` in `mode`. Where the tables have several, e.g. the undocumented
+/// NOPs, the first one wins, except for the documented NOP. The disassembler only prints
+/// the opcodes this returns, the others wouldn't assemble back to the same byte.
+pub(crate) fn opcode(variant: CpuVariant, name: &str, mode: AddressingType) -> Option<u8> {
+    if name == "NOP" && mode == AddressingType::NONE {
+        return Some(0xea);
+    }
+    let names = variant.opcode_names();
+    let modes = variant.addressing_types();
+    (0..=0xffu8).find(|&opcode| names[opcode as usize] == name && modes[opcode as usize] == mode)
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            (';', None) => return &line[..i],
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {},
+        }
+    }
+    line
+}
+
+fn parse_operand<'a>(name: &str, text: &'a str) -> Operand<'a> {
+    let upper = text.to_ascii_uppercase();
+    // Strip `suffix` and the spaces before it
+    let without = |suffix: &str| -> Option<&'a str> {
+        let trimmed = upper.trim_end();
+        if trimmed.ends_with(suffix) {
+            let rest = text[..trimmed.len() - suffix.len()].trim_end();
+            Some(rest.strip_suffix(',').unwrap_or(rest).trim_end())
+        } else {
+            None
+        }
+    };
+    let compact: String = upper.chars().filter(|c| ! c.is_whitespace()).collect();
+    if text.is_empty() {
+        Operand::Implied
+    } else if upper == "A" {
+        Operand::Accumulator
+    } else if let Some(value) = text.strip_prefix('#') {
+        Operand::Immediate(value)
+    } else if name.starts_with("BBR") || name.starts_with("BBS") {
+        let mut parts = text.splitn(2, ',');
+        Operand::BitBranch(parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+    } else if text.starts_with('(') && compact.ends_with(",X)") {
+        let inner = &text[1..text.rfind(',').unwrap_or(1)];
+        Operand::IndirectX(inner)
+    } else if text.starts_with('(') && compact.ends_with("),Y") {
+        let inner = &text[1..text.rfind(')').unwrap_or(1)];
+        Operand::IndirectY(inner)
+    } else if text.starts_with('(') && text.ends_with(')') && balanced(&text[1..text.len() - 1]) {
+        Operand::Indirect(&text[1..text.len() - 1])
+    } else if compact.ends_with(",X") {
+        Operand::IndexedX(without("X").unwrap_or(text))
+    } else if compact.ends_with(",Y") {
+        Operand::IndexedY(without("Y").unwrap_or(text))
+    } else {
+        Operand::Direct(text)
+    }
+}
+
+/// False for `1) * (2`, the inside of `(1) * (2)`, which is an expression and not an
+/// indirect operand
+fn balanced(text: &str) -> bool {
+    let mut depth = 0;
+    for c in text.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return false,
+            ')' => depth -= 1,
+            _ => {},
+        }
+    }
+    depth == 0
+}
+
+fn to_byte(value: Option<i64>) -> Result<u8, String> {
+    match value.unwrap_or(0) {
+        v @ -128..=255 => Ok(v as u8),
+        v => Err(format!("{} doesn't fit in a byte", v)),
+    }
+}
+
+fn to_zero_page(value: i64) -> Result<u8, String> {
+    match value {
+        0..=255 => Ok(value as u8),
+        _ => Err(format!("${:X} isn't in the zero page", value)),
+    }
+}
+
+fn to_word(value: Option<i64>) -> Result<u16, String> {
+    match value.unwrap_or(0) {
+        v @ -32768..=65535 => Ok(v as u16),
+        v => Err(format!("{} doesn't fit in a word", v)),
+    }
+}
+
+/// Recursive descent over an expression, lowest precedence first
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+    assembler: &'a Assembler,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str, assembler: &'a Assembler) -> Parser<'a> {
+        Parser { text, position: 0, assembler }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        if self.rest().is_empty() {
+            Ok(())
+        } else {
+            Err(format!("unexpected {}", self.rest()))
+        }
+    }
+
+    /// A string in double quotes, for `.byte`
+    fn string(&mut self) -> Result<Option<&'a str>, String> {
+        if ! self.eat("\"") {
+            return Ok(None);
+        }
+        let rest = self.rest();
+        let end = rest.find('"').ok_or_else(|| "unterminated string".to_string())?;
+        self.position += end + 1;
+        Ok(Some(&rest[..end]))
+    }
+
+    fn expression(&mut self) -> Result<Option<i64>, String> {
+        self.binary(0)
+    }
+
+    /// The operators of each precedence level, from the loosest
+    const LEVELS: [&'static [&'static str]; 6] =
+        [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+
+    fn binary(&mut self, level: usize) -> Result<Option<i64>, String> {
+        if level == Self::LEVELS.len() {
+            return self.unary();
+        }
+        let mut result = self.binary(level + 1)?;
+        'operators: loop {
+            for operator in Self::LEVELS[level] {
+                if self.eat(operator) {
+                    let right = self.binary(level + 1)?;
+                    result = match (result, right) {
+                        (Some(_), Some(0)) if *operator == "/" =>
+                            return Err("division by zero".to_string()),
+                        (Some(l), Some(r)) => Some(match *operator {
+                            "|" => Some(l | r),
+                            "^" => Some(l ^ r),
+                            "&" => Some(l & r),
+                            "<<" => Some(l << (r & 63)),
+                            ">>" => Some(l >> (r & 63)),
+                            "+" => l.checked_add(r),
+                            "-" => l.checked_sub(r),
+                            "*" => l.checked_mul(r),
+                            _ => l.checked_div(r),
+                        }.ok_or_else(|| "overflow".to_string())?),
+                        _ => None,
+                    };
+                    continue 'operators;
+                }
+            }
+            return Ok(result);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Option<i64>, String> {
+        if self.eat("-") {
+            self.unary()?.map(|v| v.checked_neg().ok_or_else(|| "overflow".to_string()))
+                .transpose()
+        } else if self.eat("~") {
+            Ok(self.unary()?.map(|v| ! v))
+        } else if self.eat("<") {
+            Ok(self.unary()?.map(|v| v & 0xff))
+        } else if self.eat(">") {
+            Ok(self.unary()?.map(|v| (v >> 8) & 0xff))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Option<i64>, String> {
+        if self.eat("(") {
+            let result = self.expression()?;
+            return if self.eat(")") { Ok(result) } else { Err("missing )".to_string()) };
+        }
+        if self.eat("*") {
+            return Ok(Some(self.assembler.pc as i64));
+        }
+        if self.eat("'") {
+            let c = self.rest().chars().next().ok_or_else(|| "missing character".to_string())?;
+            self.position += c.len_utf8();
+            return if self.eat("'") { Ok(Some(c as i64)) } else { Err("missing '".to_string()) };
+        }
+        let (radix, prefix) = if self.eat("$") || self.eat("0x") {
+            (16, true)
+        } else if self.eat("%") {
+            (2, true)
+        } else {
+            (10, false)
+        };
+        self.skip_spaces();
+        let rest = self.rest();
+        let end = rest.find(|c: char| ! is_label_char(c)).unwrap_or(rest.len());
+        let token = &rest[..end];
+        self.position += end;
+        if token.is_empty() {
+            return Err(format!("expected a value at {}", if rest.is_empty() { "the end" } else { rest }));
+        }
+        if prefix || token.starts_with(|c: char| c.is_ascii_digit()) {
+            return i64::from_str_radix(token, radix).map(Some)
+                .map_err(|_| format!("invalid number {}", token));
+        }
+        match self.assembler.labels.get(token) {
+            Some(value) => Ok(Some(*value as i64)),
+            None if self.assembler.final_pass => Err(format!("undefined label {}", token)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::constants::*;
+    use crate::cpu::Cpu;
+    use crate::memory::Memory;
+    use crate::test::EndListener;
+    use sixty_asm::sixty_asm;
+
+    #[test]
+    fn assembler_encodes_like_the_tables() {
+        let source = "
+            count = 3
+            pointer = $fe
+                    .org $0800
+            start:  ldx #count       ; load the counter
+            loop:   lda table-1,X
+                    sta (pointer),y
+                    sta forward,x
+                    jmp (vector)
+                    asl
+                    inc a
+                    bbr1 $12, loop
+                    bne loop
+                    lda #<start
+                    lda #>start
+                    jmp ($1234,x)
+                    nop
+            table:  .byte 1, 'b', \"cd\"
+            vector: .word start, * + 2
+                    .res 2, $ff
+            forward = $40
+        ";
+        let program = assemble(source, CpuVariant::Cmos65C02).unwrap();
+        assert_eq!(program.segments.len(), 1);
+        let (address, bytes) = &program.segments[0];
+        assert_eq!(*address, 0x800);
+        assert_eq!(*bytes, vec!(
+            LDX_IMM, 0x03,
+            LDA_ABS_X, 0x1b, 0x08,
+            STA_IND_Y, 0xfe,
+            // Used before its definition, so absolute even though it fits in the zero page
+            STA_ABS_X, 0x40, 0x00,
+            JMP_IND, 0x20, 0x08,
+            ASL,
+            INC,
+            BBR1, 0x12, 0xf0,
+            BNE, 0xee,
+            LDA_IMM, 0x00,
+            LDA_IMM, 0x08,
+            JMP_IND_X, 0x34, 0x12,
+            NOP,
+            0x01, b'b', b'c', b'd',
+            0x00, 0x08, 0x22, 0x08,
+            0xff, 0xff));
+        assert_eq!(program.labels["loop"], 0x802);
+        assert_eq!(program.labels["vector"], 0x820);
+        // STX and STY have no absolute indexed form, so a forward label stays in the zero page
+        let program = assemble("stx fwd,y\nsty fwd,x\nfwd = $40", CpuVariant::Nmos6502).unwrap();
+        assert_eq!(program.to_vec(), vec!(STX_ZP_Y, 0x40, STY_ZP_X, 0x40));
+    }
+
+    #[test]
+    fn assembled_program_runs() {
+        let source = "
+                    ldx #10
+                    lda #0
+                    clc
+            loop:   adc values-1,x
+                    dex
+                    bne loop
+                    sta $80
+            end:    jmp end
+            values: .byte 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+        ";
+        let program = assemble(source, CpuVariant::Nmos6502).unwrap();
+        let end = program.labels["end"];
+        let m = Memory::new_with_vec(program.to_vec(), None);
+        let mut cpu = Cpu::new(m, CpuVariant::Nmos6502, Some(Box::new(EndListener { end })));
+        cpu.run(0).unwrap();
+        assert_eq!(cpu.memory.read(0x80), 55);
+    }
+
+    #[test]
+    fn assembler_errors() {
+        let error = |source: &str| assemble(source, CpuVariant::Nmos6502).unwrap_err().to_string();
+        assert_eq!(error("nop\n  jmp nowhere"), "Line 2: undefined label nowhere");
+        assert_eq!(error("bbr0 $10, *"), "Line 1: unknown instruction BBR0");
+        assert_eq!(error("a: nop\na: nop"), "Line 2: a is defined twice");
+        assert_eq!(error("lda ($1234),y"), "Line 1: $1234 isn't in the zero page");
+        assert_eq!(error("ldx ($12,x)"), "Line 1: LDX doesn't support this addressing mode");
+        assert_eq!(error("beq far\n.res 200\nfar: rts"), "Line 1: branch target out of range by 73 bytes");
+        assert_eq!(error("lda #256"), "Line 1: 256 doesn't fit in a byte");
+        assert_eq!(error(".org $ffff\n.word 1"), "Line 2: $10000 is outside of the address space");
+        assert_eq!(error(".org $fffe\nnop\nnop\nnop"), "Line 4: $10000 is outside of the address space");
+        assert_eq!(error("lda #$7fffffffffffffff * 2"), "Line 1: overflow");
+        assert_eq!(error("lda #-$7fffffffffffffff - 2"), "Line 1: overflow");
+        assert_eq!(error("stx far,y\nfar = $1234"), "Line 1: $1234 isn't in the zero page");
+        assert!(matches!(assemble("nop", CpuVariant::W65C816),
+            Err(SixtyError::Unsupported { variant: CpuVariant::W65C816, .. })));
+    }
+
+    #[test]
+    fn inline_assembly() {
+        let program = sixty_asm! {
+                    .org 2
+            start:  lda #0x42       // Rust comments are allowed
+                    sta $10,x
+                    jmp (start)
+        };
+        assert_eq!(program, vec!(0, 0, LDA_IMM, 0x42, STA_ZP_X, 0x10, JMP_IND, 0x02, 0x00));
+        let memory = sixty_asm! {
+            #[memory]
+            #[variant(Nmos6502)]
+                    ldx #'A'
+            end:    jmp end
+        };
+        let mut cpu = Cpu::new(memory, CpuVariant::Nmos6502, Some(Box::new(EndListener { end: 2 })));
+        cpu.run(0).unwrap();
+        assert_eq!(cpu.x, b'A');
+        // Strings keep the source as is, $1E and ; comments included
+        let program = sixty_asm!(r#"
+                    lda #$1E        ; "quoted"
+                    sta $10
+        "#);
+        assert_eq!(program, vec!(LDA_IMM, 0x1e, STA_ZP, 0x10));
+        assert_eq!(sixty_asm!("lda #$1E\n\
+                               sta $10"), program);
+    }
+}
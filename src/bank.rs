@@ -0,0 +1,133 @@
+//! Bank switching for `Memory`. Banks are extra buffers that can be mapped over the first 64K
+//! of the address space one page (256 bytes) at a time. Reads and writes are mapped
+//! separately, so a page can read ROM while its writes go to RAM underneath, like with the
+//! Apple ][ language card. Switching only rewrites the page tables, nothing is copied.
+
+use std::cell::Cell;
+use std::ops::Range;
+
+/// A bank added with `Memory::add_bank()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BankId(pub(crate) usize);
+
+/// Where the accesses to a page go
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mapping {
+    /// The buffer of `Memory`, the default for every page
+    Buffer,
+    /// The pages of `bank` starting at `page`, the page of the bank that the first page of the
+    /// mapped range sees
+    Bank { bank: BankId, page: usize },
+    /// Nothing answers: reads return 0 and writes are dropped, e.g. writes to a write
+    /// protected RAM bank
+    Nothing,
+}
+
+/// Hardware that switches banks when it's accessed. Like a `Device`, it's added to a range of
+/// addresses, with `Memory::add_switch()`, and receives the accesses to that range. It's also
+/// given the `Banks` of its `Memory` to remap pages.
+pub trait BankSwitch {
+    /// Switches that react to reads keep their state in a `Cell`, see `Device::read()`
+    fn read(&self, address: usize, banks: &Banks) -> u8;
+
+    /// `Bus::peek()` of the switch, which doesn't switch anything
+    fn peek(&self, _address: usize, _banks: &Banks) -> u8 {
+        0
+    }
+
+    fn write(&mut self, address: usize, value: u8, banks: &Banks);
+}
+
+pub const PAGE_SIZE: usize = 0x100;
+const PAGE_COUNT: usize = 0x100;
+
+/// The banks of a `Memory` and the page tables that map them
+#[derive(Default)]
+pub struct Banks {
+    data: Vec<Vec<u8>>,
+    /// Empty until the first bank is added, so that memories without banks skip the lookup
+    reads: Vec<Cell<Mapping>>,
+    writes: Vec<Cell<Mapping>>,
+}
+
+impl Banks {
+    pub(crate) fn add(&mut self, data: Vec<u8>) -> BankId {
+        if self.reads.is_empty() {
+            self.reads = (0..PAGE_COUNT).map(|_| Cell::new(Mapping::Buffer)).collect();
+            self.writes = (0..PAGE_COUNT).map(|_| Cell::new(Mapping::Buffer)).collect();
+        }
+        self.data.push(data);
+        BankId(self.data.len() - 1)
+    }
+
+    pub fn bank(&self, bank: BankId) -> &[u8] {
+        &self.data[bank.0]
+    }
+
+    pub fn bank_mut(&mut self, bank: BankId) -> &mut [u8] {
+        &mut self.data[bank.0]
+    }
+
+    /// Map the reads of `pages`, e.g. `0xd0..0xe0` for $D000-$DFFF
+    ///
+    /// # Panics
+    ///
+    /// If `pages` goes past the first 64K, or the mapping past the end of its bank. Both
+    /// are mistakes in the `BankSwitch`, which can't report an error from an access.
+    pub fn map_read(&self, pages: Range<usize>, mapping: Mapping) {
+        self.map(&self.reads, pages, mapping);
+    }
+
+    /// Map the writes of `pages`, like `map_read()`
+    ///
+    /// # Panics
+    ///
+    /// Like `map_read()`
+    pub fn map_write(&self, pages: Range<usize>, mapping: Mapping) {
+        self.map(&self.writes, pages, mapping);
+    }
+
+    pub fn read_mapping(&self, page: usize) -> Mapping {
+        self.reads.get(page).map_or(Mapping::Buffer, Cell::get)
+    }
+
+    pub fn write_mapping(&self, page: usize) -> Mapping {
+        self.writes.get(page).map_or(Mapping::Buffer, Cell::get)
+    }
+
+    fn map(&self, table: &[Cell<Mapping>], pages: Range<usize>, mapping: Mapping) {
+        assert!(pages.end <= PAGE_COUNT, "Only the first 64K can be bank switched");
+        if let Mapping::Bank { bank, page } = mapping {
+            assert!((page + pages.len()) * PAGE_SIZE <= self.data[bank.0].len(),
+                "The mapping goes past the end of the bank");
+        }
+        for (i, entry) in table[pages].iter().enumerate() {
+            entry.set(match mapping {
+                Mapping::Bank { bank, page } => Mapping::Bank { bank, page: page + i },
+                other => other,
+            });
+        }
+    }
+
+    /// The value at `address`, or `None` if its page reads the buffer
+    pub(crate) fn read(&self, address: usize) -> Option<u8> {
+        match self.read_mapping(address / PAGE_SIZE) {
+            Mapping::Buffer => None,
+            Mapping::Bank { bank, page } =>
+                Some(self.data[bank.0][page * PAGE_SIZE + address % PAGE_SIZE]),
+            Mapping::Nothing => Some(0),
+        }
+    }
+
+    /// Returns false if the page of `address` writes to the buffer
+    pub(crate) fn write(&mut self, address: usize, value: u8) -> bool {
+        match self.write_mapping(address / PAGE_SIZE) {
+            Mapping::Buffer => false,
+            Mapping::Bank { bank, page } => {
+                self.data[bank.0][page * PAGE_SIZE + address % PAGE_SIZE] = value;
+                true
+            },
+            Mapping::Nothing => true,
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! What the CPU is connected to. `Memory` is a flat buffer of RAM, a machine with ROM or
+//! memory mapped I/O implements `Bus` to decode the addresses itself.
+//!
+//! Addresses are already wrapped to the address lines of the CPU variant. The reads of the
+//! 6510 processor port at $00 and $01 never reach the bus, its writes do.
+
+use crate::error::SixtyError;
+use crate::memory::BusCycle;
+
+pub trait Bus {
+    /// Reads take `&self` so that `peek()` can fall back on them. Hardware that reacts to
+    /// reads, like soft switches, keeps its state in a `Cell` or `RefCell`.
+    fn read(&self, address: usize) -> u8;
+
+    /// What `read()` would return, without its side effects, for the debuggers, the
+    /// disassemblers and the traces. Override it when reads change the state of the hardware.
+    fn peek(&self, address: usize) -> u8 {
+        self.read(address)
+    }
+
+    fn write(&mut self, address: usize, value: u8);
+
+    /// The first error since the last call, if any, e.g. `SixtyError::AddressOutOfRange`
+    /// for an address nothing answered to. `Cpu::step()` returns it.
+    fn take_fault(&self) -> Option<SixtyError> {
+        None
+    }
+
+    /// Return true to have `on_bus_cycle()` called. `Cpu::new()` then sets
+    /// `Cpu::cycle_accurate`, so the accesses are exactly the ones of the real chip.
+    fn observes_bus_cycles(&self) -> bool {
+        false
+    }
+
+    /// Called after each access with what it was for and the cycle it happened on. The
+    /// 65C816, which isn't cycle accurate, stamps the accesses of an instruction with
+    /// consecutive cycles from the one the instruction started on, leaving out its internal
+    /// cycles.
+    fn on_bus_cycle(&mut self, _access: BusCycle, _cycle: u64) {}
+}
@@ -1,8 +1,19 @@
 #![allow(unused)]
 #![allow(warnings)]
 
+pub(crate) const NMI_VECTOR_L: usize = 0xfffa;
+pub(crate) const NMI_VECTOR_H: usize = 0xfffb;
+pub(crate) const RESET_VECTOR_L: usize = 0xfffc;
+pub(crate) const RESET_VECTOR_H: usize = 0xfffd;
 pub(crate) const IRQ_VECTOR_L: usize = 0xfffe;
 pub(crate) const IRQ_VECTOR_H: usize = 0xffff;
+pub(crate) const COP_VECTOR_L: usize = 0xfff4;
+
+// The 65C816 has its own vectors in native mode
+pub(crate) const NATIVE_COP_VECTOR_L: usize = 0xffe4;
+pub(crate) const NATIVE_BRK_VECTOR_L: usize = 0xffe6;
+pub(crate) const NATIVE_NMI_VECTOR_L: usize = 0xffea;
+pub(crate) const NATIVE_IRQ_VECTOR_L: usize = 0xffee;
 
 pub const BRK: u8 = 0x00;
 
@@ -14,6 +25,7 @@ pub const ADC_ABS_X: u8 = 0x7d;
 pub const ADC_ABS_Y: u8 = 0x79;
 pub const ADC_IND_X: u8 = 0x61;
 pub const ADC_IND_Y: u8 = 0x71;
+pub const ADC_ZPI: u8 = 0x72;
 
 pub const AND_IMM: u8 = 0x29;
 pub const AND_ZP: u8 = 0x25;
@@ -23,6 +35,7 @@ pub const AND_ABS_X: u8 = 0x3d;
 pub const AND_ABS_Y: u8 = 0x39;
 pub const AND_IND_X: u8 = 0x21;
 pub const AND_IND_Y: u8 = 0x31;
+pub const AND_ZPI: u8 = 0x32;
 
 pub const ASL: u8 = 0xa;
 pub const ASL_ZP: u8 = 0x06;
@@ -32,6 +45,9 @@ pub const ASL_ABS_X: u8 = 0x1e;
 
 pub const BIT_ZP: u8 = 0x24;
 pub const BIT_ABS: u8 = 0x2c;
+pub const BIT_IMM: u8 = 0x89;
+pub const BIT_ZP_X: u8 = 0x34;
+pub const BIT_ABS_X: u8 = 0x3c;
 
 pub const BPL: u8 = 0x10;
 pub const BMI: u8 = 0x30;
@@ -41,6 +57,7 @@ pub const BCC: u8 = 0x90;
 pub const BCS: u8 = 0xb0;
 pub const BNE: u8 = 0xd0;
 pub const BEQ: u8 = 0xf0;
+pub const BRA: u8 = 0x80;
 
 pub const CPX_IMM: u8 = 0xe0;
 pub const CPX_ZP: u8 = 0xe4;
@@ -62,6 +79,7 @@ pub const CMP_ABS_X: u8 = 0xdd;
 pub const CMP_ABS_Y: u8 = 0xd9;
 pub const CMP_IND_X: u8 = 0xc1;
 pub const CMP_IND_Y: u8 = 0xd1;
+pub const CMP_ZPI: u8 = 0xd2;
 
 pub const CPY_IMM: u8 = 0xc0;
 pub const CPY_ZP: u8 = 0xc4;
@@ -71,6 +89,7 @@ pub const DEC_ZP: u8 = 0xc6;
 pub const DEC_ZP_X: u8 = 0xd6;
 pub const DEC_ABS: u8 = 0xce;
 pub const DEC_ABS_X: u8 = 0xde;
+pub const DEC: u8 = 0x3a;
 
 pub const EOR_IMM: u8 = 0x49;
 pub const EOR_ZP: u8 = 0x45;
@@ -80,14 +99,17 @@ pub const EOR_ABS_X: u8 = 0x5d;
 pub const EOR_ABS_Y: u8 = 0x59;
 pub const EOR_IND_X: u8 = 0x41;
 pub const EOR_IND_Y: u8 = 0x51;
+pub const EOR_ZPI: u8 = 0x52;
 
 pub const INC_ZP: u8 = 0xe6;
 pub const INC_ZP_X: u8 = 0xf6;
 pub const INC_ABS: u8 = 0xee;
 pub const INC_ABS_X: u8 = 0xfe;
+pub const INC: u8 = 0x1a;
 
 pub const JMP: u8 = 0x4c;
 pub const JMP_IND: u8 = 0x6c;
+pub const JMP_IND_X: u8 = 0x7c;
 pub const JSR: u8 = 0x20;
 
 pub const LDA_IMM: u8 = 0xa9;
@@ -98,6 +120,7 @@ pub const LDA_ABS_X: u8 = 0xbd;
 pub const LDA_ABS_Y: u8 = 0xb9;
 pub const LDA_IND_X: u8 = 0xa1;
 pub const LDA_IND_Y: u8 = 0xb1;
+pub const LDA_ZPI: u8 = 0xb2;
 
 pub const LDX_IMM: u8 = 0xa2;
 pub const LDX_ZP: u8 = 0xa6;
@@ -125,6 +148,7 @@ pub const ORA_ABS_X: u8 = 0x1d;
 pub const ORA_ABS_Y: u8 = 0x19;
 pub const ORA_IND_X: u8 = 0x01;
 pub const ORA_IND_Y: u8 = 0x11;
+pub const ORA_ZPI: u8 = 0x12;
 
 pub const NOP: u8 = 0xea;
 
@@ -149,6 +173,7 @@ pub const STA_ABS_X: u8 = 0x9d;
 pub const STA_ABS_Y: u8 = 0x99;
 pub const STA_IND_X: u8 = 0x81;
 pub const STA_IND_Y: u8 = 0x91;
+pub const STA_ZPI: u8 = 0x92;
 
 pub const STY_ZP: u8 = 0x84;
 pub const STY_ZP_X: u8 = 0x94;
@@ -158,12 +183,26 @@ pub const STX_ZP: u8 = 0x86;
 pub const STX_ZP_Y: u8 = 0x96;
 pub const STX_ABS: u8 = 0x8e;
 
+pub const STZ_ZP: u8 = 0x64;
+pub const STZ_ZP_X: u8 = 0x74;
+pub const STZ_ABS: u8 = 0x9c;
+pub const STZ_ABS_X: u8 = 0x9e;
+
+pub const TSB_ZP: u8 = 0x04;
+pub const TSB_ABS: u8 = 0x0c;
+pub const TRB_ZP: u8 = 0x14;
+pub const TRB_ABS: u8 = 0x1c;
+
 pub const TXS: u8 = 0x9a;
 pub const TSX: u8 = 0xba;
 pub const PHA: u8 = 0x48;
 pub const PLA: u8 = 0x68;
 pub const PHP: u8 = 0x08;
 pub const PLP: u8 = 0x28;
+pub const PHX: u8 = 0xda;
+pub const PLX: u8 = 0xfa;
+pub const PHY: u8 = 0x5a;
+pub const PLY: u8 = 0x7a;
 
 pub const SBC_IMM: u8 = 0xe9;
 pub const SBC_ZP: u8 = 0xe5;
@@ -173,6 +212,7 @@ pub const SBC_ABS_X: u8 = 0xfd;
 pub const SBC_ABS_Y: u8 = 0xf9;
 pub const SBC_IND_X: u8 = 0xe1;
 pub const SBC_IND_Y: u8 = 0xf1;
+pub const SBC_ZPI: u8 = 0xf2;
 
 pub const TAX: u8 = 0xaa;
 pub const TXA: u8 = 0x8a;
@@ -185,6 +225,198 @@ pub const INY: u8 = 0xc8;
 
 pub const RTS: u8 = 0x60;
 
+pub const RMB0: u8 = 0x07;
+pub const RMB1: u8 = 0x17;
+pub const RMB2: u8 = 0x27;
+pub const RMB3: u8 = 0x37;
+pub const RMB4: u8 = 0x47;
+pub const RMB5: u8 = 0x57;
+pub const RMB6: u8 = 0x67;
+pub const RMB7: u8 = 0x77;
+
+pub const SMB0: u8 = 0x87;
+pub const SMB1: u8 = 0x97;
+pub const SMB2: u8 = 0xa7;
+pub const SMB3: u8 = 0xb7;
+pub const SMB4: u8 = 0xc7;
+pub const SMB5: u8 = 0xd7;
+pub const SMB6: u8 = 0xe7;
+pub const SMB7: u8 = 0xf7;
+
+pub const BBR0: u8 = 0x0f;
+pub const BBR1: u8 = 0x1f;
+pub const BBR2: u8 = 0x2f;
+pub const BBR3: u8 = 0x3f;
+pub const BBR4: u8 = 0x4f;
+pub const BBR5: u8 = 0x5f;
+pub const BBR6: u8 = 0x6f;
+pub const BBR7: u8 = 0x7f;
+
+pub const BBS0: u8 = 0x8f;
+pub const BBS1: u8 = 0x9f;
+pub const BBS2: u8 = 0xaf;
+pub const BBS3: u8 = 0xbf;
+pub const BBS4: u8 = 0xcf;
+pub const BBS5: u8 = 0xdf;
+pub const BBS6: u8 = 0xef;
+pub const BBS7: u8 = 0xff;
+
+// Stable undocumented opcodes of the NMOS 6502
+pub const SLO_IND_X: u8 = 0x03;
+pub const SLO_ZP: u8 = 0x07;
+pub const SLO_ABS: u8 = 0x0f;
+pub const SLO_IND_Y: u8 = 0x13;
+pub const SLO_ZP_X: u8 = 0x17;
+pub const SLO_ABS_Y: u8 = 0x1b;
+pub const SLO_ABS_X: u8 = 0x1f;
+
+pub const RLA_IND_X: u8 = 0x23;
+pub const RLA_ZP: u8 = 0x27;
+pub const RLA_ABS: u8 = 0x2f;
+pub const RLA_IND_Y: u8 = 0x33;
+pub const RLA_ZP_X: u8 = 0x37;
+pub const RLA_ABS_Y: u8 = 0x3b;
+pub const RLA_ABS_X: u8 = 0x3f;
+
+pub const SRE_IND_X: u8 = 0x43;
+pub const SRE_ZP: u8 = 0x47;
+pub const SRE_ABS: u8 = 0x4f;
+pub const SRE_IND_Y: u8 = 0x53;
+pub const SRE_ZP_X: u8 = 0x57;
+pub const SRE_ABS_Y: u8 = 0x5b;
+pub const SRE_ABS_X: u8 = 0x5f;
+
+pub const RRA_IND_X: u8 = 0x63;
+pub const RRA_ZP: u8 = 0x67;
+pub const RRA_ABS: u8 = 0x6f;
+pub const RRA_IND_Y: u8 = 0x73;
+pub const RRA_ZP_X: u8 = 0x77;
+pub const RRA_ABS_Y: u8 = 0x7b;
+pub const RRA_ABS_X: u8 = 0x7f;
+
+pub const SAX_IND_X: u8 = 0x83;
+pub const SAX_ZP: u8 = 0x87;
+pub const SAX_ABS: u8 = 0x8f;
+pub const SAX_ZP_Y: u8 = 0x97;
+
+pub const LAX_IND_X: u8 = 0xa3;
+pub const LAX_ZP: u8 = 0xa7;
+pub const LAX_ABS: u8 = 0xaf;
+pub const LAX_IND_Y: u8 = 0xb3;
+pub const LAX_ZP_Y: u8 = 0xb7;
+pub const LAX_ABS_Y: u8 = 0xbf;
+
+pub const DCP_IND_X: u8 = 0xc3;
+pub const DCP_ZP: u8 = 0xc7;
+pub const DCP_ABS: u8 = 0xcf;
+pub const DCP_IND_Y: u8 = 0xd3;
+pub const DCP_ZP_X: u8 = 0xd7;
+pub const DCP_ABS_Y: u8 = 0xdb;
+pub const DCP_ABS_X: u8 = 0xdf;
+
+pub const ISC_IND_X: u8 = 0xe3;
+pub const ISC_ZP: u8 = 0xe7;
+pub const ISC_ABS: u8 = 0xef;
+pub const ISC_IND_Y: u8 = 0xf3;
+pub const ISC_ZP_X: u8 = 0xf7;
+pub const ISC_ABS_Y: u8 = 0xfb;
+pub const ISC_ABS_X: u8 = 0xff;
+
+pub const ANC_IMM: u8 = 0x0b;
+pub const ANC_IMM_ALT: u8 = 0x2b;
+pub const ALR_IMM: u8 = 0x4b;
+pub const ARR_IMM: u8 = 0x6b;
+pub const SBX_IMM: u8 = 0xcb;
+pub const SBC_IMM_ALT: u8 = 0xeb;
+
+// WDC 65C816
+pub const COP: u8 = 0x02;
+pub const PHD: u8 = 0x0b;
+pub const TCS: u8 = 0x1b;
+pub const JSL: u8 = 0x22;
+pub const PLD: u8 = 0x2b;
+pub const TSC: u8 = 0x3b;
+pub const WDM: u8 = 0x42;
+pub const MVP: u8 = 0x44;
+pub const PHK: u8 = 0x4b;
+pub const MVN: u8 = 0x54;
+pub const TCD: u8 = 0x5b;
+pub const JML: u8 = 0x5c;
+pub const PER: u8 = 0x62;
+pub const RTL: u8 = 0x6b;
+pub const TDC: u8 = 0x7b;
+pub const BRL: u8 = 0x82;
+pub const PHB: u8 = 0x8b;
+pub const TXY: u8 = 0x9b;
+pub const PLB: u8 = 0xab;
+pub const TYX: u8 = 0xbb;
+pub const REP: u8 = 0xc2;
+pub const WAI: u8 = 0xcb;
+pub const PEI: u8 = 0xd4;
+pub const STP: u8 = 0xdb;
+pub const JML_IND: u8 = 0xdc;
+pub const SEP: u8 = 0xe2;
+pub const XBA: u8 = 0xeb;
+pub const PEA: u8 = 0xf4;
+pub const XCE: u8 = 0xfb;
+pub const JSR_IND_X: u8 = 0xfc;
+
+pub const ORA_SR: u8 = 0x03;
+pub const ORA_IND_LONG: u8 = 0x07;
+pub const ORA_LONG: u8 = 0x0f;
+pub const ORA_SR_IND_Y: u8 = 0x13;
+pub const ORA_IND_LONG_Y: u8 = 0x17;
+pub const ORA_LONG_X: u8 = 0x1f;
+
+pub const AND_SR: u8 = 0x23;
+pub const AND_IND_LONG: u8 = 0x27;
+pub const AND_LONG: u8 = 0x2f;
+pub const AND_SR_IND_Y: u8 = 0x33;
+pub const AND_IND_LONG_Y: u8 = 0x37;
+pub const AND_LONG_X: u8 = 0x3f;
+
+pub const EOR_SR: u8 = 0x43;
+pub const EOR_IND_LONG: u8 = 0x47;
+pub const EOR_LONG: u8 = 0x4f;
+pub const EOR_SR_IND_Y: u8 = 0x53;
+pub const EOR_IND_LONG_Y: u8 = 0x57;
+pub const EOR_LONG_X: u8 = 0x5f;
+
+pub const ADC_SR: u8 = 0x63;
+pub const ADC_IND_LONG: u8 = 0x67;
+pub const ADC_LONG: u8 = 0x6f;
+pub const ADC_SR_IND_Y: u8 = 0x73;
+pub const ADC_IND_LONG_Y: u8 = 0x77;
+pub const ADC_LONG_X: u8 = 0x7f;
+
+pub const STA_SR: u8 = 0x83;
+pub const STA_IND_LONG: u8 = 0x87;
+pub const STA_LONG: u8 = 0x8f;
+pub const STA_SR_IND_Y: u8 = 0x93;
+pub const STA_IND_LONG_Y: u8 = 0x97;
+pub const STA_LONG_X: u8 = 0x9f;
+
+pub const LDA_SR: u8 = 0xa3;
+pub const LDA_IND_LONG: u8 = 0xa7;
+pub const LDA_LONG: u8 = 0xaf;
+pub const LDA_SR_IND_Y: u8 = 0xb3;
+pub const LDA_IND_LONG_Y: u8 = 0xb7;
+pub const LDA_LONG_X: u8 = 0xbf;
+
+pub const CMP_SR: u8 = 0xc3;
+pub const CMP_IND_LONG: u8 = 0xc7;
+pub const CMP_LONG: u8 = 0xcf;
+pub const CMP_SR_IND_Y: u8 = 0xd3;
+pub const CMP_IND_LONG_Y: u8 = 0xd7;
+pub const CMP_LONG_X: u8 = 0xdf;
+
+pub const SBC_SR: u8 = 0xe3;
+pub const SBC_IND_LONG: u8 = 0xe7;
+pub const SBC_LONG: u8 = 0xef;
+pub const SBC_SR_IND_Y: u8 = 0xf3;
+pub const SBC_IND_LONG_Y: u8 = 0xf7;
+pub const SBC_LONG_X: u8 = 0xff;
+
 pub const SIZES: [usize; 256] = [
     1, 2, 2, 1, 2, 2, 2, 2, 1, 2, 1, 1, 3, 3, 3, 3,  // 0x00-0x0f
     2, 2, 2, 1, 2, 2, 2, 2, 1, 3, 1, 1, 3, 3, 3, 3,  // 0x10-0x1f
@@ -204,6 +436,46 @@ pub const SIZES: [usize; 256] = [
     2, 2, 2, 1, 2, 2, 2, 2, 1, 3, 1, 1, 3, 3, 3, 3 // 0xf0-0xff
 ];
 
+pub const NMOS_SIZES: [usize; 256] = [
+    1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,  // 0x00-0x0f
+    2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,  // 0x10-0x1f
+    3, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,  // 0x20-0x2f
+    2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,  // 0x30-0x3f
+    1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,  // 0x40-0x4f
+    2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,  // 0x50-0x5f
+    1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,  // 0x60-0x6f
+    2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,  // 0x70-0x7f
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,  // 0x80-0x8f
+    2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,  // 0x90-0x9f
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,  // 0xa0-0xaf
+    2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,  // 0xb0-0xbf
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,  // 0xc0-0xcf
+    2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,  // 0xd0-0xdf
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,  // 0xe0-0xef
+    2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3 // 0xf0-0xff
+];
+
+/// Sizes with an 8 bit accumulator and index registers, immediate operands are one byte
+/// longer when the corresponding register is 16 bit wide
+pub const W65C816_SIZES: [usize; 256] = [
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 3, 3, 3, 4,  // 0x00-0x0f
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 3, 1, 1, 3, 3, 3, 4,  // 0x10-0x1f
+    3, 2, 4, 2, 2, 2, 2, 2, 1, 2, 1, 1, 3, 3, 3, 4,  // 0x20-0x2f
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 3, 1, 1, 3, 3, 3, 4,  // 0x30-0x3f
+    1, 2, 2, 2, 3, 2, 2, 2, 1, 2, 1, 1, 3, 3, 3, 4,  // 0x40-0x4f
+    2, 2, 2, 2, 3, 2, 2, 2, 1, 3, 1, 1, 4, 3, 3, 4,  // 0x50-0x5f
+    1, 2, 3, 2, 2, 2, 2, 2, 1, 2, 1, 1, 3, 3, 3, 4,  // 0x60-0x6f
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 3, 1, 1, 3, 3, 3, 4,  // 0x70-0x7f
+    2, 2, 3, 2, 2, 2, 2, 2, 1, 2, 1, 1, 3, 3, 3, 4,  // 0x80-0x8f
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 3, 1, 1, 3, 3, 3, 4,  // 0x90-0x9f
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 3, 3, 3, 4,  // 0xa0-0xaf
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 3, 1, 1, 3, 3, 3, 4,  // 0xb0-0xbf
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 3, 3, 3, 4,  // 0xc0-0xcf
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 3, 1, 1, 3, 3, 3, 4,  // 0xd0-0xdf
+    2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 3, 3, 3, 4,  // 0xe0-0xef
+    2, 2, 2, 2, 3, 2, 2, 2, 1, 3, 1, 1, 3, 3, 3, 4 // 0xf0-0xff
+];
+
 pub const OPCODE_NAMES: [&str; 256] = [
     "BRK", "ORA", "NOP", "NOP", "TSB", "ORA", "ASL", "RMB0",  // 0x00-0x07
     "PHP", "ORA", "ASL", "NOP", "TSB", "ORA", "ASL", "BBR0",  // 0x08-0x0f
@@ -239,32 +511,162 @@ pub const OPCODE_NAMES: [&str; 256] = [
     "SED", "SBC", "PLX", "NOP", "NOP", "SBC", "INC", "BBS7" // 0xf8-0xff
 ];
 
+/// The opcodes the 65C02 leaves unused, all the NOPs of `OPCODE_NAMES` but $EA. They fetch
+/// the operand bytes of their size, then idle for the rest of their cycles.
+pub const CMOS_RESERVED_NOPS: [u8; 46] = [
+    // Immediate
+    0x02, 0x22, 0x42, 0x62, 0x82, 0xc2, 0xe2,
+    // Zero page, zero page,X and absolute
+    0x44, 0x54, 0xd4, 0xf4, 0x5c, 0xdc, 0xfc,
+    // One byte, one cycle
+    0x03, 0x13, 0x23, 0x33, 0x43, 0x53, 0x63, 0x73, 0x83, 0x93, 0xa3, 0xb3, 0xc3, 0xd3, 0xe3, 0xf3,
+    0x0b, 0x1b, 0x2b, 0x3b, 0x4b, 0x5b, 0x6b, 0x7b, 0x8b, 0x9b, 0xab, 0xbb, 0xcb, 0xdb, 0xeb, 0xfb,
+];
+
+pub const NMOS_OPCODE_NAMES: [&str; 256] = [
+    "BRK", "ORA", "JAM", "SLO", "NOP", "ORA", "ASL", "SLO",  // 0x00-0x07
+    "PHP", "ORA", "ASL", "ANC", "NOP", "ORA", "ASL", "SLO",  // 0x08-0x0f
+    "BPL", "ORA", "JAM", "SLO", "NOP", "ORA", "ASL", "SLO",  // 0x10-0x17
+    "CLC", "ORA", "NOP", "SLO", "NOP", "ORA", "ASL", "SLO",  // 0x18-0x1f
+    "JSR", "AND", "JAM", "RLA", "BIT", "AND", "ROL", "RLA",  // 0x20-0x27
+    "PLP", "AND", "ROL", "ANC", "BIT", "AND", "ROL", "RLA",  // 0x28-0x2f
+    "BMI", "AND", "JAM", "RLA", "NOP", "AND", "ROL", "RLA",  // 0x30-0x37
+    "SEC", "AND", "NOP", "RLA", "NOP", "AND", "ROL", "RLA",  // 0x38-0x3f
+    "RTI", "EOR", "JAM", "SRE", "NOP", "EOR", "LSR", "SRE",  // 0x40-0x47
+    "PHA", "EOR", "LSR", "ALR", "JMP", "EOR", "LSR", "SRE",  // 0x48-0x4f
+    "BVC", "EOR", "JAM", "SRE", "NOP", "EOR", "LSR", "SRE",  // 0x50-0x57
+    "CLI", "EOR", "NOP", "SRE", "NOP", "EOR", "LSR", "SRE",  // 0x58-0x5f
+    "RTS", "ADC", "JAM", "RRA", "NOP", "ADC", "ROR", "RRA",  // 0x60-0x67
+    "PLA", "ADC", "ROR", "ARR", "JMP", "ADC", "ROR", "RRA",  // 0x68-0x6f
+    "BVS", "ADC", "JAM", "RRA", "NOP", "ADC", "ROR", "RRA",  // 0x70-0x77
+    "SEI", "ADC", "NOP", "RRA", "NOP", "ADC", "ROR", "RRA",  // 0x78-0x7f
+    "NOP", "STA", "NOP", "SAX", "STY", "STA", "STX", "SAX",  // 0x80-0x87
+    "DEY", "NOP", "TXA", "ANE", "STY", "STA", "STX", "SAX",  // 0x88-0x8f
+    "BCC", "STA", "JAM", "SHA", "STY", "STA", "STX", "SAX",  // 0x90-0x97
+    "TYA", "STA", "TXS", "TAS", "SHY", "STA", "SHX", "SHA",  // 0x98-0x9f
+    "LDY", "LDA", "LDX", "LAX", "LDY", "LDA", "LDX", "LAX",  // 0xa0-0xa7
+    "TAY", "LDA", "TAX", "LXA", "LDY", "LDA", "LDX", "LAX",  // 0xa8-0xaf
+    "BCS", "LDA", "JAM", "LAX", "LDY", "LDA", "LDX", "LAX",  // 0xb0-0xb7
+    "CLV", "LDA", "TSX", "LAS", "LDY", "LDA", "LDX", "LAX",  // 0xb8-0xbf
+    "CPY", "CMP", "NOP", "DCP", "CPY", "CMP", "DEC", "DCP",  // 0xc0-0xc7
+    "INY", "CMP", "DEX", "SBX", "CPY", "CMP", "DEC", "DCP",  // 0xc8-0xcf
+    "BNE", "CMP", "JAM", "DCP", "NOP", "CMP", "DEC", "DCP",  // 0xd0-0xd7
+    "CLD", "CMP", "NOP", "DCP", "NOP", "CMP", "DEC", "DCP",  // 0xd8-0xdf
+    "CPX", "SBC", "NOP", "ISC", "CPX", "SBC", "INC", "ISC",  // 0xe0-0xe7
+    "INX", "SBC", "NOP", "SBC", "CPX", "SBC", "INC", "ISC",  // 0xe8-0xef
+    "BEQ", "SBC", "JAM", "ISC", "NOP", "SBC", "INC", "ISC",  // 0xf0-0xf7
+    "SED", "SBC", "NOP", "ISC", "NOP", "SBC", "INC", "ISC" // 0xf8-0xff
+];
+
+pub const W65C816_OPCODE_NAMES: [&str; 256] = [
+    "BRK", "ORA", "COP", "ORA", "TSB", "ORA", "ASL", "ORA",  // 0x00-0x07
+    "PHP", "ORA", "ASL", "PHD", "TSB", "ORA", "ASL", "ORA",  // 0x08-0x0f
+    "BPL", "ORA", "ORA", "ORA", "TRB", "ORA", "ASL", "ORA",  // 0x10-0x17
+    "CLC", "ORA", "INC", "TCS", "TRB", "ORA", "ASL", "ORA",  // 0x18-0x1f
+    "JSR", "AND", "JSL", "AND", "BIT", "AND", "ROL", "AND",  // 0x20-0x27
+    "PLP", "AND", "ROL", "PLD", "BIT", "AND", "ROL", "AND",  // 0x28-0x2f
+    "BMI", "AND", "AND", "AND", "BIT", "AND", "ROL", "AND",  // 0x30-0x37
+    "SEC", "AND", "DEC", "TSC", "BIT", "AND", "ROL", "AND",  // 0x38-0x3f
+    "RTI", "EOR", "WDM", "EOR", "MVP", "EOR", "LSR", "EOR",  // 0x40-0x47
+    "PHA", "EOR", "LSR", "PHK", "JMP", "EOR", "LSR", "EOR",  // 0x48-0x4f
+    "BVC", "EOR", "EOR", "EOR", "MVN", "EOR", "LSR", "EOR",  // 0x50-0x57
+    "CLI", "EOR", "PHY", "TCD", "JML", "EOR", "LSR", "EOR",  // 0x58-0x5f
+    "RTS", "ADC", "PER", "ADC", "STZ", "ADC", "ROR", "ADC",  // 0x60-0x67
+    "PLA", "ADC", "ROR", "RTL", "JMP", "ADC", "ROR", "ADC",  // 0x68-0x6f
+    "BVS", "ADC", "ADC", "ADC", "STZ", "ADC", "ROR", "ADC",  // 0x70-0x77
+    "SEI", "ADC", "PLY", "TDC", "JMP", "ADC", "ROR", "ADC",  // 0x78-0x7f
+    "BRA", "STA", "BRL", "STA", "STY", "STA", "STX", "STA",  // 0x80-0x87
+    "DEY", "BIT", "TXA", "PHB", "STY", "STA", "STX", "STA",  // 0x88-0x8f
+    "BCC", "STA", "STA", "STA", "STY", "STA", "STX", "STA",  // 0x90-0x97
+    "TYA", "STA", "TXS", "TXY", "STZ", "STA", "STZ", "STA",  // 0x98-0x9f
+    "LDY", "LDA", "LDX", "LDA", "LDY", "LDA", "LDX", "LDA",  // 0xa0-0xa7
+    "TAY", "LDA", "TAX", "PLB", "LDY", "LDA", "LDX", "LDA",  // 0xa8-0xaf
+    "BCS", "LDA", "LDA", "LDA", "LDY", "LDA", "LDX", "LDA",  // 0xb0-0xb7
+    "CLV", "LDA", "TSX", "TYX", "LDY", "LDA", "LDX", "LDA",  // 0xb8-0xbf
+    "CPY", "CMP", "REP", "CMP", "CPY", "CMP", "DEC", "CMP",  // 0xc0-0xc7
+    "INY", "CMP", "DEX", "WAI", "CPY", "CMP", "DEC", "CMP",  // 0xc8-0xcf
+    "BNE", "CMP", "CMP", "CMP", "PEI", "CMP", "DEC", "CMP",  // 0xd0-0xd7
+    "CLD", "CMP", "PHX", "STP", "JML", "CMP", "DEC", "CMP",  // 0xd8-0xdf
+    "CPX", "SBC", "SEP", "SBC", "CPX", "SBC", "INC", "SBC",  // 0xe0-0xe7
+    "INX", "SBC", "NOP", "XBA", "CPX", "SBC", "INC", "SBC",  // 0xe8-0xef
+    "BEQ", "SBC", "SBC", "SBC", "PEA", "SBC", "INC", "SBC",  // 0xf0-0xf7
+    "SED", "SBC", "PLX", "XCE", "JSR", "SBC", "INC", "SBC" // 0xf8-0xff
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AddressingType {
     IMMEDIATE, ZP, ZP_X, ZP_Y, ABSOLUTE, ABSOLUTE_X, ABSOLUTE_Y, INDIRECT_X, INDIRECT_Y, REGISTER_A,
-    INDIRECT, RELATIVE, ZPI, AIX, NONE
+    INDIRECT, RELATIVE, ZPI, AIX, NONE,
+    // 65C816 only
+    ABSOLUTE_LONG, ABSOLUTE_LONG_X, INDIRECT_LONG, INDIRECT_LONG_Y, ABSOLUTE_INDIRECT_LONG,
+    STACK_RELATIVE, STACK_RELATIVE_Y, RELATIVE_LONG, BLOCK_MOVE
 }
 
 fn h(v: u8) -> String { format!("{:02X}", v) }
 
-fn hh(v: u16) -> String { format!("{:X}", v) }
+fn hh(v: u16) -> String { format!("{:04X}", v) }
 
 impl AddressingType {
-    pub fn to_string(&self, pc: usize, byte: u8, word: u16) -> String {
+    /// The operand of the instruction at `pc`, whose operand bytes are `operand`. BBR and
+    /// BBS have two, the zero page address and the branch offset, and so have MVN and MVP,
+    /// the destination and source banks, which are printed source first.
+    pub fn to_string(&self, pc: usize, operand: &[u8]) -> String {
+        self.to_string_with(pc, operand, &Symbols::new())
+    }
+
+    /// Like `to_string()`, with the addresses that have a name in `symbols` replaced by it
+    pub fn to_string_with(&self, pc: usize, operand: &[u8], symbols: &Symbols) -> String {
+        let byte = operand.first().copied().unwrap_or(0);
+        let word = operand.iter().take(2).rev().fold(0, |result, b| result << 8 | *b as u16);
+        let long = operand.iter().rev().fold(0, |result, b| result << 8 | *b as usize);
+        let name = |address: usize, digits: usize| match symbols.name(address) {
+            Some(name) => name.to_string(),
+            None => format!("${:0width$X}", address, width = digits),
+        };
+        let zp = name(byte as usize, 2);
+        // Like in the disassembler, an absolute operand in the zero page keeps its `a:`, so
+        // that it doesn't read like the zero page instruction when a symbol names it
+        let absolute = name(word as usize, 4);
+        let absolute = if word < 0x100 && matches!(self, AddressingType::ABSOLUTE
+                | AddressingType::ABSOLUTE_X | AddressingType::ABSOLUTE_Y) {
+            format!("a:{}", absolute)
+        } else {
+            absolute
+        };
+        let branch = |size: usize, offset: u16| {
+            name((pc as u16).wrapping_add(size as u16).wrapping_add(offset) as usize, 4)
+        };
         match self {
+            AddressingType::IMMEDIATE if operand.len() == 2 => format!("#${}", hh(word)),
             AddressingType::IMMEDIATE => format!("#${}", h(byte)),
-            AddressingType::ZP => format!("${}", h(byte)),
-            AddressingType::ZP_X => format!("${},X", h(byte)),
-            AddressingType::ZP_Y => format!("${},Y", h(byte)),
-            AddressingType::ABSOLUTE => format!("${}", hh(word)),
-            AddressingType::ABSOLUTE_X => format!("${},X", hh(word)),
-            AddressingType::ABSOLUTE_Y => format!("${},Y", hh(word)),
-            AddressingType::INDIRECT_X => format!("(${},X)", hh(word)),
-            AddressingType::INDIRECT_Y => format!("(${}),Y", hh(word)),
-            AddressingType::INDIRECT => format!("(${})", hh(word)),
-            AddressingType::RELATIVE => {
-                format!("${}", hh(2 + pc as u16 + byte as u16 - (if byte >= 0x7f {0x100} else {0})))
+            AddressingType::ZP => zp,
+            AddressingType::ZP_X => format!("{},X", zp),
+            AddressingType::ZP_Y => format!("{},Y", zp),
+            AddressingType::ABSOLUTE => absolute,
+            AddressingType::ABSOLUTE_X => format!("{},X", absolute),
+            AddressingType::ABSOLUTE_Y => format!("{},Y", absolute),
+            AddressingType::INDIRECT_X => format!("({},X)", zp),
+            AddressingType::INDIRECT_Y => format!("({}),Y", zp),
+            AddressingType::ZPI => format!("({})", zp),
+            AddressingType::INDIRECT => format!("({})", absolute),
+            AddressingType::AIX => format!("({},X)", absolute),
+            AddressingType::REGISTER_A => "A".to_string(),
+            AddressingType::NONE => "".to_string(),
+            AddressingType::RELATIVE if operand.len() == 2 => {
+                format!("{},{}", zp, branch(3, operand[1] as i8 as u16))
+            },
+            AddressingType::RELATIVE => branch(2, byte as i8 as u16),
+            AddressingType::ABSOLUTE_LONG => name(long, 6),
+            AddressingType::ABSOLUTE_LONG_X => format!("{},X", name(long, 6)),
+            AddressingType::INDIRECT_LONG => format!("[${}]", h(byte)),
+            AddressingType::INDIRECT_LONG_Y => format!("[${}],Y", h(byte)),
+            AddressingType::ABSOLUTE_INDIRECT_LONG => format!("[{}]", absolute),
+            AddressingType::STACK_RELATIVE => format!("${},S", h(byte)),
+            AddressingType::STACK_RELATIVE_Y => format!("(${},S),Y", h(byte)),
+            AddressingType::RELATIVE_LONG => branch(3, word),
+            AddressingType::BLOCK_MOVE => {
+                format!("${},${}", h(operand.get(1).copied().unwrap_or(0)), h(byte))
             },
-            _ => "".to_string()
         }
     }
 
@@ -274,25 +676,30 @@ impl AddressingType {
         memory.word(w)
     }
 
-    pub fn address(&self, pc: usize, cpu: &Cpu) -> usize {
-        let memory = &cpu.memory;
+    pub fn address<B: Bus>(&self, pc: usize, cpu: &Cpu<B>) -> usize {
         fn zp(a: u8, b: u8) -> u8 {
             ((a as u16 + b as u16) as u8)
         }
         match self {
-            ZP => memory.get(pc + 1) as usize,
-            ZP_X => zp(memory.get(pc + 1), cpu.x) as usize,
-            ZP_Y => zp(memory.get(pc + 1), cpu.y) as usize,
-            ABSOLUTE => memory.word(pc + 1) as usize,
-            ABSOLUTE_X => (memory.word(pc + 1) + cpu.x as u16) as usize,
-            ABSOLUTE_Y => (memory.word(pc + 1) + cpu.y as u16) as usize,
-            INDIRECT => memory.word(pc + 1) as usize,
+            ZP => cpu.peek(pc + 1) as usize,
+            ZP_X => zp(cpu.peek(pc + 1), cpu.x) as usize,
+            ZP_Y => zp(cpu.peek(pc + 1), cpu.y) as usize,
+            ABSOLUTE => cpu.word(pc + 1) as usize,
+            ABSOLUTE_X => cpu.word(pc + 1).wrapping_add(cpu.x as u16) as usize,
+            ABSOLUTE_Y => cpu.word(pc + 1).wrapping_add(cpu.y as u16) as usize,
+            INDIRECT => cpu.word(pc + 1) as usize,
             INDIRECT_X => {
-                let address = zp(memory.get(pc + 1), cpu.x);
-                memory.word(address as usize) as usize
+                cpu.zp_word(zp(cpu.peek(pc + 1), cpu.x)) as usize
             },
-            INDIRECT_Y => (memory.word(memory.get(pc + 1) as usize) + cpu.y as u16) as usize,
-            IMMEDIATE | RELATIVE | ZPI | REGISTER_A | AIX | NONE => 0
+            INDIRECT_Y => cpu.zp_word(cpu.peek(pc + 1)).wrapping_add(cpu.y as u16) as usize,
+            ZPI => cpu.zp_word(cpu.peek(pc + 1)) as usize,
+            // Only used by JMP ($1234,X), returns the address of the pointer like INDIRECT
+            AIX => cpu.word(pc + 1).wrapping_add(cpu.x as u16) as usize,
+            IMMEDIATE | RELATIVE | REGISTER_A | NONE => 0,
+            // The 65C816 modes depend on registers the 6502 doesn't have, see w65c816.rs
+            ABSOLUTE_LONG | ABSOLUTE_LONG_X | INDIRECT_LONG | INDIRECT_LONG_Y
+                | ABSOLUTE_INDIRECT_LONG | STACK_RELATIVE | STACK_RELATIVE_Y | RELATIVE_LONG
+                | BLOCK_MOVE => 0
         }
     }
 }
@@ -306,26 +713,74 @@ pub const TIMINGS: [u8; 256] = [
     2, 5, 5, 1, 5, 4, 6, 5, 2, 4, 2, 1, 6, 4, 7, 5,  // 0x10-0x1f
     6, 6, 2, 1, 3, 3, 5, 5, 4, 2, 2, 1, 4, 4, 6, 5,  // 0x20-0x2f
     2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 2, 1, 4, 4, 7, 5,  // 0x30-0x3f
-    6, 6, 2, 1, 2, 3, 5, 3, 3, 2, 2, 1, 3, 4, 6, 5,  // 0x40-0x4f
+    6, 6, 2, 1, 3, 3, 5, 5, 3, 2, 2, 1, 3, 4, 6, 5,  // 0x40-0x4f
     2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 3, 1, 8, 4, 7, 5,  // 0x50-0x5f
-    6, 6, 2, 1, 3, 3, 5, 5, 4, 2, 2, 1, 5, 4, 6, 5,  // 0x60-0x6f
-    2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 4, 3, 6, 4, 7, 5,  // 0x70-0x7f
+    6, 6, 2, 1, 3, 3, 5, 5, 4, 2, 2, 1, 6, 4, 6, 5,  // 0x60-0x6f
+    2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 4, 1, 6, 4, 7, 5,  // 0x70-0x7f
     3, 6, 2, 1, 3, 3, 3, 5, 2, 2, 2, 1, 4, 4, 4, 5,  // 0x80-0x8f
     2, 6, 5, 1, 4, 4, 4, 5, 2, 5, 2, 1, 4, 5, 5, 5,  // 0x90-0x9f
     2, 6, 2, 1, 3, 3, 3, 5, 2, 2, 2, 1, 4, 4, 4, 5,  // 0xa0-0xaf
     2, 5, 5, 1, 4, 4, 4, 5, 2, 4, 2, 1, 4, 4, 4, 5,  // 0xb0-0xbf
-    2, 6, 2, 1, 3, 3, 5, 5, 2, 2, 2, 3, 4, 4, 6, 5,  // 0xc0-0xcf
-    2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 3, 3, 4, 4, 7, 5,  // 0xd0-0xdf
+    2, 6, 2, 1, 3, 3, 5, 5, 2, 2, 2, 1, 4, 4, 6, 5,  // 0xc0-0xcf
+    2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 3, 1, 4, 4, 7, 5,  // 0xd0-0xdf
     2, 6, 2, 1, 3, 3, 5, 5, 2, 2, 2, 1, 4, 4, 6, 5,  // 0xe0-0xef
     2, 5, 5, 1, 4, 4, 6, 5, 2, 4, 4, 1, 4, 4, 7, 5 // 0xf0-0xff
 ];
 
+/**
+ * Number of clock cycles required for each instruction when
+ * in NMOS mode
+ */
+pub const NMOS_TIMINGS: [u8; 256] = [
+    7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,  // 0x00-0x0f
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,  // 0x10-0x1f
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,  // 0x20-0x2f
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,  // 0x30-0x3f
+    6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,  // 0x40-0x4f
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,  // 0x50-0x5f
+    6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,  // 0x60-0x6f
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,  // 0x70-0x7f
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,  // 0x80-0x8f
+    2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,  // 0x90-0x9f
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,  // 0xa0-0xaf
+    2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,  // 0xb0-0xbf
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,  // 0xc0-0xcf
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,  // 0xd0-0xdf
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,  // 0xe0-0xef
+    2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7 // 0xf0-0xff
+];
+
+/**
+ * Number of clock cycles required for each instruction on the 65C816, in emulation mode
+ * with 8 bit registers and the low byte of the direct page register set to zero
+ */
+pub const W65C816_TIMINGS: [u8; 256] = [
+    7, 6, 7, 4, 5, 3, 5, 6, 3, 2, 2, 4, 6, 4, 6, 5,  // 0x00-0x0f
+    2, 5, 5, 7, 5, 4, 6, 6, 2, 4, 2, 2, 6, 4, 7, 5,  // 0x10-0x1f
+    6, 6, 8, 4, 3, 3, 5, 6, 4, 2, 2, 5, 4, 4, 6, 5,  // 0x20-0x2f
+    2, 5, 5, 7, 4, 4, 6, 6, 2, 4, 2, 2, 4, 4, 7, 5,  // 0x30-0x3f
+    6, 6, 2, 4, 7, 3, 5, 6, 3, 2, 2, 3, 3, 4, 6, 5,  // 0x40-0x4f
+    2, 5, 5, 7, 7, 4, 6, 6, 2, 4, 3, 2, 4, 4, 7, 5,  // 0x50-0x5f
+    6, 6, 6, 4, 3, 3, 5, 6, 4, 2, 2, 6, 5, 4, 6, 5,  // 0x60-0x6f
+    2, 5, 5, 7, 4, 4, 6, 6, 2, 4, 4, 2, 6, 4, 7, 5,  // 0x70-0x7f
+    3, 6, 4, 4, 3, 3, 3, 6, 2, 2, 2, 3, 4, 4, 4, 5,  // 0x80-0x8f
+    2, 6, 5, 7, 4, 4, 4, 6, 2, 5, 2, 2, 4, 5, 5, 5,  // 0x90-0x9f
+    2, 6, 2, 4, 3, 3, 3, 6, 2, 2, 2, 4, 4, 4, 4, 5,  // 0xa0-0xaf
+    2, 5, 5, 7, 4, 4, 4, 6, 2, 4, 2, 2, 4, 4, 4, 5,  // 0xb0-0xbf
+    2, 6, 3, 4, 3, 3, 5, 6, 2, 2, 2, 3, 4, 4, 6, 5,  // 0xc0-0xcf
+    2, 5, 5, 7, 6, 4, 6, 6, 2, 4, 3, 3, 6, 4, 7, 5,  // 0xd0-0xdf
+    2, 6, 3, 4, 3, 3, 5, 6, 2, 2, 2, 3, 4, 4, 6, 5,  // 0xe0-0xef
+    2, 5, 5, 7, 5, 4, 6, 6, 2, 4, 4, 2, 8, 4, 7, 5 // 0xf0-0xff
+];
+
 use AddressingType::*;
+use crate::bus::Bus;
 use crate::cpu::Cpu;
 use crate::memory::Memory;
+use crate::symbols::Symbols;
 
 pub const ADDRESSING_TYPES: [AddressingType; 256] = [
-    NONE, INDIRECT_X, NONE, NONE,  // 0x00-0x03
+    NONE, INDIRECT_X, IMMEDIATE, NONE,  // 0x00-0x03
     ZP, ZP, ZP, ZP,  // 0x04-0x07
     NONE, IMMEDIATE, REGISTER_A, NONE,  // 0x08-0x0b
     ABSOLUTE, ABSOLUTE, ABSOLUTE, RELATIVE,  // 0x0c-0x0f
@@ -333,23 +788,23 @@ pub const ADDRESSING_TYPES: [AddressingType; 256] = [
     ZP, ZP_X, ZP_X, ZP,  // 0x14-0x17
     NONE, ABSOLUTE_Y, NONE, NONE,  // 0x18-0x1b
     ABSOLUTE, ABSOLUTE_X, ABSOLUTE_X, RELATIVE,  // 0x1c-0x1f
-    ABSOLUTE, INDIRECT_X, NONE, NONE,  // 0x20-0x23
+    ABSOLUTE, INDIRECT_X, IMMEDIATE, NONE,  // 0x20-0x23
     ZP, ZP, ZP, ZP,  // 0x24-0x27
     NONE, IMMEDIATE, REGISTER_A, NONE,  // 0x28-0x2b
     ABSOLUTE, ABSOLUTE, ABSOLUTE, RELATIVE,  // 0x2c-0x2f
     RELATIVE, INDIRECT_Y, ZPI, NONE,  // 0x30-0x33
     ZP_X, ZP_X, ZP_X, ZP,  // 0x34-0x37
     NONE, ABSOLUTE_Y, NONE, NONE,  // 0x38-0x3b
-    NONE, ABSOLUTE_X, ABSOLUTE_X, RELATIVE,  // 0x3c-0x3f
-    NONE, INDIRECT_X, NONE, NONE,  // 0x40-0x43
-    NONE, ZP, ZP, ZP,  // 0x44-0x47
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X, RELATIVE,  // 0x3c-0x3f
+    NONE, INDIRECT_X, IMMEDIATE, NONE,  // 0x40-0x43
+    ZP, ZP, ZP, ZP,  // 0x44-0x47
     NONE, IMMEDIATE, REGISTER_A, NONE,  // 0x48-0x4b
     ABSOLUTE, ABSOLUTE, ABSOLUTE, RELATIVE,  // 0x4c-0x4f
     RELATIVE, INDIRECT_Y, ZPI, NONE,  // 0x50-0x53
-    NONE, ZP_X, ZP_X, ZP,  // 0x54-0x57
+    ZP_X, ZP_X, ZP_X, ZP,  // 0x54-0x57
     NONE, ABSOLUTE_Y, NONE, NONE,  // 0x58-0x5b
-    NONE, ABSOLUTE_X, ABSOLUTE_X, RELATIVE,  // 0x5c-0x5f
-    NONE, INDIRECT_X, NONE, NONE,  // 0x60-0x63
+    ABSOLUTE, ABSOLUTE_X, ABSOLUTE_X, RELATIVE,  // 0x5c-0x5f
+    NONE, INDIRECT_X, IMMEDIATE, NONE,  // 0x60-0x63
     ZP, ZP, ZP, ZP,  // 0x64-0x67
     NONE, IMMEDIATE, REGISTER_A, NONE,  // 0x68-0x6b
     INDIRECT, ABSOLUTE, ABSOLUTE, RELATIVE,  // 0x6c-0x6f
@@ -357,9 +812,9 @@ pub const ADDRESSING_TYPES: [AddressingType; 256] = [
     ZP_X, ZP_X, ZP_X, ZP,  // 0x74-0x77
     NONE, ABSOLUTE_Y, NONE, NONE,  // 0x78-0x7b
     AIX, ABSOLUTE_X, ABSOLUTE_X, RELATIVE,  // 0x7c-0x7f
-    RELATIVE, INDIRECT_X, NONE, NONE,  // 0x80-0x83
+    RELATIVE, INDIRECT_X, IMMEDIATE, NONE,  // 0x80-0x83
     ZP, ZP, ZP, ZP,  // 0x84-0x87
-    NONE, NONE, NONE, NONE,  // 0x88-0x8b
+    NONE, IMMEDIATE, NONE, NONE,  // 0x88-0x8b
     ABSOLUTE, ABSOLUTE, ABSOLUTE, RELATIVE,  // 0x8c-0x8f
     RELATIVE, INDIRECT_Y, ZPI, NONE,  // 0x90-0x93
     ZP_X, ZP_X, ZP_Y, ZP,  // 0x94-0x97
@@ -373,21 +828,154 @@ pub const ADDRESSING_TYPES: [AddressingType; 256] = [
     ZP_X, ZP_X, ZP_Y, ZP,  // 0xb4-0xb7
     NONE, ABSOLUTE_Y, NONE, NONE,  // 0xb8-0xbb
     ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_Y, RELATIVE,  // 0xbc-0xbf
-    IMMEDIATE, INDIRECT_X, NONE, NONE,  // 0xc0-0xc3
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, NONE,  // 0xc0-0xc3
     ZP, ZP, ZP, ZP,  // 0xc4-0xc7
     NONE, IMMEDIATE, NONE, NONE,  // 0xc8-0xcb
     ABSOLUTE, ABSOLUTE, ABSOLUTE, RELATIVE,  // 0xcc-0xcf
     RELATIVE, INDIRECT_Y, ZPI, NONE,  // 0xd0-0xd3
-    NONE, ZP_X, ZP_X, ZP,  // 0xd4-0xd7
+    ZP_X, ZP_X, ZP_X, ZP,  // 0xd4-0xd7
     NONE, ABSOLUTE_Y, NONE, NONE,  // 0xd8-0xdb
-    NONE, ABSOLUTE_X, ABSOLUTE_X, RELATIVE,  // 0xdc-0xdf
-    IMMEDIATE, INDIRECT_X, NONE, NONE,  // 0xe0-0xe3
+    ABSOLUTE, ABSOLUTE_X, ABSOLUTE_X, RELATIVE,  // 0xdc-0xdf
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, NONE,  // 0xe0-0xe3
     ZP, ZP, ZP, ZP,  // 0xe4-0xe7
     NONE, IMMEDIATE, NONE, NONE,  // 0xe8-0xeb
     ABSOLUTE, ABSOLUTE, ABSOLUTE, RELATIVE,  // 0xec-0xef
     RELATIVE, INDIRECT_Y, ZPI, NONE,  // 0xf0-0xf3
-    NONE, ZP_X, ZP_X, ZP,  // 0xf4-0xf7
+    ZP_X, ZP_X, ZP_X, ZP,  // 0xf4-0xf7
     NONE, ABSOLUTE_Y, NONE, NONE,  // 0xf8-0xfb
-    NONE, ABSOLUTE_X, ABSOLUTE_X, RELATIVE // 0xfc-0xff
+    ABSOLUTE, ABSOLUTE_X, ABSOLUTE_X, RELATIVE // 0xfc-0xff
 ];
 
+pub const NMOS_ADDRESSING_TYPES: [AddressingType; 256] = [
+    NONE, INDIRECT_X, NONE, INDIRECT_X,  // 0x00-0x03
+    ZP, ZP, ZP, ZP,  // 0x04-0x07
+    NONE, IMMEDIATE, REGISTER_A, IMMEDIATE,  // 0x08-0x0b
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE,  // 0x0c-0x0f
+    RELATIVE, INDIRECT_Y, NONE, INDIRECT_Y,  // 0x10-0x13
+    ZP_X, ZP_X, ZP_X, ZP_X,  // 0x14-0x17
+    NONE, ABSOLUTE_Y, NONE, ABSOLUTE_Y,  // 0x18-0x1b
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X,  // 0x1c-0x1f
+    ABSOLUTE, INDIRECT_X, NONE, INDIRECT_X,  // 0x20-0x23
+    ZP, ZP, ZP, ZP,  // 0x24-0x27
+    NONE, IMMEDIATE, REGISTER_A, IMMEDIATE,  // 0x28-0x2b
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE,  // 0x2c-0x2f
+    RELATIVE, INDIRECT_Y, NONE, INDIRECT_Y,  // 0x30-0x33
+    ZP_X, ZP_X, ZP_X, ZP_X,  // 0x34-0x37
+    NONE, ABSOLUTE_Y, NONE, ABSOLUTE_Y,  // 0x38-0x3b
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X,  // 0x3c-0x3f
+    NONE, INDIRECT_X, NONE, INDIRECT_X,  // 0x40-0x43
+    ZP, ZP, ZP, ZP,  // 0x44-0x47
+    NONE, IMMEDIATE, REGISTER_A, IMMEDIATE,  // 0x48-0x4b
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE,  // 0x4c-0x4f
+    RELATIVE, INDIRECT_Y, NONE, INDIRECT_Y,  // 0x50-0x53
+    ZP_X, ZP_X, ZP_X, ZP_X,  // 0x54-0x57
+    NONE, ABSOLUTE_Y, NONE, ABSOLUTE_Y,  // 0x58-0x5b
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X,  // 0x5c-0x5f
+    NONE, INDIRECT_X, NONE, INDIRECT_X,  // 0x60-0x63
+    ZP, ZP, ZP, ZP,  // 0x64-0x67
+    NONE, IMMEDIATE, REGISTER_A, IMMEDIATE,  // 0x68-0x6b
+    INDIRECT, ABSOLUTE, ABSOLUTE, ABSOLUTE,  // 0x6c-0x6f
+    RELATIVE, INDIRECT_Y, NONE, INDIRECT_Y,  // 0x70-0x73
+    ZP_X, ZP_X, ZP_X, ZP_X,  // 0x74-0x77
+    NONE, ABSOLUTE_Y, NONE, ABSOLUTE_Y,  // 0x78-0x7b
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X,  // 0x7c-0x7f
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, INDIRECT_X,  // 0x80-0x83
+    ZP, ZP, ZP, ZP,  // 0x84-0x87
+    NONE, IMMEDIATE, NONE, IMMEDIATE,  // 0x88-0x8b
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE,  // 0x8c-0x8f
+    RELATIVE, INDIRECT_Y, NONE, INDIRECT_Y,  // 0x90-0x93
+    ZP_X, ZP_X, ZP_Y, ZP_Y,  // 0x94-0x97
+    NONE, ABSOLUTE_Y, NONE, ABSOLUTE_Y,  // 0x98-0x9b
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_Y, ABSOLUTE_Y,  // 0x9c-0x9f
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, INDIRECT_X,  // 0xa0-0xa3
+    ZP, ZP, ZP, ZP,  // 0xa4-0xa7
+    NONE, IMMEDIATE, NONE, IMMEDIATE,  // 0xa8-0xab
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE,  // 0xac-0xaf
+    RELATIVE, INDIRECT_Y, NONE, INDIRECT_Y,  // 0xb0-0xb3
+    ZP_X, ZP_X, ZP_Y, ZP_Y,  // 0xb4-0xb7
+    NONE, ABSOLUTE_Y, NONE, ABSOLUTE_Y,  // 0xb8-0xbb
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_Y, ABSOLUTE_Y,  // 0xbc-0xbf
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, INDIRECT_X,  // 0xc0-0xc3
+    ZP, ZP, ZP, ZP,  // 0xc4-0xc7
+    NONE, IMMEDIATE, NONE, IMMEDIATE,  // 0xc8-0xcb
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE,  // 0xcc-0xcf
+    RELATIVE, INDIRECT_Y, NONE, INDIRECT_Y,  // 0xd0-0xd3
+    ZP_X, ZP_X, ZP_X, ZP_X,  // 0xd4-0xd7
+    NONE, ABSOLUTE_Y, NONE, ABSOLUTE_Y,  // 0xd8-0xdb
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X,  // 0xdc-0xdf
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, INDIRECT_X,  // 0xe0-0xe3
+    ZP, ZP, ZP, ZP,  // 0xe4-0xe7
+    NONE, IMMEDIATE, NONE, IMMEDIATE,  // 0xe8-0xeb
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE,  // 0xec-0xef
+    RELATIVE, INDIRECT_Y, NONE, INDIRECT_Y,  // 0xf0-0xf3
+    ZP_X, ZP_X, ZP_X, ZP_X,  // 0xf4-0xf7
+    NONE, ABSOLUTE_Y, NONE, ABSOLUTE_Y,  // 0xf8-0xfb
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X // 0xfc-0xff
+];
+
+pub const W65C816_ADDRESSING_TYPES: [AddressingType; 256] = [
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, STACK_RELATIVE,  // 0x00-0x03
+    ZP, ZP, ZP, INDIRECT_LONG,  // 0x04-0x07
+    NONE, IMMEDIATE, REGISTER_A, NONE,  // 0x08-0x0b
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE_LONG,  // 0x0c-0x0f
+    RELATIVE, INDIRECT_Y, ZPI, STACK_RELATIVE_Y,  // 0x10-0x13
+    ZP, ZP_X, ZP_X, INDIRECT_LONG_Y,  // 0x14-0x17
+    NONE, ABSOLUTE_Y, NONE, NONE,  // 0x18-0x1b
+    ABSOLUTE, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_LONG_X,  // 0x1c-0x1f
+    ABSOLUTE, INDIRECT_X, ABSOLUTE_LONG, STACK_RELATIVE,  // 0x20-0x23
+    ZP, ZP, ZP, INDIRECT_LONG,  // 0x24-0x27
+    NONE, IMMEDIATE, REGISTER_A, NONE,  // 0x28-0x2b
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE_LONG,  // 0x2c-0x2f
+    RELATIVE, INDIRECT_Y, ZPI, STACK_RELATIVE_Y,  // 0x30-0x33
+    ZP_X, ZP_X, ZP_X, INDIRECT_LONG_Y,  // 0x34-0x37
+    NONE, ABSOLUTE_Y, NONE, NONE,  // 0x38-0x3b
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_LONG_X,  // 0x3c-0x3f
+    NONE, INDIRECT_X, IMMEDIATE, STACK_RELATIVE,  // 0x40-0x43
+    BLOCK_MOVE, ZP, ZP, INDIRECT_LONG,  // 0x44-0x47
+    NONE, IMMEDIATE, REGISTER_A, NONE,  // 0x48-0x4b
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE_LONG,  // 0x4c-0x4f
+    RELATIVE, INDIRECT_Y, ZPI, STACK_RELATIVE_Y,  // 0x50-0x53
+    BLOCK_MOVE, ZP_X, ZP_X, INDIRECT_LONG_Y,  // 0x54-0x57
+    NONE, ABSOLUTE_Y, NONE, NONE,  // 0x58-0x5b
+    ABSOLUTE_LONG, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_LONG_X,  // 0x5c-0x5f
+    NONE, INDIRECT_X, RELATIVE_LONG, STACK_RELATIVE,  // 0x60-0x63
+    ZP, ZP, ZP, INDIRECT_LONG,  // 0x64-0x67
+    NONE, IMMEDIATE, REGISTER_A, NONE,  // 0x68-0x6b
+    INDIRECT, ABSOLUTE, ABSOLUTE, ABSOLUTE_LONG,  // 0x6c-0x6f
+    RELATIVE, INDIRECT_Y, ZPI, STACK_RELATIVE_Y,  // 0x70-0x73
+    ZP_X, ZP_X, ZP_X, INDIRECT_LONG_Y,  // 0x74-0x77
+    NONE, ABSOLUTE_Y, NONE, NONE,  // 0x78-0x7b
+    AIX, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_LONG_X,  // 0x7c-0x7f
+    RELATIVE, INDIRECT_X, RELATIVE_LONG, STACK_RELATIVE,  // 0x80-0x83
+    ZP, ZP, ZP, INDIRECT_LONG,  // 0x84-0x87
+    NONE, IMMEDIATE, NONE, NONE,  // 0x88-0x8b
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE_LONG,  // 0x8c-0x8f
+    RELATIVE, INDIRECT_Y, ZPI, STACK_RELATIVE_Y,  // 0x90-0x93
+    ZP_X, ZP_X, ZP_Y, INDIRECT_LONG_Y,  // 0x94-0x97
+    NONE, ABSOLUTE_Y, NONE, NONE,  // 0x98-0x9b
+    ABSOLUTE, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_LONG_X,  // 0x9c-0x9f
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, STACK_RELATIVE,  // 0xa0-0xa3
+    ZP, ZP, ZP, INDIRECT_LONG,  // 0xa4-0xa7
+    NONE, IMMEDIATE, NONE, NONE,  // 0xa8-0xab
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE_LONG,  // 0xac-0xaf
+    RELATIVE, INDIRECT_Y, ZPI, STACK_RELATIVE_Y,  // 0xb0-0xb3
+    ZP_X, ZP_X, ZP_Y, INDIRECT_LONG_Y,  // 0xb4-0xb7
+    NONE, ABSOLUTE_Y, NONE, NONE,  // 0xb8-0xbb
+    ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_Y, ABSOLUTE_LONG_X,  // 0xbc-0xbf
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, STACK_RELATIVE,  // 0xc0-0xc3
+    ZP, ZP, ZP, INDIRECT_LONG,  // 0xc4-0xc7
+    NONE, IMMEDIATE, NONE, NONE,  // 0xc8-0xcb
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE_LONG,  // 0xcc-0xcf
+    RELATIVE, INDIRECT_Y, ZPI, STACK_RELATIVE_Y,  // 0xd0-0xd3
+    ZPI, ZP_X, ZP_X, INDIRECT_LONG_Y,  // 0xd4-0xd7
+    NONE, ABSOLUTE_Y, NONE, NONE,  // 0xd8-0xdb
+    ABSOLUTE_INDIRECT_LONG, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_LONG_X,  // 0xdc-0xdf
+    IMMEDIATE, INDIRECT_X, IMMEDIATE, STACK_RELATIVE,  // 0xe0-0xe3
+    ZP, ZP, ZP, INDIRECT_LONG,  // 0xe4-0xe7
+    NONE, IMMEDIATE, NONE, NONE,  // 0xe8-0xeb
+    ABSOLUTE, ABSOLUTE, ABSOLUTE, ABSOLUTE_LONG,  // 0xec-0xef
+    RELATIVE, INDIRECT_Y, ZPI, STACK_RELATIVE_Y,  // 0xf0-0xf3
+    ABSOLUTE, ZP_X, ZP_X, INDIRECT_LONG_Y,  // 0xf4-0xf7
+    NONE, ABSOLUTE_Y, NONE, NONE,  // 0xf8-0xfb
+    AIX, ABSOLUTE_X, ABSOLUTE_X, ABSOLUTE_LONG_X // 0xfc-0xff
+];
@@ -1,17 +1,47 @@
+//! The CPU. `Cpu::new()` takes the `Bus` to run on, the `CpuVariant` to emulate and an
+//! optional `CpuListener`, which `run()` asks after each instruction whether to stop.
+//! `step()` runs one instruction and returns what it did, `tick()` one clock cycle, and
+//! `run_cycles()` and `run_until()` a budget of cycles. None of them panic on bad input: an
+//! opcode the CPU can't execute or an address past the end of the memory is a `SixtyError`.
+//!
+//! With `cycle_accurate` set, the CPU issues every bus cycle of each instruction in order,
+//! including the dummy read when indexing crosses a page and the double write of the NMOS
+//! read-modify-write instructions, which memory mapped hardware such as the Apple ][ soft
+//! switches reacts to.
+//!
+//! The 65C816 starts in emulation mode and `XCE` switches it to native mode, with 16 bit
+//! registers and 24 bit addresses: give it a `Memory` as large as the banks the program
+//! uses. The high bytes of the registers are in `b`, `xh` and `yh`, `accumulator()`,
+//! `x_register()` and `y_register()` return the whole registers. It isn't cycle accurate,
+//! `tick()` and `cycle_accurate` return `SixtyError::Unsupported` on it.
+//!
+//! `set_debug_info()` gives the CPU the source lines of the program: `step_line()` then
+//! runs one line at a time, and `location()` prints the PC as `PC=0812 (hello.c:5, _main)`
+//! in the traces and in the stop reason of `run()`.
+
 #![allow(unused)]
 #![allow(warnings)]
+#![allow(clippy::absurd_extreme_comparisons)]
 
 use crate::{constants::*};
 use std::fmt;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::borrow::BorrowMut;
-use crate::memory::Memory;
+use crate::bus::Bus;
+use crate::memory::{disassemble, BusCycle, Memory};
+use crate::cycle::CycleState;
+use crate::error::SixtyError;
+use crate::port::{ProcessorPort, DATA_ADDRESS};
+use crate::symbols::Symbols;
+use crate::debug_info::DebugInfo;
+use crate::decoder::{self, Mnemonic};
 
 const DEBUG_ASM: bool = false;
 const DEBUG_PC: usize = 0x20000; // 0x670;
 const DEBUG_CYCLES: u64 = u64::max_value(); // 0x4FC1A00
 
+#[derive(Clone, Copy)]
 pub struct StatusFlags {
     _value: u8
 }
@@ -21,11 +51,17 @@ impl StatusFlags {
         StatusFlags { _value: 0x20 /* reserved to true by default */ }
     }
 
-    fn set_value(&mut self, value: u8) {
-        self._value = value | 1 << 4 | 1 << 5;  // always set the B and reserved flags
+    pub(crate) fn set_value(&mut self, value: u8) {
+        // B is not a real flag, it only exists in the copies of P pushed on the stack
+        self._value = (value & !(1 << 4)) | 1 << 5;  // always set the reserved flag
+    }
+
+    /// 65C816 native mode: bits 4 and 5 are the X and M flags instead of B and reserved
+    pub(crate) fn set_native_value(&mut self, value: u8) {
+        self._value = value;
     }
 
-    fn value(&self) -> u8 { self._value }
+    pub fn value(&self) -> u8 { self._value }
 
     fn get_bit(&self, bit: u8) -> bool {
         self._value & (1 << bit) != 0
@@ -36,23 +72,26 @@ impl StatusFlags {
         else { self._value &= !(1 << bit) }
     }
 
-    fn n(&self) -> bool { self.get_bit(7) }
-    fn set_n(&mut self, f: bool) { self.set_bit(f, 7) }
-    fn v(&self) -> bool { self.get_bit(6) }
-    fn set_v(&mut self, f: bool) { self.set_bit(f, 6) }
-    fn reserved(&self) -> bool { true }  // reserved always true
-    fn b(&self) -> bool { true } // b always true
-    fn set_b(&mut self, f: bool) { self.set_bit(f, 4) }
-    fn d(&self) -> bool { self.get_bit(3) }
-    fn set_d(&mut self, f: bool) { self.set_bit(f, 3) }
-    fn i(&self) -> bool { self.get_bit(2) }
-    fn set_i(&mut self, f: bool) { self.set_bit(f, 2) }
-    fn z(&self) -> bool { self.get_bit(1) }
-    fn set_z(&mut self, f: bool) { self.set_bit(f, 1) }
-    fn c(&self) -> bool { self.get_bit(0) }
-    fn set_c(&mut self, f: bool) { self.set_bit(f, 0) }
-
-    fn set_nz_flags(&mut self, reg: u8) {
+    pub fn n(&self) -> bool { self.get_bit(7) }
+    pub(crate) fn set_n(&mut self, f: bool) { self.set_bit(f, 7) }
+    pub fn v(&self) -> bool { self.get_bit(6) }
+    pub(crate) fn set_v(&mut self, f: bool) { self.set_bit(f, 6) }
+    pub fn reserved(&self) -> bool { true }  // reserved always true
+    pub fn b(&self) -> bool { self.get_bit(4) }
+    pub fn d(&self) -> bool { self.get_bit(3) }
+    pub(crate) fn set_d(&mut self, f: bool) { self.set_bit(f, 3) }
+    pub fn i(&self) -> bool { self.get_bit(2) }
+    pub(crate) fn set_i(&mut self, f: bool) { self.set_bit(f, 2) }
+    pub fn z(&self) -> bool { self.get_bit(1) }
+    pub(crate) fn set_z(&mut self, f: bool) { self.set_bit(f, 1) }
+    pub fn c(&self) -> bool { self.get_bit(0) }
+    pub(crate) fn set_c(&mut self, f: bool) { self.set_bit(f, 0) }
+    /// 65C816 native mode: 8 bit accumulator and memory
+    pub fn m(&self) -> bool { self.get_bit(5) }
+    /// 65C816 native mode: 8 bit index registers
+    pub fn x(&self) -> bool { self.get_bit(4) }
+
+    pub(crate) fn set_nz_flags(&mut self, reg: u8) {
         self.set_z(reg == 0);
         self.set_n(reg & 0x80 != 0);
     }
@@ -78,30 +117,178 @@ impl fmt::Display for StatusFlags {
 
 // type CpuListener = Fn(Cpu) -> bool;
 
-pub trait CpuListener {
+pub trait CpuListener<B: Bus = Memory> {
     /// return Ok() if the execution should continue and Err() if it should stop, in which
     /// case the String will give the reason for the stop.
-    fn on_pc_changed(&mut self, cpu: &Cpu) -> RunStatus;
+    fn on_pc_changed(&mut self, cpu: &Cpu<B>) -> RunStatus;
+}
+
+/// The flavor of 6502 being emulated, which decides which opcodes are available and
+/// how many cycles they take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuVariant {
+    /// The original NMOS 6502, including its stable undocumented opcodes
+    Nmos6502,
+    /// The CMOS 65C02, as found in the enhanced Apple //e and the Apple //c
+    Cmos65C02,
+    /// The WDC 65C816 of the Apple IIgs and the SNES, which starts in 6502 emulation mode
+    W65C816,
+    /// The NMOS core of the Ricoh 2A03 in the NES, which has no decimal mode: the D flag
+    /// can be set but ADC and SBC ignore it
+    Ricoh2A03,
+    /// The NMOS 6507 of the Atari 2600, which only has 13 address lines: every address is
+    /// mirrored every 8K
+    Mos6507,
+    /// The NMOS 6510 of the Commodore 64, whose processor port replaces $00 and $01, see
+    /// `Cpu::port()`
+    Mos6510,
+}
+
+impl CpuVariant {
+    pub fn sizes(&self) -> &'static [usize; 256] {
+        match self {
+            CpuVariant::Nmos6502 | CpuVariant::Ricoh2A03 | CpuVariant::Mos6507
+                | CpuVariant::Mos6510 => &NMOS_SIZES,
+            CpuVariant::Cmos65C02 => &SIZES,
+            CpuVariant::W65C816 => &W65C816_SIZES,
+        }
+    }
+
+    pub fn timings(&self) -> &'static [u8; 256] {
+        match self {
+            CpuVariant::Nmos6502 | CpuVariant::Ricoh2A03 | CpuVariant::Mos6507
+                | CpuVariant::Mos6510 => &NMOS_TIMINGS,
+            CpuVariant::Cmos65C02 => &TIMINGS,
+            CpuVariant::W65C816 => &W65C816_TIMINGS,
+        }
+    }
+
+    pub fn opcode_names(&self) -> &'static [&'static str; 256] {
+        match self {
+            CpuVariant::Nmos6502 | CpuVariant::Ricoh2A03 | CpuVariant::Mos6507
+                | CpuVariant::Mos6510 => &NMOS_OPCODE_NAMES,
+            CpuVariant::Cmos65C02 => &OPCODE_NAMES,
+            CpuVariant::W65C816 => &W65C816_OPCODE_NAMES,
+        }
+    }
+
+    pub fn addressing_types(&self) -> &'static [AddressingType; 256] {
+        match self {
+            CpuVariant::Nmos6502 | CpuVariant::Ricoh2A03 | CpuVariant::Mos6507
+                | CpuVariant::Mos6510 => &NMOS_ADDRESSING_TYPES,
+            CpuVariant::Cmos65C02 => &ADDRESSING_TYPES,
+            CpuVariant::W65C816 => &W65C816_ADDRESSING_TYPES,
+        }
+    }
+
+    /// The opcode names as `Mnemonic`s, which the CPU dispatches on
+    pub fn mnemonics(&self) -> &'static [Mnemonic; 256] {
+        decoder::mnemonics(*self)
+    }
+
+    pub fn is_cmos(&self) -> bool {
+        matches!(self, CpuVariant::Cmos65C02 | CpuVariant::W65C816)
+    }
+
+    /// Whether ADC and SBC honour the D flag
+    pub fn has_decimal_mode(&self) -> bool {
+        *self != CpuVariant::Ricoh2A03
+    }
+
+    /// JAM and the unstable undocumented opcodes of the NMOS 6502, which aren't emulated
+    pub fn is_illegal(&self, opcode: u8) -> bool {
+        use Mnemonic::*;
+        ! self.is_cmos() && matches!(self.mnemonics()[opcode as usize],
+            JAM | ANE | LXA | SHA | SHX | SHY | TAS | LAS)
+    }
+
+    /// The address lines the chip has, addresses outside of them wrap around
+    pub fn address_mask(&self) -> usize {
+        match self {
+            CpuVariant::Mos6507 => 0x1fff,
+            CpuVariant::W65C816 => 0xff_ffff,
+            _ => 0xffff,
+        }
+    }
 }
 
-pub struct Cpu {
-    pub memory: Memory,
+const STACK_ADDRESS: usize = 0x100;
+
+pub struct Cpu<B: Bus = Memory> {
+    pub memory: B,
+    pub variant: CpuVariant,
     pub a: u8,
     pub x: u8,
     pub y: u8,
     pub pc: usize,
     pub p: StatusFlags,
+    /// The offset of the stack in page 1, or the whole stack register in 65C816 native mode
+    pub(crate) stack_pointer: usize,
+
+    // 65C816 only: the high bytes of the accumulator and of the index registers, the direct
+    // page register, the data and program banks, and the emulation flag
+    pub b: u8,
+    pub xh: u8,
+    pub yh: u8,
+    pub d: u16,
+    pub dbr: u8,
+    pub pbr: u8,
+    pub e: bool,
 
     pub cycles: u64,
 
-    pub listener: RefCell<Option<Box<dyn CpuListener>>>
+    /// When set, step() and run() issue every bus cycle of each instruction in order, dummy
+    /// accesses included, see `tick()`. step() returns `SixtyError::Unsupported` when it's
+    /// set on the 65C816. `new()` sets it when the bus observes its cycles, because only
+    /// this mode calls `Bus::on_bus_cycle()`; clearing it trades the calls for speed.
+    pub cycle_accurate: bool,
+
+    pub listener: RefCell<Option<Box<dyn CpuListener<B>>>>,
+
+    /// Names for the addresses in the traces
+    pub symbols: Symbols,
+
+    /// Source lines, scopes and C locals for `location()` and `step_line()`, see
+    /// `set_debug_info()`
+    pub debug_info: Option<DebugInfo>,
+
+    /// The 6510 processor port, which takes the place of $00 and $01
+    port: Option<ProcessorPort>,
+
+    /// Bytes written since the CPU started its current step
+    pub(crate) writes: Vec<(usize, u8)>,
+
+    /// One bit per device currently asserting the IRQ line
+    irq_sources: u32,
+    nmi_line: bool,
+    pub(crate) nmi_pending: bool,
+    /// The I flag before the last instruction when it was CLI, SEI or PLP. They change I
+    /// after the CPU polled the interrupt lines, so the next IRQ check still sees the old
+    /// value.
+    pub(crate) polled_i: Option<bool>,
+
+    // Reset before each instruction, reported in StepResult
+    pub(crate) branch_taken: bool,
+    pub(crate) page_crossed: bool,
+
+    /// The instruction tick() is in the middle of
+    pub(crate) cycle_state: Option<CycleState>,
+
+    /// Cycles used by the last call to run_until() past its budget
+    overshoot: u64,
 }
 
-impl fmt::Display for Cpu {
+impl<B: Bus> fmt::Display for Cpu<B> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let sp = self.memory.format_stack();
-        let registers = std::format!("A={:02X} X={:02X} Y={:02X} S={:02X}",
-                                     self.a, self.x, self.y, self.memory.stack_pointer);
+        let sp = self.format_stack();
+        let registers = if self.variant == CpuVariant::W65C816 {
+            std::format!("C={:04X} X={:04X} Y={:04X} S={:04X} D={:04X} DB={:02X} PB={:02X} E={}",
+                         self.accumulator(), self.x_register(), self.y_register(),
+                         self.stack_register(), self.d, self.dbr, self.pbr, self.e as u8)
+        } else {
+            std::format!("A={:02X} X={:02X} Y={:02X} S={:02X}",
+                         self.a, self.x, self.y, self.stack_pointer)
+        };
         // Desired format:
         // 00000000| 05E0: D0 FE      BNE  $05E0       (2) A=AA X=FF Y=00 S=FD P=03 PC=$5E2 P=$03 {---- --ZC} SP={$FD stack:[$1FF:$55 $1FE:$AA ]}
         write!(f, "{} {} {}", registers, self.p, sp)
@@ -113,440 +300,397 @@ pub enum RunStatus {
     Stop(bool, String) // If bool is true, stopping with no error + reason for stopping
 }
 
-impl Cpu {
-    pub fn new(mut memory: Memory, listener: Option<Box<dyn CpuListener>>) -> Cpu {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+/// The bytes following an opcode, decoded according to its addressing type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    None,
+    Byte(u8),
+    Word(u16),
+    /// BBR and BBS: the zero page address to test and the branch offset
+    ZeroPageRelative(u8, u8),
+    /// 65C816 24 bit addresses
+    Long(u32),
+    /// MVN and MVP: the destination and source banks
+    BlockMove(u8, u8),
+}
+
+/// Everything that happened during one call to `Cpu::step()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StepResult {
+    /// The address of the instruction that was executed
+    pub pc: usize,
+    pub opcode: u8,
+    pub operand: Operand,
+    /// Cycles taken by the instruction, including the interrupt sequence if one was serviced
+    pub cycles: u64,
+    pub branch_taken: bool,
+    /// Indexing or a taken branch crossed a page, whether it cost a cycle or not: indexed
+    /// stores and read-modify-writes always take the extra cycle
+    pub page_crossed: bool,
+    /// Every byte written to memory (stack included), in order, as (address, value)
+    pub writes: Vec<(usize, u8)>,
+    /// The hardware interrupt serviced right before the instruction, if any
+    pub interrupt: Option<Interrupt>,
+    /// Every bus cycle of the instruction in order, only recorded in cycle accurate mode
+    pub bus_cycles: Vec<BusCycle>,
+}
+
+impl<B: Bus> Cpu<B> {
+    pub fn new(memory: B, variant: CpuVariant, listener: Option<Box<dyn CpuListener<B>>>)
+            -> Cpu<B> {
+        let port = if variant == CpuVariant::Mos6510 { Some(ProcessorPort::new()) } else { None };
+        let cycle_accurate = variant != CpuVariant::W65C816 && memory.observes_bus_cycles();
         Cpu {
             memory,
+            stack_pointer: 0xff,
+            port,
+            writes: Vec::new(),
+            variant,
             a: 0,
             x: 0,
             y: 0,
             pc: 0,
             p: StatusFlags::new(),
+            b: 0,
+            xh: 0,
+            yh: 0,
+            d: 0,
+            dbr: 0,
+            pbr: 0,
+            e: true,
             cycles: 0,
-            listener: RefCell::new(listener)
+            cycle_accurate,
+            listener: RefCell::new(listener),
+            symbols: Symbols::new(),
+            debug_info: None,
+            irq_sources: 0,
+            nmi_line: false,
+            nmi_pending: false,
+            polled_i: None,
+            branch_taken: false,
+            page_crossed: false,
+            cycle_state: None,
+            overshoot: 0,
         }
     }
 
-    pub fn run(&mut self, start_pc: usize) -> RunStatus {
-        self.pc = start_pc;
-        let mut result = RunStatus::Continue;
-        loop {
-            let previous_pc = self.pc;
-            let opcode = self.memory.get(self.pc);
-            self.pc += SIZES[opcode as usize];
-            self.cycles = self.cycles + self.next_instruction(previous_pc);
+    /// The processor port, only present on the 6510
+    pub fn port(&self) -> Option<&ProcessorPort> {
+        self.port.as_ref()
+    }
 
-            let stop = if let Some(l) = self.listener.borrow_mut().as_mut() {
-                l.on_pc_changed(self)
-            } else {
-                RunStatus::Continue
-            };
+    pub fn port_mut(&mut self) -> Option<&mut ProcessorPort> {
+        self.port.as_mut()
+    }
 
-            match stop {
-                RunStatus::Stop(success, ref reason) => {
-                    result = RunStatus::Stop(success, reason.to_string());
-                    println!("{}", reason.as_str());
-                    break;
-                },
-                _ => {}
-            }
+    /// Read from the bus, after wrapping the address to the address lines of the CPU
+    pub(crate) fn get(&self, address: usize) -> u8 {
+        let address = address & self.variant.address_mask();
+        match &self.port {
+            Some(port) if address <= DATA_ADDRESS => port.read(address),
+            _ => self.memory.read(address),
+        }
+    }
+
+    pub(crate) fn set(&mut self, address: usize, value: u8) {
+        let address = address & self.variant.address_mask();
+        if let Some(port) = self.port.as_mut().filter(|_| address <= DATA_ADDRESS) {
+            port.write(address, value);
         }
-        return result;
-    }
-
-    pub fn next_instruction(&mut self, pc: usize) -> u64 {
-        let max = 10;
-        let mut i = 0;
-
-        // let mut bm = Box::new(&self.memory);
-        let opcode = self.memory.get(pc);
-        let addressing_type = &ADDRESSING_TYPES[opcode as usize];
-        let mut cycles = TIMINGS[opcode as usize];
-
-        fn run_inst(opcode: u8, cpu: &mut Cpu, pc: usize, address: usize,
-                    ind_y: u8, abs_x: u8, abs_y: u8) -> u8 {
-            cpu.p.set_nz_flags(cpu.a);
-            let memory = & cpu.memory;
-            let result =
-                if opcode == ind_y {
-                    cpu.page_crossed(memory.word(memory.get(pc - 1) as usize), address)
-                } else if opcode == abs_x || opcode == abs_y {
-                    cpu.page_crossed(memory.word(pc - 2), address)
-                } else {
-                    0
-                };
-            result
+        // The 6510 drives the writes to its port onto the bus too, so they reach the RAM
+        // underneath
+        self.memory.write(address, value);
+        self.writes.push((address, value));
+    }
+
+    /// `get()` without the side effects of the read, see `Bus::peek()`
+    pub(crate) fn peek(&self, address: usize) -> u8 {
+        let address = address & self.variant.address_mask();
+        match &self.port {
+            Some(port) if address <= DATA_ADDRESS => port.read(address),
+            _ => self.memory.peek(address),
         }
+    }
 
-        // if pc == 0x4e5 {
-        //     println!("BREAKPOINT");
-        // }
-        match opcode {
-            ADC_IMM => self.adc(self.memory.get(pc + 1)),
-            ADC_ZP| ADC_ZP_X| ADC_ABS| ADC_ABS_X| ADC_ABS_Y| ADC_IND_X| ADC_IND_Y => {
-                let address = addressing_type.address(pc, self);
-                self.adc(self.memory.get(address));
-                cycles += run_inst(opcode, self, pc, address, ADC_IND_Y, ADC_ABS_X, ADC_ABS_Y);
-            },
-            AND_IMM => {
-                self.a = self.a & self.memory.get(pc + 1);
-                self.p.set_nz_flags(self.a);
-            },
-            AND_ZP | AND_ZP_X | AND_ABS | AND_ABS_X | AND_ABS_Y | AND_IND_X | AND_IND_Y => {
-                let address = addressing_type.address(pc, self);
-                let content = self.memory.get(address);
-                self.a &= content;
-                cycles += run_inst(opcode, self, pc, address, AND_IND_Y, AND_ABS_X, AND_ABS_Y);
-            },
-            ASL => self.a = self.asl(self.a),
-            ASL_ZP | ASL_ZP_X | ASL_ABS | ASL_ABS_X => {
-                let address = addressing_type.address(pc, self);
-                let result = self.asl(self.memory.get(address));
-                self.memory.set(address, result);
-            },
-            BIT_ZP | BIT_ABS => {
-                let address = addressing_type.address(pc, self);
-                let content = self.memory.get(address);
-                self.p.set_z(content & self.a == 0);
-                self.p.set_n(content & 0x80 != 0);
-                self.p.set_v(content & 0x40 != 0);
-            },
-            BPL => { cycles += self.branch(self.memory.get(pc + 1), ! self.p.n()) },
-            BMI => { cycles += self.branch(self.memory.get(pc + 1), self.p.n()) },
-            BNE => { cycles += self.branch(self.memory.get(pc + 1), ! self.p.z()) },
-            BEQ => { cycles += self.branch(self.memory.get(pc + 1), self.p.z()) },
-            BCC => { cycles += self.branch(self.memory.get(pc + 1), ! self.p.c()) },
-            BCS => { cycles += self.branch(self.memory.get(pc + 1), self.p.c()) },
-            BVC => { cycles += self.branch(self.memory.get(pc + 1), ! self.p.v()) },
-            BVS => { cycles += self.branch(self.memory.get(pc + 1), self.p.v()) },
-            BRK => self.handle_interrupt(true, IRQ_VECTOR_H, IRQ_VECTOR_L),
-            CMP_IMM => self.cmp(self.a, self.memory.get(pc + 1)),
-            CMP_ZP| CMP_ZP_X| CMP_ABS| CMP_ABS_X| CMP_ABS_Y| CMP_IND_X| CMP_IND_Y => {
-                self.cmp(self.a, self.memory.get(
-                    addressing_type.address(pc, self)));
-            },
-            CPX_IMM => self.cmp(self.x, self.memory.get(pc + 1)),
-            CPX_ZP | CPX_ABS => {
-                self.cmp(self.x, self.memory.get(
-                    addressing_type.address(pc, self)));
-            },
-            CPY_IMM => self.cmp(self.y, self.memory.get(pc + 1)),
-            CPY_ZP | CPY_ABS => {
-                self.cmp(self.y, self.memory.get(
-                    addressing_type.address(pc, self)));
-            },
-            DEC_ZP| DEC_ZP_X| DEC_ABS| DEC_ABS_X => {
-                let address = addressing_type.address(pc, self);
-                let old_value = self.memory.get(address);
-                let new_value = if old_value == 0 { 0xff } else { old_value - 1};
-                self.memory.set(address, new_value);
-                self.p.set_nz_flags(new_value);
-            },
-            EOR_IMM => {
-                self.a = self.a ^ self.memory.get(pc + 1);
-                self.p.set_nz_flags(self.a);
-            },
-            EOR_ZP| EOR_ZP_X| EOR_ABS| EOR_ABS_X| EOR_ABS_Y| EOR_IND_Y| EOR_IND_X => {
-                let address = addressing_type.address(pc, self);
-                self.a = self.a ^ self.memory.get(address);
-                self.p.set_nz_flags(self.a);
-            },
-            CLC => self.p.set_c(false),
-            SEC => self.p.set_c(true),
-            CLI => self.p.set_i(false),
-            SEI => self.p.set_i(true),
-            CLD => self.p.set_d(false),
-            SED => self.p.set_d(true),
-            CLV => self.p.set_v(false),
-            INC_ZP | INC_ZP_X | INC_ABS | INC_ABS_X => {
-                let address = addressing_type.address(pc, self);
-                let content = self.memory.get(address);
-                let word = self.memory.word(pc + 1);
-                let new_value = if content == 0xff { 0 } else { content + 1 };
-                self.memory.set(address, new_value);
-                self.p.set_nz_flags(new_value);
-            },
-            JMP => self.pc = self.memory.word(pc + 1) as usize,
-            JMP_IND => {
-                self.pc = self.memory.word(addressing_type.address(pc, self))
-                    as usize
-            },
-            JSR => {
-                self.memory.push_word(pc as u16 + 2);
-                self.pc = self.memory.word(pc + 1) as usize;
-            },
-            LDX_IMM => {
-                self.x = self.memory.get(pc + 1);
-                self.p.set_nz_flags(self.x);
-            },
-            LDA_IMM => {
-                self.a = self.memory.get(pc + 1);
-                self.p.set_nz_flags(self.a);
-            },
-            LDA_ZP| LDA_ZP_X| LDA_ABS| LDA_ABS_X| LDA_ABS_Y| LDA_IND_X| LDA_IND_Y => {
-                let address = addressing_type.address(pc, self);
-                self.a = self.memory.get(address);
-                self.p.set_nz_flags(self.a);
-            },
-            LDX_IMM => {
-                self.x = self.memory.get(pc + 1);
-                self.p.set_nz_flags(self.x);
-            },
-            LDX_ZP | LDX_ZP_Y | LDX_ABS | LDX_ABS_Y => {
-                let address = addressing_type.address(pc, self);
-                let content = self.memory.get(address);
-                self.x = content;
-                self.p.set_nz_flags(self.x);
-            },
-            LDY_IMM => {
-                self.y = self.memory.get(pc + 1);
-                self.p.set_nz_flags(self.y);
-            },
-            LDY_ZP | LDY_ZP_X | LDY_ABS | LDY_ABS_X => {
-                let address = addressing_type.address(pc, self);
-                let content = self.memory.get(address);
-                self.y = content;
-                self.p.set_nz_flags(self.y);
-            },
-            LSR => self.a = self.lsr(self.a),
-            LSR_ZP| LSR_ZP_X| LSR_ABS| LSR_ABS_X => {
-                let address = addressing_type.address(pc, self);
-                let new_value = self.lsr(self.memory.get(address));
-                self.memory.set(address, new_value);
-            },
-            NOP => {},
-            ORA_IMM => {
-                self.a = self.a | self.memory.get(pc + 1);
-                self.p.set_nz_flags(self.a);
-            },
-            ORA_ZP| ORA_ZP_X| ORA_ABS| ORA_ABS_X| ORA_ABS_Y| ORA_IND_X| ORA_IND_Y => {
-                let address = addressing_type.address(pc, self);
-                self.a = self.a | self.memory.get(address);
-                self.p.set_nz_flags(self.a);
-            },
-            TAX => {
-                self.x = self.a;
-                self.p.set_nz_flags(self.x);
-            },
-            TXA => {
-                self.a = self.x;
-                self.p.set_nz_flags(self.a);
-            },
-            DEX => {
-                if self.x == 0 { self.x = 0xff } else { self.x -= 1; }
-                self.p.set_nz_flags(self.x);
-            },
-            INX => {
-                if self.x == 0xff { self.x = 0 } else { self.x += 1; }
-                self.p.set_nz_flags(self.x);
-            },
-            TAY => {
-                self.y = self.a;
-                self.p.set_nz_flags(self.y);
-            },
-            TYA => {
-                self.a = self.y;
-                self.p.set_nz_flags(self.a);
-            },
-            DEY => {
-                if self.y == 0 { self.y = 0xff; } else { self.y -= 1; }
-                self.p.set_nz_flags(self.y);
-            },
-            INY => {
-                if self.y == 0xff { self.y = 0; } else { self.y += 1; }
-                self.p.set_nz_flags(self.y);
-            },
-            ROL => {
-                self.a = self.rol(self.a);
-            },
-            ROL_ZP | ROL_ZP_X | ROL_ABS | ROL_ABS_X => {
-                let address = addressing_type.address(pc, self);
-                let new_value = self.rol(self.memory.get(address));
-                self.memory.set(address, new_value);
-            },
-            ROR => {
-                self.a = self.ror(self.a);
-            },
-            ROR_ZP | ROR_ZP_X | ROR_ABS | ROR_ABS_X => {
-                let address = addressing_type.address(pc, self);
-                let new_value = self.ror(self.memory.get(address));
-                self.memory.set(address, new_value);
-            },
-            RTI => {
-                self.p.set_value(self.memory.pop_byte());
-                self.pc = self.memory.pop_word();
-            },
-            RTS => {
-                self.pc = self.memory.pop_word() + 1;
-            },
-            SBC_IMM => {
-                self.sbc(self.memory.get(pc + 1));
-            },
-            SBC_ZP |  SBC_ZP_X | SBC_ABS | SBC_ABS_X | SBC_ABS_Y | SBC_IND_X | SBC_IND_Y =>{
-                let address = addressing_type.address(pc, self);
-                self.sbc(self.memory.get(address));
-            },
-            STA_ZP | STA_ZP_X | STA_ABS | STA_ABS_X | STA_ABS_Y | STA_IND_X | STA_IND_Y => {
-                let address = addressing_type.address(pc, self);
-                self.memory.set(address, self.a);
-            },
-            TXS => self.memory.stack_pointer = self.x as usize,
-            TSX => {
-                self.x = self.memory.stack_pointer as u8;
-                self.p.set_nz_flags(self.x);
-            },
-            PHA => self.memory.push_byte(self.a),
-            PLA => {
-                self.a = self.memory.pop_byte();
-                self.p.set_nz_flags(self.a);
-            },
-            PHP => {
-                self.p.set_b(true);
-                // self.p.set_reserved(true);
-                self.memory.push_byte(self.p.value());
-            },
-            PLP => self.p.set_value(self.memory.pop_byte()),
-            STX_ZP | STX_ZP_Y | STX_ABS => {
-                let address = addressing_type.address(pc, self);
-                self.memory.set(address, self.x);
-            },
-            STY_ZP | STY_ZP_X | STY_ABS => {
-                let address = addressing_type.address(pc, self);
-                self.memory.set(address, self.y);
-            },
-            _ => {
-                panic!("Unknown opcode: {}", opcode);
+    pub(crate) fn word(&self, address: usize) -> u16 {
+        self.peek(address) as u16 | ((self.peek(address + 1) as u16) << 8)
+    }
+
+    /// The pointers of the indirect zero page modes wrap inside page zero: ($FF) is read
+    /// from $FF and $00
+    pub(crate) fn zp_word(&self, address: u8) -> u16 {
+        self.peek(address as usize) as u16 | ((self.peek(address.wrapping_add(1) as usize) as u16) << 8)
+    }
+
+    fn format_stack(&self) -> String {
+        let mut result = Vec::new();
+        result.push(std::format!("SP={{${:2X} stack:[", self.stack_pointer));
+        let down = std::cmp::max(self.stack_pointer + 1, 0xf8);
+        let mut i = 0xff;
+        if self.stack_pointer < 0xff {
+            loop {
+                let v = self.peek(STACK_ADDRESS + i);
+                result.push(std::format!("{:02X}={:02X}", i, v));
+                i -= 1;
+                if i < down { break; }
             }
         }
-        if self.pc == DEBUG_PC {
-            println!("BREAKPOINT DEBUG_PC");
+        result.push("]}}".to_string());
+        result.join(" ")
+    }
+
+    /// Assert or release the IRQ line on behalf of the sources in `sources`, a mask with one
+    /// bit per device, like `1 << 3`. The line is level triggered and stays active as long
+    /// as at least one source asserts it.
+    pub fn set_irq(&mut self, sources: u32, asserted: bool) {
+        if asserted {
+            self.irq_sources |= sources;
+        } else {
+            self.irq_sources &= ! sources;
         }
-        if self.cycles == DEBUG_CYCLES {
-            println!("BREAKPOINT CYCLES");
+    }
+
+    pub fn irq_asserted(&self) -> bool {
+        self.irq_sources != 0
+    }
+
+    /// Drive the NMI line. NMI is edge triggered: only the transition from released to
+    /// asserted causes an interrupt, keeping the line asserted doesn't trigger another one.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && ! self.nmi_line {
+            self.nmi_pending = true;
         }
-        let close_to_breakpoint = self.pc > DEBUG_PC - 50 && self.pc < DEBUG_PC;
-        if DEBUG_ASM || close_to_breakpoint || self.cycles > DEBUG_CYCLES {
-            let (s, size) = self.memory.disassemble(pc);
-            println!("{:08X}| {:<30} {}", self.cycles, s, self);
+        self.nmi_line = asserted;
+    }
+
+    /// Pull the RESET line: the CPU restarts at the address in the reset vector. The stack
+    /// pointer is decremented by three without anything being written, like on the real chip.
+    pub fn reset(&mut self) {
+        if self.variant == CpuVariant::W65C816 {
+            self.reset_65c816();
+        }
+        if let Some(port) = self.port.as_mut() {
+            port.reset();
         }
-        // i = i + 1;
-        // if i >= max { break };
-        return cycles as u64;
+        self.p.set_i(true);
+        if self.variant.is_cmos() {
+            self.p.set_d(false);
+        }
+        self.nmi_pending = false;
+        self.polled_i = None;
+        self.cycle_state = None;
+        // Without a sequence in progress the bus cycles never yield
+        let _ = self.reset_cycles();
     }
 
-    fn ror(&mut self, v: u8) -> u8 {
-        let bit0 = v & 1;
-        let result = (v >> 1) | (self.p.c() as u8) << 7;
-        self.p.set_nz_flags(result);
-        self.p.set_c(bit0 != 0);
-        result
+    /// The interrupt to take before the next instruction, if `interrupts` allows it: a
+    /// pending NMI, or an IRQ if the line is asserted and interrupts were enabled when the
+    /// last instruction polled it
+    pub(crate) fn pending_interrupt(&mut self, interrupts: bool) -> Option<Interrupt> {
+        if interrupts && self.nmi_pending {
+            self.nmi_pending = false;
+            Some(Interrupt::Nmi)
+        } else if interrupts && self.irq_asserted() && ! self.polled_i.unwrap_or(self.p.i()) {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
     }
 
-    fn rol(&mut self, v: u8) -> u8 {
-        let result = (v << 1) | self.p.c() as u8;
-        self.p.set_c(v & 0x80 != 0);
-        self.p.set_nz_flags(result);
-        result
+    /// Execute exactly one instruction at `pc`, after servicing a pending interrupt if there
+    /// is one, in which case the instruction is the first one of the handler. Accesses past
+    /// the end of the memory, including the ones done by reset(), are reported here.
+    pub fn step(&mut self) -> Result<StepResult, SixtyError> {
+        let result = if self.variant == CpuVariant::W65C816 && self.cycle_accurate {
+            return Err(SixtyError::Unsupported {
+                variant: self.variant,
+                feature: "Cycle accurate execution",
+            });
+        } else if self.variant == CpuVariant::W65C816 {
+            Ok(self.step_65c816())
+        } else if self.cycle_accurate || self.cycle_state.is_some() {
+            self.step_cycles()
+        } else {
+            self.step_instruction()
+        };
+        match self.memory.take_fault() {
+            Some(error) => Err(error),
+            None => result,
+        }
     }
 
-    fn lsr(&mut self, v: u8) -> u8 {
-        let bit0 = v & 1;
-        self.p.set_c(bit0 != 0);
-        let result = v >> 1;
-        self.p.set_nz_flags(result);
-        result
+    /// The error for an opcode the CPU can't execute
+    pub(crate) fn check_opcode(&self, pc: usize, opcode: u8) -> Result<(), SixtyError> {
+        if self.variant.is_illegal(opcode) {
+            Err(SixtyError::IllegalOpcode { pc, opcode })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn step_instruction(&mut self) -> Result<StepResult, SixtyError> {
+        self.writes.clear();
+        self.branch_taken = false;
+        self.page_crossed = false;
+
+        let start = self.cycles;
+        let interrupt = self.pending_interrupt(true);
+        if let Some(interrupt) = interrupt {
+            // Nothing yields outside of tick()
+            let _ = self.interrupt_cycles(interrupt);
+        }
+
+        let pc = self.pc;
+        let opcode = self.peek(pc);
+        let operand = self.operand(pc, opcode);
+        self.next_instruction(pc)?;
+
+        Ok(StepResult {
+            pc,
+            opcode,
+            operand,
+            cycles: self.cycles - start,
+            branch_taken: self.branch_taken,
+            page_crossed: self.page_crossed,
+            writes: std::mem::take(&mut self.writes),
+            interrupt,
+            bus_cycles: Vec::new(),
+        })
     }
 
-    fn cmp(&mut self, register: u8, v: u8) {
-        // let tmp: i8 = 0;
-        let tmp: i8 = (register as i16 - v as i16) as i8;
-        self.p.set_c(register >= v);
-        self.p.set_z(tmp == 0);
-        self.p.set_n(tmp < 0);
+    /// Run from the current `pc` for `budget` cycles, e.g. one video frame. Returns the
+    /// number of cycles actually executed, see `run_until()`.
+    pub fn run_cycles(&mut self, budget: u64) -> Result<u64, SixtyError> {
+        let start = self.cycles;
+        self.run_until(budget, |_| false)?;
+        Ok(self.cycles - start)
     }
 
-    fn handle_interrupt(&mut self, brk: bool, vector_high: usize, vector_low: usize) {
-        self.p.set_b(brk);
-        self.memory.push_word((self.pc + 1) as u16);
-        self.memory.push_byte(self.p.value());
-        self.p.set_i(true);
-        let memory = &self.memory;
-        let new_pc = (self.memory.get(vector_high) as u16) << 8 | self.memory.get(vector_low) as u16;
-        self.pc = new_pc as usize;
-    }
-
-    fn branch(&mut self, byte: u8, condition: bool) -> u8 {
-        let mut result = 0;
-        if condition {
-            let old = self.pc;
-            self.pc += byte as usize;
-            if byte >= 0x80 {
-                self.pc -= 0x100 as usize
+    /// Run from the current `pc` until `predicate` returns true (it's checked after each
+    /// instruction) or `budget` cycles have been used. An instruction can't be split, so
+    /// the last one can go past the budget: that overshoot is taken out of the budget of the
+    /// next call, even when the predicate stopped on it. The listener is not called, use the
+    /// predicate instead.
+    /// Returns true if the predicate stopped the execution.
+    pub fn run_until<F>(&mut self, budget: u64, mut predicate: F) -> Result<bool, SixtyError>
+            where F: FnMut(&Cpu<B>) -> bool {
+        if self.overshoot >= budget {
+            self.overshoot -= budget;
+            return Ok(false);
+        }
+        let target = self.cycles + budget - self.overshoot;
+        self.overshoot = 0;
+        while self.cycles < target {
+            self.step()?;
+            if predicate(self) {
+                self.overshoot = self.cycles.saturating_sub(target);
+                return Ok(true);
             }
-            result += 1 + self.page_crossed(old as u16, self.pc);
         }
-        result
+        self.overshoot = self.cycles - target;
+        Ok(false)
     }
 
-    fn page_crossed(&self, old: u16, new: usize) -> u8 {
-        if ((old ^ new as u16) & 0xff00) > 0 { 1 } else { 0 }
+    /// Use `info` for the source lines, and its labels in the traces
+    pub fn set_debug_info(&mut self, info: DebugInfo) {
+        self.symbols.extend(info.symbols());
+        self.debug_info = Some(info);
     }
 
-    fn asl(&mut self, v: u8) -> u8 {
-        self.p.set_c(v & 0x80 != 0);
-        let result: u8 = v << 1;
-        self.p.set_nz_flags(result);
-        return result;
+    /// The PC, followed by its source line and scope when the debug info knows them:
+    /// `PC=0812 (hello.c:5, _main)`
+    pub fn location(&self) -> String {
+        let info = self.debug_info.as_ref();
+        let details: Vec<String> = [
+            info.and_then(|i| i.location(self.pc)).map(|l| l.to_string()),
+            info.and_then(|i| i.scope(self.pc)).map(|s| s.to_string()),
+        ].iter().flatten().cloned().collect();
+        if details.is_empty() {
+            format!("PC={:04X}", self.pc)
+        } else {
+            format!("PC={:04X} ({})", self.pc, details.join(", "))
+        }
     }
 
-    fn adc(&mut self, v: u8) {
-        if self.p.d() {
-            let mut l = (self.a & 0x0f) + (v & 0x0f) + self.p.c() as u8;
-            if l & 0xff > 9 { l += 6 }
-            let mut h = (self.a >> 4) + (v >> 4) + if l > 15 { 1 } else { 0 };
-            if h & 0xff > 9 { h += 6 };
-            let result = (l & 0x0f | (h << 4)) & 0xff;
+    /// Run until the PC reaches a source line other than the current one, stepping over the
+    /// code without debug info like the runtime library, or until `budget` cycles have been
+    /// used. Returns true if a new line was reached.
+    pub fn step_line(&mut self, budget: u64) -> Result<bool, SixtyError> {
+        let start = self.debug_info.as_ref().and_then(|i| i.location(self.pc)).cloned();
+        self.run_until(budget, |cpu| {
+            match cpu.debug_info.as_ref().and_then(|i| i.location(cpu.pc)) {
+                Some(location) => Some(location) != start.as_ref(),
+                None => false,
+            }
+        })
+    }
 
-            self.p.set_c(h > 15);
-            self.p.set_z(result == 0);
-            self.p.set_v(false);  // BCD never sets overflow flag
-            self.p.set_n((result & 0x80) != 0);  // N flag is valid on CMOS 6502/65816
+    fn operand(&self, pc: usize, opcode: u8) -> Operand {
+        self.decode_operand(opcode, |i| self.peek(pc + 1 + i))
+    }
 
-            self.a = result;
-        } else {
-            self.add(v);
+    /// `byte(i)` returns the i-th byte following the opcode
+    pub(crate) fn decode_operand<F>(&self, opcode: u8, byte: F) -> Operand
+            where F: Fn(usize) -> u8 {
+        let addressing_type = self.variant.addressing_types()[opcode as usize];
+        match self.variant.sizes()[opcode as usize] {
+            2 => Operand::Byte(byte(0)),
+            // BBR and BBS
+            3 if addressing_type == AddressingType::RELATIVE =>
+                Operand::ZeroPageRelative(byte(0), byte(1)),
+            3 => Operand::Word(byte(0) as u16 | (byte(1) as u16) << 8),
+            _ => Operand::None
         }
     }
 
-    fn add(&mut self, v: u8) {
-        let result: u16 = self.a as u16 + v as u16 + self.p.c() as u16;
-        // NOTE: Parentheses are important here! Remove them and carry6 is incorrectly calculated
-        let carry6 = (self.a & 0x7f) + (v as u8 & 0x7f) + self.p.c() as u8;
-        self.p.set_c(result & 0x100 != 0);
-        self.p.set_v(self.p.c() ^ (carry6 & 0x80 != 0));
-        let result2 = result as u8;
-        self.p.set_nz_flags(result2);
-        self.a = result2;
-    }
-
-    fn sbc(&mut self, v: u8) {
-        if self.p.d() {
-            let mut l: i16 = (self.a as i16 & 0x0f) - (v as i16 & 0x0f)
-                - if self.p.c() { 0 } else { 1 };
-            if (l & 0x10) != 0 { l -= 6 };
-            let mut h: i16 = (self.a  as i16 >> 4) - (v as i16 >> 4)
-                - if (l & 0x10) != 0 { 1 } else { 0 };
-            if (h & 0x10) != 0 { h -= 6 }
-            let result = (l & 0x0f | (h << 4)) & 0xff;
-
-            self.p.set_c((h & 0xff) < 15);
-            self.p.set_z(result == 0);
-            self.p.set_v(false);  // BCD never sets overflow flag
-            self.p.set_n((result & 0x80) != 0);
-
-            self.a = result as u8 & 0xff;
-        } else {
-            self.add((v ^ 0xff) as u8);
+    pub fn run(&mut self, start_pc: usize) -> Result<RunStatus, SixtyError> {
+        self.pc = start_pc;
+        let mut result = RunStatus::Continue;
+        loop {
+            self.step()?;
+
+            let stop = if let Some(l) = self.listener.borrow_mut().as_mut() {
+                l.on_pc_changed(self)
+            } else {
+                RunStatus::Continue
+            };
+
+            match stop {
+                RunStatus::Stop(success, ref reason) => {
+                    result = RunStatus::Stop(success, format!("{} at {}", reason, self.location()));
+                    break;
+                },
+                _ => {}
+            }
+        }
+        return Ok(result);
+    }
+
+    /// Run the instruction at `pc` straight through, without servicing interrupts, and
+    /// return the cycles it took. They are already added to `cycles`.
+    pub fn next_instruction(&mut self, pc: usize) -> Result<u64, SixtyError> {
+        self.check_opcode(pc, self.peek(pc))?;
+        let start = self.cycles;
+        self.pc = pc;
+        // Nothing yields outside of tick()
+        let _ = self.instruction_cycles();
+        if self.pc == DEBUG_PC {
+            println!("BREAKPOINT DEBUG_PC");
+        }
+        if self.cycles == DEBUG_CYCLES {
+            println!("BREAKPOINT CYCLES");
+        }
+        let close_to_breakpoint = self.pc > DEBUG_PC - 50 && self.pc < DEBUG_PC;
+        if DEBUG_ASM || close_to_breakpoint || self.cycles > DEBUG_CYCLES {
+            let (s, size) = disassemble(&self.memory, pc, self.variant, &self.symbols);
+            println!("{:08X}| {:<30} {}", self.cycles, s, self);
         }
+        Ok(self.cycles - start)
     }
 }
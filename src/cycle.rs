@@ -0,0 +1,608 @@
+//! Instruction execution. Each instruction is written once, as the sequence of bus cycles
+//! the real chip performs, dummy reads and writes included, and dispatched on its
+//! `Mnemonic`.
+//!
+//! In cycle accurate mode every bus cycle reaches the bus in order, so memory mapped
+//! hardware sees the same accesses as on the real chip. Otherwise the same sequence runs
+//! straight through, and the dummy accesses only take their cycle.
+//!
+//! `tick()` runs one cycle at a time without turning every instruction into a state
+//! machine: the instruction is restarted from the registers it began with, the bus cycles
+//! it already did are replayed from a log instead of reaching the bus again, and it stops
+//! right after doing one new bus cycle.
+
+use crate::constants::{self, AddressingType, IRQ_VECTOR_L, NMI_VECTOR_L, RESET_VECTOR_L};
+use crate::bus::Bus;
+use crate::cpu::{Cpu, CpuVariant, Interrupt, Operand, StepResult};
+use crate::decoder::Mnemonic::{self, *};
+use crate::error::SixtyError;
+use crate::memory::{AccessKind, BusCycle};
+
+const STACK_ADDRESS: usize = 0x100;
+
+/// Returned by a bus cycle when the current tick() has already done its own
+pub(crate) struct Yield;
+
+pub(crate) type Cycle<T> = Result<T, Yield>;
+
+#[derive(Clone, Copy)]
+enum Sequence {
+    Instruction,
+    Interrupt(Interrupt),
+}
+
+/// The registers when a sequence started, restored before each replay
+#[derive(Clone, Copy)]
+struct Registers {
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    pc: usize,
+    s: usize,
+}
+
+/// An instruction or interrupt sequence in progress
+pub(crate) struct CycleState {
+    sequence: Sequence,
+    registers: Registers,
+    bus_cycles: Vec<BusCycle>,
+    /// Index of the next bus cycle during the current replay
+    index: usize,
+    /// The current replay yields when it reaches this bus cycle
+    limit: usize,
+    /// `Cpu::cycles` when the sequence started, the first bus cycle happens on that cycle
+    start_cycle: u64,
+}
+
+/// How an instruction with an operand in memory accesses it
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    Read,
+    Write,
+    Modify,
+}
+
+pub(crate) fn access(mnemonic: Mnemonic) -> Access {
+    match mnemonic {
+        STA | STX | STY | STZ | SAX => Access::Write,
+        ASL | LSR | ROL | ROR | INC | DEC | TSB | TRB | SLO | RLA | SRE | RRA | DCP | ISC
+            | RMB0 | RMB1 | RMB2 | RMB3 | RMB4 | RMB5 | RMB6 | RMB7
+            | SMB0 | SMB1 | SMB2 | SMB3 | SMB4 | SMB5 | SMB6 | SMB7 => Access::Modify,
+        _ => Access::Read,
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    /// Advance the CPU by exactly one clock cycle, which is always one bus cycle. Returns
+    /// the result of the instruction when this was its last cycle. Interrupt sequences are
+    /// reported on their own, with the BRK opcode the hardware forces into the instruction
+    /// register. An instruction that faults, e.g. by accessing memory past the end of the
+    /// buffer, is abandoned. Returns `SixtyError::Unsupported` on the 65C816.
+    pub fn tick(&mut self) -> Result<Option<StepResult>, SixtyError> {
+        if self.variant == CpuVariant::W65C816 {
+            return Err(SixtyError::Unsupported { variant: self.variant, feature: "tick()" });
+        }
+        self.start_sequence(true)?;
+        let limit = self.cycle_state.as_ref().map_or(0, |s| s.bus_cycles.len()) + 1;
+        self.cycles += 1;
+        let result = self.run_sequence(limit);
+        match self.memory.take_fault() {
+            Some(error) => {
+                self.cycle_state = None;
+                Err(error)
+            },
+            None => Ok(result),
+        }
+    }
+
+    /// True if tick() stopped in the middle of an instruction
+    pub fn in_instruction(&self) -> bool {
+        self.cycle_state.is_some()
+    }
+
+    /// step() in cycle accurate mode. Also finishes an instruction started with tick().
+    pub(crate) fn step_cycles(&mut self) -> Result<StepResult, SixtyError> {
+        let first = self.finish_sequence(true)?;
+        if first.interrupt.is_none() {
+            return Ok(first);
+        }
+        // Like step(), run the first instruction of the handler right away
+        let mut result = self.finish_sequence(false)?;
+        result.cycles += first.cycles;
+        result.interrupt = first.interrupt;
+        result.writes.splice(0..0, first.writes);
+        result.bus_cycles.splice(0..0, first.bus_cycles);
+        Ok(result)
+    }
+
+    fn finish_sequence(&mut self, interrupts: bool) -> Result<StepResult, SixtyError> {
+        self.start_sequence(interrupts)?;
+        let done = self.cycle_state.as_ref().map_or(0, |s| s.bus_cycles.len());
+        // Only the limit of tick() stops a sequence before its end, and a sequence in
+        // progress was started by start_sequence()
+        let result = self.run_sequence(usize::MAX)
+            .expect("a sequence without a limit always completes");
+        self.cycles += (result.bus_cycles.len() - done) as u64;
+        Ok(result)
+    }
+
+    /// Illegal opcodes are refused before their first cycle
+    fn start_sequence(&mut self, interrupts: bool) -> Result<(), SixtyError> {
+        if self.cycle_state.is_some() {
+            return Ok(());
+        }
+        let sequence = match self.pending_interrupt(interrupts) {
+            Some(interrupt) => Sequence::Interrupt(interrupt),
+            None => {
+                self.check_opcode(self.pc, self.peek(self.pc))?;
+                Sequence::Instruction
+            },
+        };
+        let registers = Registers {
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            p: self.p.value(),
+            pc: self.pc,
+            s: self.stack_pointer,
+        };
+        self.cycle_state = Some(CycleState {
+            sequence,
+            registers,
+            bus_cycles: Vec::with_capacity(8),
+            index: 0,
+            limit: 0,
+            start_cycle: self.cycles,
+        });
+        Ok(())
+    }
+
+    /// Run the current sequence from its start until it either completes or reaches the
+    /// bus cycle number `limit`.
+    fn run_sequence(&mut self, limit: usize) -> Option<StepResult> {
+        let (sequence, registers) = {
+            let state = self.cycle_state.as_mut()?;
+            state.index = 0;
+            state.limit = limit;
+            (state.sequence, state.registers)
+        };
+
+        self.a = registers.a;
+        self.x = registers.x;
+        self.y = registers.y;
+        self.p.set_value(registers.p);
+        self.pc = registers.pc;
+        self.stack_pointer = registers.s;
+        self.branch_taken = false;
+        self.page_crossed = false;
+
+        let completed = match sequence {
+            Sequence::Instruction => self.instruction_cycles(),
+            Sequence::Interrupt(interrupt) => self.interrupt_cycles(interrupt),
+        };
+        if completed.is_err() {
+            return None;
+        }
+
+        let state = self.cycle_state.take()?;
+        self.writes.clear();
+        let (opcode, interrupt) = match sequence {
+            Sequence::Instruction => (state.bus_cycles[0].value, None),
+            Sequence::Interrupt(interrupt) => (constants::BRK, Some(interrupt)),
+        };
+        let operand = if interrupt.is_some() {
+            Operand::None
+        } else {
+            self.decode_operand(opcode, |i| {
+                state.bus_cycles.iter()
+                    .filter(|c| c.kind == AccessKind::OperandFetch)
+                    .nth(i)
+                    .map_or(0, |c| c.value)
+            })
+        };
+        Some(StepResult {
+            pc: registers.pc,
+            opcode,
+            operand,
+            cycles: state.bus_cycles.len() as u64,
+            branch_taken: self.branch_taken,
+            page_crossed: self.page_crossed,
+            writes: state.bus_cycles.iter()
+                .filter(|c| c.kind.is_write())
+                .map(|c| (c.address, c.value))
+                .collect(),
+            interrupt,
+            bus_cycles: state.bus_cycles,
+        })
+    }
+
+    /// Issue one bus cycle, or replay it if tick() already did it. Without a sequence in
+    /// progress the instruction runs straight through: the cycle is counted right away and
+    /// the dummy accesses only reach the bus when the CPU is cycle accurate.
+    fn bus_cycle(&mut self, address: usize, value: Option<u8>, kind: AccessKind) -> Cycle<u8> {
+        let address = address & self.variant.address_mask();
+        let cycle = match self.cycle_state.as_mut() {
+            Some(state) => {
+                let index = state.index;
+                if let Some(cycle) = state.bus_cycles.get(index) {
+                    state.index += 1;
+                    return Ok(cycle.value);
+                }
+                if index >= state.limit {
+                    return Err(Yield);
+                }
+                state.start_cycle + index as u64
+            },
+            None => {
+                self.cycles += 1;
+                if ! self.cycle_accurate
+                        && matches!(kind, AccessKind::DummyRead | AccessKind::DummyWrite) {
+                    return Ok(0);
+                }
+                self.cycles - 1
+            },
+        };
+        let value = match value {
+            Some(value) => {
+                self.set(address, value);
+                value
+            },
+            None => self.get(address),
+        };
+        let bus_cycle = BusCycle { address, value, kind };
+        if let Some(state) = self.cycle_state.as_mut() {
+            state.bus_cycles.push(bus_cycle);
+            state.index += 1;
+        }
+        if self.memory.observes_bus_cycles() {
+            self.memory.on_bus_cycle(bus_cycle, cycle);
+        }
+        Ok(value)
+    }
+
+    fn read(&mut self, address: usize, kind: AccessKind) -> Cycle<u8> {
+        self.bus_cycle(address, None, kind)
+    }
+
+    fn write(&mut self, address: usize, value: u8, kind: AccessKind) -> Cycle<()> {
+        self.bus_cycle(address, Some(value), kind).map(|_| ())
+    }
+
+    fn fetch(&mut self, kind: AccessKind) -> Cycle<u8> {
+        let result = self.read(self.pc, kind)?;
+        self.pc = (self.pc + 1) & 0xffff;
+        Ok(result)
+    }
+
+    fn fetch_word(&mut self) -> Cycle<u16> {
+        let low = self.fetch(AccessKind::OperandFetch)? as u16;
+        let high = self.fetch(AccessKind::OperandFetch)? as u16;
+        Ok(low | high << 8)
+    }
+
+    /// The 65C02 spends its extra cycles reading the last byte of the instruction again,
+    /// instead of the half computed addresses the NMOS 6502 puts on the bus
+    fn cmos_dummy_read(&mut self) -> Cycle<()> {
+        self.read(self.pc.wrapping_sub(1) & 0xffff, AccessKind::DummyRead).map(|_| ())
+    }
+
+    fn push(&mut self, value: u8) -> Cycle<()> {
+        self.write(STACK_ADDRESS + self.stack_pointer, value, AccessKind::StackPush)?;
+        self.stack_pointer = (self.stack_pointer + 0xff) & 0xff;
+        Ok(())
+    }
+
+    fn pull(&mut self) -> Cycle<u8> {
+        self.stack_pointer = (self.stack_pointer + 1) & 0xff;
+        self.read(STACK_ADDRESS + self.stack_pointer, AccessKind::StackPull)
+    }
+
+    pub(crate) fn interrupt_cycles(&mut self, interrupt: Interrupt) -> Cycle<()> {
+        // The opcode fetch and the next cycle are turned into dummy reads
+        self.read(self.pc, AccessKind::DummyRead)?;
+        self.read(self.pc, AccessKind::DummyRead)?;
+        let vector = match interrupt {
+            Interrupt::Nmi => NMI_VECTOR_L,
+            Interrupt::Irq => IRQ_VECTOR_L,
+        };
+        self.interrupt_sequence(false, vector)
+    }
+
+    /// The reset sequence is the one of the interrupts with the three pushes turned into
+    /// reads, which is why S ends up three lower without anything being written
+    pub(crate) fn reset_cycles(&mut self) -> Cycle<()> {
+        self.read(self.pc, AccessKind::DummyRead)?;
+        self.read(self.pc, AccessKind::DummyRead)?;
+        for _ in 0..3 {
+            self.read(STACK_ADDRESS + self.stack_pointer, AccessKind::DummyRead)?;
+            self.stack_pointer = (self.stack_pointer + 0xff) & 0xff;
+        }
+        let low = self.read(RESET_VECTOR_L, AccessKind::Read)? as usize;
+        let high = self.read(RESET_VECTOR_L + 1, AccessKind::Read)? as usize;
+        self.pc = low | high << 8;
+        Ok(())
+    }
+
+    /// Push PC and P, then jump through the vector. BRK pushes P with the B bit set.
+    fn interrupt_sequence(&mut self, brk: bool, vector: usize) -> Cycle<()> {
+        self.push((self.pc >> 8) as u8)?;
+        self.push(self.pc as u8)?;
+        self.push(self.p.value() | if brk { 1 << 4 } else { 0 })?;
+        self.p.set_i(true);
+        self.polled_i = None;
+        if self.variant.is_cmos() {
+            self.p.set_d(false);
+        }
+        let low = self.read(vector, AccessKind::Read)? as usize;
+        let high = self.read(vector + 1, AccessKind::Read)? as usize;
+        self.pc = low | high << 8;
+        Ok(())
+    }
+
+    /// Fetch the instruction at the PC and run it
+    pub(crate) fn instruction_cycles(&mut self) -> Cycle<()> {
+        let i = self.p.i();
+        let opcode = self.fetch(AccessKind::OpcodeFetch)?;
+        let mnemonic = self.variant.mnemonics()[opcode as usize];
+        self.opcode_cycles(opcode, mnemonic)?;
+        self.polled_i = if matches!(mnemonic, CLI | SEI | PLP) { Some(i) } else { None };
+        Ok(())
+    }
+
+    fn opcode_cycles(&mut self, opcode: u8, mnemonic: Mnemonic) -> Cycle<()> {
+        let addressing_type = self.variant.addressing_types()[opcode as usize];
+        match mnemonic {
+            BRK => {
+                // BRK skips the byte that follows it
+                self.fetch(AccessKind::OperandFetch)?;
+                self.interrupt_sequence(true, IRQ_VECTOR_L)
+            },
+            JSR => {
+                let low = self.fetch(AccessKind::OperandFetch)? as usize;
+                self.read(STACK_ADDRESS + self.stack_pointer, AccessKind::DummyRead)?;
+                self.push((self.pc >> 8) as u8)?;
+                self.push(self.pc as u8)?;
+                let high = self.read(self.pc, AccessKind::OperandFetch)? as usize;
+                self.pc = low | high << 8;
+                Ok(())
+            },
+            RTS => {
+                self.read(self.pc, AccessKind::DummyRead)?;
+                self.read(STACK_ADDRESS + self.stack_pointer, AccessKind::DummyRead)?;
+                let low = self.pull()? as usize;
+                let high = self.pull()? as usize;
+                self.pc = low | high << 8;
+                self.read(self.pc, AccessKind::DummyRead)?;
+                self.pc = (self.pc + 1) & 0xffff;
+                Ok(())
+            },
+            RTI => {
+                self.read(self.pc, AccessKind::DummyRead)?;
+                self.read(STACK_ADDRESS + self.stack_pointer, AccessKind::DummyRead)?;
+                let p = self.pull()?;
+                self.p.set_value(p);
+                let low = self.pull()? as usize;
+                let high = self.pull()? as usize;
+                self.pc = low | high << 8;
+                Ok(())
+            },
+            JMP => self.jmp_cycles(addressing_type),
+            PHA | PHP | PHX | PHY => {
+                self.read(self.pc, AccessKind::DummyRead)?;
+                let value = match mnemonic {
+                    PHA => self.a,
+                    // PHP always pushes P with the B bit set
+                    PHP => self.p.value() | 1 << 4,
+                    PHX => self.x,
+                    _ => self.y,
+                };
+                self.push(value)
+            },
+            PLA | PLP | PLX | PLY => {
+                self.read(self.pc, AccessKind::DummyRead)?;
+                self.read(STACK_ADDRESS + self.stack_pointer, AccessKind::DummyRead)?;
+                let value = self.pull()?;
+                match mnemonic {
+                    PLA => self.a = value,
+                    PLP => self.p.set_value(value),
+                    PLX => self.x = value,
+                    _ => self.y = value,
+                }
+                if mnemonic != PLP {
+                    self.p.set_nz_flags(value);
+                }
+                Ok(())
+            },
+            BPL | BMI | BVC | BVS | BCC | BCS | BNE | BEQ | BRA => {
+                let offset = self.fetch(AccessKind::OperandFetch)?;
+                let condition = match mnemonic {
+                    BPL => ! self.p.n(),
+                    BMI => self.p.n(),
+                    BVC => ! self.p.v(),
+                    BVS => self.p.v(),
+                    BCC => ! self.p.c(),
+                    BCS => self.p.c(),
+                    BNE => ! self.p.z(),
+                    BEQ => self.p.z(),
+                    _ => true,
+                };
+                self.branch_cycles(offset, condition)
+            },
+            BBR0 | BBR1 | BBR2 | BBR3 | BBR4 | BBR5 | BBR6 | BBR7
+                    | BBS0 | BBS1 | BBS2 | BBS3 | BBS4 | BBS5 | BBS6 | BBS7 => {
+                let address = self.fetch(AccessKind::OperandFetch)? as usize;
+                let value = self.read(address, AccessKind::Read)?;
+                self.read(address, AccessKind::DummyRead)?;
+                let offset = self.fetch(AccessKind::OperandFetch)?;
+                // BBS are the opcodes $8F-$FF
+                let bit_set = value & (1 << ((opcode >> 4) & 7)) != 0;
+                self.branch_cycles(offset, bit_set == (opcode & 0x80 != 0))
+            },
+            JAM | ANE | LXA | SHA | SHX | SHY | TAS | LAS => {
+                unreachable!("check_opcode() refuses illegal opcodes")
+            },
+            NOP if self.variant == CpuVariant::Cmos65C02
+                    && constants::CMOS_RESERVED_NOPS.contains(&opcode) => {
+                let size = self.variant.sizes()[opcode as usize];
+                for _ in 1..size {
+                    self.fetch(AccessKind::OperandFetch)?;
+                }
+                for _ in size..self.variant.timings()[opcode as usize] as usize {
+                    self.cmos_dummy_read()?;
+                }
+                Ok(())
+            },
+            _ => match addressing_type {
+                AddressingType::NONE | AddressingType::REGISTER_A => {
+                    self.read(self.pc, AccessKind::DummyRead)?;
+                    self.implied(mnemonic);
+                    Ok(())
+                },
+                AddressingType::IMMEDIATE => {
+                    let value = self.fetch(AccessKind::OperandFetch)?;
+                    self.read_instruction(mnemonic, true, value)
+                },
+                _ => match access(mnemonic) {
+                    Access::Read => {
+                        let address = self.effective_address(addressing_type, false)?;
+                        let value = self.read(address, AccessKind::Read)?;
+                        self.read_instruction(mnemonic, false, value)
+                    },
+                    Access::Write => {
+                        let address = self.effective_address(addressing_type, true)?;
+                        let value = match mnemonic {
+                            STA => self.a,
+                            STX => self.x,
+                            STY => self.y,
+                            SAX => self.a & self.x,
+                            _ => 0,
+                        };
+                        self.write(address, value, AccessKind::Write)
+                    },
+                    Access::Modify => {
+                        let address = self.effective_address(addressing_type, true)?;
+                        let value = self.read(address, AccessKind::Read)?;
+                        if self.variant.is_cmos() {
+                            self.read(address, AccessKind::DummyRead)?;
+                        } else {
+                            self.write(address, value, AccessKind::DummyWrite)?;
+                        }
+                        let result = self.modify_operation(mnemonic, opcode, value as u16) as u8;
+                        self.write(address, result, AccessKind::Write)
+                    },
+                },
+            },
+        }
+    }
+
+    fn jmp_cycles(&mut self, addressing_type: AddressingType) -> Cycle<()> {
+        let address = self.fetch_word()?;
+        let pointer = match addressing_type {
+            AddressingType::INDIRECT => address,
+            AddressingType::AIX => address.wrapping_add(self.x as u16),
+            _ => {
+                self.pc = address as usize;
+                return Ok(());
+            }
+        };
+        if self.variant.is_cmos() {
+            self.cmos_dummy_read()?;
+        }
+        let low = self.read(pointer as usize, AccessKind::Read)? as usize;
+        // The NMOS 6502 doesn't carry into the high byte of the pointer
+        let next = if self.variant.is_cmos() {
+            pointer.wrapping_add(1)
+        } else {
+            (pointer & 0xff00) | (pointer.wrapping_add(1) & 0xff)
+        };
+        let high = self.read(next as usize, AccessKind::Read)? as usize;
+        self.pc = low | high << 8;
+        Ok(())
+    }
+
+    fn branch_cycles(&mut self, offset: u8, condition: bool) -> Cycle<()> {
+        if condition {
+            self.read(self.pc, AccessKind::DummyRead)?;
+            let target = (self.pc as u16).wrapping_add(offset as i8 as u16) as usize;
+            self.branch_taken = true;
+            if (target ^ self.pc) & 0xff00 != 0 {
+                // PCL is fixed first, so the wrong page is read before the high byte is
+                self.read((self.pc & 0xff00) | (target & 0xff), AccessKind::DummyRead)?;
+                self.page_crossed = true;
+            }
+            self.pc = target;
+        }
+        Ok(())
+    }
+
+    /// Fetch the operand and compute the effective address. Writes and read-modify-writes
+    /// always spend a cycle fixing the high byte of indexed addresses, reads only when the
+    /// indexing crosses a page.
+    fn effective_address(&mut self, addressing_type: AddressingType, always_fix: bool)
+            -> Cycle<usize> {
+        match addressing_type {
+            AddressingType::ZP => Ok(self.fetch(AccessKind::OperandFetch)? as usize),
+            AddressingType::ZP_X | AddressingType::ZP_Y => {
+                let base = self.fetch(AccessKind::OperandFetch)?;
+                self.read(base as usize, AccessKind::DummyRead)?;
+                let index = if addressing_type == AddressingType::ZP_X { self.x } else { self.y };
+                Ok(base.wrapping_add(index) as usize)
+            },
+            AddressingType::ABSOLUTE => Ok(self.fetch_word()? as usize),
+            AddressingType::ABSOLUTE_X => {
+                let base = self.fetch_word()?;
+                self.indexed(base, self.x, always_fix)
+            },
+            AddressingType::ABSOLUTE_Y => {
+                let base = self.fetch_word()?;
+                self.indexed(base, self.y, always_fix)
+            },
+            AddressingType::INDIRECT_X => {
+                let pointer = self.fetch(AccessKind::OperandFetch)?;
+                self.read(pointer as usize, AccessKind::DummyRead)?;
+                let pointer = pointer.wrapping_add(self.x);
+                let low = self.read(pointer as usize, AccessKind::Read)? as usize;
+                let high = self.read(pointer.wrapping_add(1) as usize, AccessKind::Read)? as usize;
+                Ok(low | high << 8)
+            },
+            AddressingType::INDIRECT_Y | AddressingType::ZPI => {
+                let pointer = self.fetch(AccessKind::OperandFetch)?;
+                let low = self.read(pointer as usize, AccessKind::Read)? as u16;
+                let high = self.read(pointer.wrapping_add(1) as usize, AccessKind::Read)? as u16;
+                if addressing_type == AddressingType::ZPI {
+                    Ok((low | high << 8) as usize)
+                } else {
+                    self.indexed(low | high << 8, self.y, always_fix)
+                }
+            },
+            _ => unreachable!("{:?} doesn't access memory", addressing_type),
+        }
+    }
+
+    fn indexed(&mut self, base: u16, index: u8, always_fix: bool) -> Cycle<usize> {
+        let address = base.wrapping_add(index as u16);
+        let crossed = (base ^ address) & 0xff00 != 0;
+        if crossed || always_fix {
+            if self.variant.is_cmos() {
+                self.cmos_dummy_read()?;
+            } else {
+                // The high byte hasn't been fixed yet
+                let unfixed = (base & 0xff00) | (address & 0xff);
+                self.read(unfixed as usize, AccessKind::DummyRead)?;
+            }
+        }
+        self.page_crossed |= crossed;
+        Ok(address as usize)
+    }
+
+    fn read_instruction(&mut self, mnemonic: Mnemonic, immediate: bool, v: u8) -> Cycle<()> {
+        // The 65C02 takes one more cycle for ADC and SBC in decimal mode
+        let decimal = self.variant.is_cmos() && self.p.d() && matches!(mnemonic, ADC | SBC);
+        self.read_operation(mnemonic, immediate, v as u16);
+        if decimal {
+            self.cmos_dummy_read()?;
+        }
+        Ok(())
+    }
+}
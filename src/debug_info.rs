@@ -0,0 +1,341 @@
+//! The debug info that ca65 and ld65 write with `-g` and `--dbgfile`, for source level
+//! debugging: which source file and line each address comes from, the scopes (the functions
+//! of a C program), the labels and the local variables of the C functions.
+//!
+//! Give it to the CPU with `Cpu::set_debug_info()` to step one source line at a time with
+//! `Cpu::step_line()` and to get the source line of the PC with `Cpu::location()`.
+
+use crate::bus::Bus;
+use crate::error::SixtyError;
+use crate::memory::read_file;
+use crate::symbols::{debug_record, number, SymbolFormat, Symbols};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A line of a source file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    /// Counted from 1
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// A local variable of a C function, on the C stack
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalVariable {
+    pub name: String,
+    pub address: usize,
+    /// The word at `address`, only the low byte is meaningful for a `char`
+    pub value: u16,
+}
+
+struct Line {
+    location: Location,
+    /// A line of C rather than of assembly
+    c: bool,
+}
+
+struct Scope {
+    name: String,
+    parent: Option<usize>,
+    /// Start and end (exclusive) of the address ranges of the scope
+    ranges: Vec<(usize, usize)>,
+}
+
+struct CSymbol {
+    name: String,
+    scope: usize,
+    /// The offset from the C stack pointer of the `auto` variables
+    offset: Option<i64>,
+}
+
+pub struct DebugInfo {
+    lines: Vec<Line>,
+    /// Start, end (exclusive) and index in `lines` of the address ranges of each line,
+    /// sorted by start
+    ranges: Vec<(usize, usize, usize)>,
+    /// The size of the largest range, how far before an address `location()` has to look
+    longest: usize,
+    scopes: HashMap<usize, Scope>,
+    c_symbols: Vec<CSymbol>,
+    symbols: Symbols,
+    /// The zero page address of the C stack pointer of the cc65 runtime
+    c_stack_pointer: Option<usize>,
+}
+
+/// `id=...` as a number
+fn field(fields: &HashMap<&str, &str>, name: &str) -> Result<usize, String> {
+    fields.get(name)
+        .ok_or_else(|| format!("missing {}", name))
+        .and_then(|value| number(value).ok_or_else(|| format!("invalid {} {}", name, value)))
+}
+
+/// A list of ids like `span=1+2+3`
+fn ids(fields: &HashMap<&str, &str>, name: &str) -> Result<Vec<usize>, String> {
+    match fields.get(name) {
+        Some(list) => list.split('+')
+            .map(|id| number(id).ok_or_else(|| format!("invalid {} {}", name, list)))
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+impl DebugInfo {
+    pub fn parse(text: &str) -> Result<DebugInfo, SixtyError> {
+        let mut files = HashMap::new();
+        let mut segments = HashMap::new();
+        let mut spans = HashMap::new();
+        // The records that refer to spans, resolved once all the spans are known
+        let mut lines = Vec::new();
+        let mut scopes = Vec::new();
+        let mut result = DebugInfo {
+            lines: Vec::new(),
+            ranges: Vec::new(),
+            longest: 0,
+            scopes: HashMap::new(),
+            c_symbols: Vec::new(),
+            symbols: Symbols::parse(text, Some(SymbolFormat::Ca65Debug))?,
+            c_stack_pointer: None,
+        };
+        for (index, line) in text.lines().enumerate() {
+            let (kind, fields) = debug_record(line.trim());
+            let mut record = || -> Result<(), String> {
+                match kind {
+                    "file" => {
+                        let name = fields.get("name").ok_or("missing name")?;
+                        files.insert(field(&fields, "id")?, name.to_string());
+                    },
+                    "seg" => {
+                        segments.insert(field(&fields, "id")?, field(&fields, "start")?);
+                    },
+                    "span" => {
+                        spans.insert(field(&fields, "id")?,
+                            (field(&fields, "seg")?, field(&fields, "start")?, field(&fields, "size")?));
+                    },
+                    "line" => {
+                        // Type 1 is a line of C, 0 of assembly and 2 a macro expansion
+                        let c = fields.get("type") == Some(&"1");
+                        lines.push((field(&fields, "file")?, field(&fields, "line")?, c, ids(&fields, "span")?));
+                    },
+                    "scope" => {
+                        let parent = fields.get("parent").and_then(|p| number(p));
+                        let name = fields.get("name").unwrap_or(&"").to_string();
+                        scopes.push((field(&fields, "id")?, name, parent, ids(&fields, "span")?));
+                    },
+                    "csym" => {
+                        let offset = match fields.get("sc") {
+                            Some(&"auto") => Some(fields.get("offs")
+                                .and_then(|o| o.parse().ok())
+                                .ok_or("auto variable without an offset")?),
+                            _ => None,
+                        };
+                        let name = fields.get("name").ok_or("missing name")?;
+                        result.c_symbols.push(CSymbol {
+                            name: name.to_string(),
+                            scope: field(&fields, "scope")?,
+                            offset,
+                        });
+                    },
+                    "sym" if matches!(fields.get("name"), Some(&"c_sp") | Some(&"sp"))
+                            && fields.get("addrsize") == Some(&"zeropage") => {
+                        result.c_stack_pointer = fields.get("val").and_then(|v| number(v));
+                    },
+                    _ => {},
+                }
+                Ok(())
+            };
+            record().map_err(|message| SixtyError::BadSymbols {
+                format: SymbolFormat::Ca65Debug,
+                message: format!("line {}: {}", index + 1, message),
+            })?;
+        }
+
+        let error = |message: String| SixtyError::BadSymbols { format: SymbolFormat::Ca65Debug, message };
+        let range = |span: &usize| -> Result<(usize, usize), SixtyError> {
+            let (segment, start, size) = spans.get(span)
+                .ok_or_else(|| error(format!("unknown span {}", span)))?;
+            let base = segments.get(segment)
+                .ok_or_else(|| error(format!("unknown segment {}", segment)))?;
+            Ok((base + start, base + start + size))
+        };
+        for (file, line, c, spans) in lines {
+            let file = files.get(&file).ok_or_else(|| error(format!("unknown file {}", file)))?;
+            for span in &spans {
+                let (start, end) = range(span)?;
+                result.ranges.push((start, end, result.lines.len()));
+            }
+            result.lines.push(Line { location: Location { file: file.clone(), line }, c });
+        }
+        result.ranges.sort_unstable();
+        result.longest = result.ranges.iter().map(|(start, end, _)| end - start).max().unwrap_or(0);
+        for (id, name, parent, spans) in scopes {
+            let ranges = spans.iter().map(range).collect::<Result<_, _>>()?;
+            result.scopes.insert(id, Scope { name, parent, ranges });
+        }
+        Ok(result)
+    }
+
+    pub fn load_file(file_name: &str) -> Result<DebugInfo, SixtyError> {
+        let data = read_file(file_name)?;
+        DebugInfo::parse(&String::from_utf8_lossy(&data))
+    }
+
+    /// The labels of the program
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// The source line `address` was assembled from. A line of C wins over the line of the
+    /// assembly generated for it, then the shortest range wins.
+    pub fn location(&self, address: usize) -> Option<&Location> {
+        let first = self.ranges.partition_point(|(start, _, _)| start + self.longest <= address);
+        let last = self.ranges.partition_point(|(start, _, _)| *start <= address);
+        self.ranges[first..last].iter()
+            .filter(|(_, end, _)| address < *end)
+            .min_by_key(|(start, end, line)| (! self.lines[*line].c, end - start))
+            .map(|(_, _, line)| &self.lines[*line].location)
+    }
+
+    /// The innermost scope containing `address`, a function in a C program
+    fn scope_id(&self, address: usize) -> Option<usize> {
+        self.scopes.iter()
+            .filter_map(|(id, scope)| scope.ranges.iter()
+                .filter(|(start, end)| (*start..*end).contains(&address))
+                .map(|(start, end)| end - start)
+                .min()
+                .map(|size| (size, *id)))
+            .min()
+            .map(|(_, id)| id)
+    }
+
+    /// The name of the innermost named scope containing `address`
+    pub fn scope(&self, address: usize) -> Option<&str> {
+        let mut id = self.scope_id(address);
+        while let Some(scope) = id.and_then(|id| self.scopes.get(&id)) {
+            if ! scope.name.is_empty() {
+                return Some(&scope.name);
+            }
+            id = scope.parent;
+        }
+        None
+    }
+
+    /// The local variables of the C function `pc` is in and of its enclosing blocks, read
+    /// from the C stack
+    pub fn locals<B: Bus + ?Sized>(&self, bus: &B, pc: usize) -> Vec<LocalVariable> {
+        let stack_pointer = match self.c_stack_pointer {
+            Some(address) => bus.peek(address) as usize | (bus.peek(address + 1) as usize) << 8,
+            None => return Vec::new(),
+        };
+        let mut scopes = Vec::new();
+        let mut id = self.scope_id(pc);
+        while let Some(scope) = id {
+            scopes.push(scope);
+            id = self.scopes.get(&scope).and_then(|s| s.parent);
+        }
+        self.c_symbols.iter()
+            .filter(|symbol| scopes.contains(&symbol.scope))
+            .filter_map(|symbol| symbol.offset.map(|offset| {
+                let address = (stack_pointer as i64 + offset) as usize & 0xffff;
+                let value = bus.peek(address) as u16 | (bus.peek((address + 1) & 0xffff) as u16) << 8;
+                LocalVariable { name: symbol.name.clone(), address, value }
+            }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::cpu::{Cpu, CpuVariant};
+    use crate::memory::Memory;
+
+    #[test]
+    fn debug_info_maps_source_lines() {
+        // hello.c: main() { int i; int count; ... } calling sub(), compiled to hello.s
+        let program = assemble("
+                    .org $0800
+                    ldx #0
+            loop:   inx
+                    jsr sub
+                    jmp loop
+            sub:    rts
+        ", CpuVariant::Nmos6502).unwrap();
+        let info = DebugInfo::parse(concat!(
+            "version\tmajor=2,minor=0\n",
+            "file\tid=0,name=\"hello.c\",size=120,mtime=0x00000000,mod=0\n",
+            "file\tid=1,name=\"hello.s\",size=900,mtime=0x00000000,mod=0\n",
+            "line\tid=0,file=0,line=3,type=1,span=0\n",
+            "line\tid=1,file=0,line=4,type=1,span=1+2\n",
+            "line\tid=2,file=0,line=5,type=1,span=3\n",
+            "line\tid=3,file=0,line=9,type=1,span=4\n",
+            "line\tid=4,file=1,line=20,span=0\n",
+            "seg\tid=0,name=\"CODE\",start=0x000800,size=0x000A,addrsize=absolute,type=ro\n",
+            "span\tid=0,seg=0,start=0,size=2\n",
+            "span\tid=1,seg=0,start=2,size=1\n",
+            "span\tid=2,seg=0,start=3,size=3\n",
+            "span\tid=3,seg=0,start=6,size=3\n",
+            "span\tid=4,seg=0,start=9,size=1\n",
+            "span\tid=5,seg=0,start=0,size=10\n",
+            "span\tid=6,seg=0,start=0,size=9\n",
+            "scope\tid=0,name=\"\",mod=0,size=10,span=5\n",
+            "scope\tid=1,name=\"_main\",mod=0,type=scope,size=9,parent=0,sym=0,span=6\n",
+            "scope\tid=2,name=\"_sub\",mod=0,type=scope,size=1,parent=0,sym=1,span=4\n",
+            "sym\tid=0,name=\"_main\",addrsize=absolute,scope=0,def=0,val=0x800,seg=0,type=lab\n",
+            "sym\tid=1,name=\"_sub\",addrsize=absolute,scope=0,def=3,val=0x809,seg=0,type=lab\n",
+            "sym\tid=2,name=\"sp\",addrsize=zeropage,scope=0,def=4,val=0x2,type=lab\n",
+            "csym\tid=0,name=\"main\",scope=0,type=0,sc=ext,sym=0\n",
+            "csym\tid=1,name=\"i\",scope=1,type=1,sc=auto,offs=0\n",
+            "csym\tid=2,name=\"count\",scope=1,type=1,sc=auto,offs=2\n",
+        )).unwrap();
+        assert_eq!(info.location(0x800).unwrap().to_string(), "hello.c:3");
+        assert_eq!(info.location(0x804).unwrap().to_string(), "hello.c:4");
+        assert_eq!(info.location(0x80a), None);
+        assert_eq!(info.scope(0x809), Some("_sub"));
+        assert_eq!(info.symbols().address("_sub"), Some(0x809));
+
+        let mut memory = Memory::new_with_vec(program.to_vec(), None);
+        for (address, value) in [(0x02, 0xf0), (0x03, 0x07), (0x07f0, 5), (0x07f2, 0x34), (0x07f3, 0x12)] {
+            memory.write(address, value);
+        }
+        let locals: Vec<_> = info.locals(&memory, 0x806).into_iter()
+            .map(|l| (l.name, l.address, l.value))
+            .collect();
+        assert_eq!(locals, vec!(("i".to_string(), 0x07f0, 5), ("count".to_string(), 0x07f2, 0x1234)));
+        assert!(info.locals(&memory, 0x809).is_empty());
+
+        let mut cpu = Cpu::new(memory, CpuVariant::Nmos6502, None);
+        assert_eq!(cpu.location(), "PC=0000");
+        cpu.set_debug_info(info);
+        cpu.pc = 0x800;
+        assert_eq!(cpu.location(), "PC=0800 (hello.c:3, _main)");
+        let mut lines = Vec::new();
+        for _ in 0..4 {
+            assert!(cpu.step_line(100).unwrap());
+            lines.push(cpu.location());
+        }
+        // The JSR is on the same line as the INX, the RTS returns to the line of the JMP
+        assert_eq!(lines, vec!("PC=0802 (hello.c:4, _main)", "PC=0809 (hello.c:9, _sub)",
+            "PC=0806 (hello.c:5, _main)", "PC=0802 (hello.c:4, _main)"));
+        assert_eq!(cpu.symbols.name(0x809), Some("_sub"));
+
+        assert_eq!(DebugInfo::parse("line\tid=0,file=7,line=3,span=0\n").err().unwrap().to_string(),
+            "Invalid ca65 debug file: unknown file 7");
+        // Quoted values can contain commas
+        let info = DebugInfo::parse(concat!(
+            "file\tid=0,name=\"a,b.c\",size=1,mtime=0x00000000,mod=0\n",
+            "line\tid=0,file=0,line=2,type=1,span=0\n",
+            "seg\tid=0,name=\"CODE\",start=0x000800,size=0x0002,addrsize=absolute,type=ro\n",
+            "span\tid=0,seg=0,start=0,size=2\n",
+        )).unwrap();
+        assert_eq!(info.location(0x801).unwrap().to_string(), "a,b.c:2");
+        assert_eq!((info.location(0x7ff), info.location(0x802)), (None, None));
+    }
+}
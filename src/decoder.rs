@@ -0,0 +1,237 @@
+//! Instructions decoded into data rather than text, for debuggers and analyzers. `decode()`
+//! reads one instruction from a `Bus` with the tables of a `CpuVariant`, `decode_range()`
+//! walks an address range one instruction after the other.
+
+use crate::bus::Bus;
+use crate::constants::AddressingType;
+use crate::cpu::CpuVariant;
+use crate::symbols::Symbols;
+use std::fmt;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+macro_rules! mnemonics {
+    ($($name: ident),* $(,)?) => {
+        /// The instructions of every variant, named like in the opcode tables
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum Mnemonic {
+            $($name),*
+        }
+
+        impl Mnemonic {
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Mnemonic::$name => stringify!($name)),*
+                }
+            }
+
+            /// The mnemonic called `name` in the opcode tables, in upper case
+            pub fn from_name(name: &str) -> Option<Mnemonic> {
+                match name {
+                    $(stringify!($name) => Some(Mnemonic::$name),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+mnemonics! {
+    ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC, BVS, CLC, CLD, CLI, CLV, CMP,
+    CPX, CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JMP, JSR, LDA, LDX, LDY, LSR, NOP, ORA, PHA,
+    PHP, PLA, PLP, ROL, ROR, RTI, RTS, SBC, SEC, SED, SEI, STA, STX, STY, TAX, TAY, TSX, TXA,
+    TXS, TYA,
+    // 65C02
+    BRA, PHX, PHY, PLX, PLY, STZ, TRB, TSB, STP, WAI,
+    BBR0, BBR1, BBR2, BBR3, BBR4, BBR5, BBR6, BBR7, BBS0, BBS1, BBS2, BBS3, BBS4, BBS5, BBS6,
+    BBS7, RMB0, RMB1, RMB2, RMB3, RMB4, RMB5, RMB6, RMB7, SMB0, SMB1, SMB2, SMB3, SMB4, SMB5,
+    SMB6, SMB7,
+    // Undocumented NMOS opcodes
+    ALR, ANC, ANE, ARR, DCP, ISC, JAM, LAS, LAX, LXA, RLA, RRA, SAX, SBX, SHA, SHX, SHY, SLO,
+    SRE, TAS,
+    // 65C816
+    BRL, COP, JML, JSL, MVN, MVP, PEA, PEI, PER, PHB, PHD, PHK, PLB, PLD, REP, RTL, SEP, TCD,
+    TCS, TDC, TSC, TXY, TYX, WDM, XBA, XCE,
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+fn mnemonic_table(names: &[&str; 256]) -> [Mnemonic; 256] {
+    let mut result = [Mnemonic::NOP; 256];
+    for (mnemonic, name) in result.iter_mut().zip(names.iter()) {
+        *mnemonic = Mnemonic::from_name(name)
+            .unwrap_or_else(|| panic!("{} is missing from Mnemonic", name));
+    }
+    result
+}
+
+/// The opcode names tables of `variant` as mnemonics, built on first use
+pub(crate) fn mnemonics(variant: CpuVariant) -> &'static [Mnemonic; 256] {
+    static NMOS: OnceLock<[Mnemonic; 256]> = OnceLock::new();
+    static CMOS: OnceLock<[Mnemonic; 256]> = OnceLock::new();
+    static W65C816: OnceLock<[Mnemonic; 256]> = OnceLock::new();
+    let table = match variant {
+        CpuVariant::Cmos65C02 => &CMOS,
+        CpuVariant::W65C816 => &W65C816,
+        _ => &NMOS,
+    };
+    table.get_or_init(|| mnemonic_table(variant.opcode_names()))
+}
+
+/// One decoded instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: usize,
+    pub opcode: u8,
+    pub mnemonic: Mnemonic,
+    pub mode: AddressingType,
+    /// The operand bytes, little endian: the zero page address and branch offset of BBR and
+    /// BBS are the low and high bytes, and so are the destination and source banks of MVN
+    /// and MVP
+    pub operand: u32,
+    /// The address the instruction reads, writes or jumps to, when it doesn't depend on the
+    /// registers or on memory: zero page and absolute operands, and branch targets. The
+    /// direct page of the 65C816 moves with D, its direct page operands have no target.
+    pub target: Option<usize>,
+    /// From the size table, so without the extra immediate byte of the 16 bit registers
+    /// of the 65C816
+    pub size: usize,
+    /// From the timing table, without the extra cycles for page crossing and taken branches
+    pub cycles: u8,
+}
+
+impl Instruction {
+    /// The bytes after the opcode
+    pub fn operand_bytes(&self) -> Vec<u8> {
+        (0..self.size - 1).map(|i| (self.operand >> (8 * i)) as u8).collect()
+    }
+
+    /// Like `to_string()`, with the addresses that have a name in `symbols` replaced by it:
+    /// `JSR COUT`
+    pub fn to_string_with(&self, symbols: &Symbols) -> String {
+        let operand = self.mode.to_string_with(self.address, &self.operand_bytes(), symbols);
+        if operand.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, operand)
+        }
+    }
+}
+
+/// In the syntax of the assembler, like `LDA ($12),Y`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_with(&Symbols::new()))
+    }
+}
+
+/// Decode the instruction at `address` with the tables of `variant`. The operand bytes wrap
+/// within the 64K bank of `address`, like the PC.
+pub fn decode<B: Bus + ?Sized>(bus: &B, address: usize, variant: CpuVariant) -> Instruction {
+    let bank = address & !0xffff;
+    let in_bank = |offset: usize| bank | ((address + offset) & 0xffff);
+    let opcode = bus.peek(address);
+    let size = variant.sizes()[opcode as usize];
+    let mode = variant.addressing_types()[opcode as usize];
+    let mnemonic = variant.mnemonics()[opcode as usize];
+    let operand = (1..size).rev().fold(0, |result, i| result << 8 | bus.peek(in_bank(i)) as u32);
+    let branch = |offset: i16, size: usize| {
+        bank | ((address + size) as u16).wrapping_add(offset as u16) as usize
+    };
+    let target = match mode {
+        AddressingType::ZP if variant != CpuVariant::W65C816 => Some(operand as usize),
+        AddressingType::ABSOLUTE => Some(operand as usize),
+        AddressingType::ABSOLUTE_LONG => Some(operand as usize),
+        // BBR and BBS have their offset in the last byte
+        AddressingType::RELATIVE => Some(branch((operand >> (8 * (size - 2))) as u8 as i8 as i16, size)),
+        AddressingType::RELATIVE_LONG => Some(branch(operand as u16 as i16, size)),
+        _ => None,
+    };
+    // Jumps and calls stay in the program bank
+    let target = match (mnemonic, mode) {
+        (Mnemonic::JMP, AddressingType::ABSOLUTE) | (Mnemonic::JSR, AddressingType::ABSOLUTE) =>
+            target.map(|t| bank | t),
+        _ => target,
+    };
+    Instruction {
+        address,
+        opcode,
+        mnemonic,
+        mode,
+        operand,
+        target,
+        size,
+        cycles: variant.timings()[opcode as usize],
+    }
+}
+
+/// The instructions that start in `range`, each one after the previous one
+pub fn decode_range<B: Bus + ?Sized>(bus: &B, range: Range<usize>, variant: CpuVariant)
+        -> Instructions<'_, B> {
+    Instructions { bus, address: range.start, end: range.end, variant }
+}
+
+pub struct Instructions<'a, B: Bus + ?Sized> {
+    bus: &'a B,
+    address: usize,
+    end: usize,
+    variant: CpuVariant,
+}
+
+impl<'a, B: Bus + ?Sized> Iterator for Instructions<'a, B> {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Instruction> {
+        if self.address >= self.end {
+            return None;
+        }
+        let result = decode(self.bus, self.address, self.variant);
+        self.address += result.size;
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::*;
+    use crate::memory::Memory;
+    use crate::test::memory;
+
+    #[test]
+    fn decoder_returns_instructions() {
+        let m = Memory::new_with_vec(vec!(LDA_IMM, 0x42, STA_ZP_X, 0x10, JSR, 0x34, 0x12,
+            BNE, 0xfe, 0x0f, 0x80, 0x7f, JMP_IND, 0x00, 0x02), None);
+        let instructions: Vec<Instruction> = decode_range(&m, 0..15, CpuVariant::Cmos65C02).collect();
+        let summary: Vec<_> = instructions.iter()
+            .map(|i| (i.address, i.mnemonic, i.mode, i.operand, i.target, i.size, i.cycles))
+            .collect();
+        assert_eq!(summary, vec!(
+            (0, Mnemonic::LDA, AddressingType::IMMEDIATE, 0x42, None, 2, 2),
+            (2, Mnemonic::STA, AddressingType::ZP_X, 0x10, None, 2, 4),
+            (4, Mnemonic::JSR, AddressingType::ABSOLUTE, 0x1234, Some(0x1234), 3, 6),
+            (7, Mnemonic::BNE, AddressingType::RELATIVE, 0xfe, Some(7), 2, 2),
+            // BBR0 $80, +$7F
+            (9, Mnemonic::BBR0, AddressingType::RELATIVE, 0x7f80, Some(0x8b), 3, 5),
+            (12, Mnemonic::JMP, AddressingType::INDIRECT, 0x0200, None, 3, 6),
+        ));
+
+        // Every opcode of every variant has a mnemonic
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Cmos65C02, CpuVariant::W65C816].iter() {
+            for opcode in 0..=0xffu8 {
+                let name = variant.opcode_names()[opcode as usize];
+                let instruction = decode(&memory(&[(0xffff, &[opcode])]), 0xffff, *variant);
+                assert_eq!(instruction.mnemonic.name(), name);
+                assert_eq!(Mnemonic::from_name(name), Some(instruction.mnemonic));
+                assert_eq!(instruction.size, variant.sizes()[opcode as usize]);
+            }
+        }
+        // The operand wraps at 64K
+        let m = memory(&[(0xfffe, &[JMP, 0x34]), (0, &[0x12])]);
+        assert_eq!(decode(&m, 0xfffe, CpuVariant::Nmos6502).target, Some(0x1234));
+    }
+}
@@ -0,0 +1,678 @@
+//! Disassembly of a whole program. Starting from the interrupt vectors and the entry points
+//! it's given, the disassembler follows jumps, subroutine calls and branches to find the
+//! code, and everything it doesn't reach is data. Jump and call targets, and the addresses
+//! used by absolute operands, get labels, and `Disassembly::source()` prints source that
+//! `assembler::assemble()` turns back into the same bytes.
+//!
+//! Only opcodes the assembler would encode the same way are taken as code: an undocumented
+//! NOP or the second SBC #, for example, ends up as data. The stable undocumented NMOS
+//! opcodes are code, but they're printed as bytes for Merlin, which doesn't know them.
+//!
+//! `Disassembly::print()` writes the program for ca65, ACME, 64tass or Merlin instead, either
+//! as bare source or as a listing with the address, bytes and cycles of each instruction.
+//! Labels that are only the target of nearby branches become local labels in the dialects
+//! that have them.
+
+use crate::assembler;
+use crate::constants::{AddressingType, IRQ_VECTOR_L, NMI_VECTOR_L, RESET_VECTOR_L};
+use crate::cpu::CpuVariant;
+use crate::decoder::Mnemonic;
+use crate::error::SixtyError;
+use crate::symbols::Symbols;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// The assembler that the printed source is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    /// `assembler::assemble()`
+    Sixty,
+    Ca65,
+    Acme,
+    Tass64,
+    /// Merlin, the classic Apple ][ assembler: upper case, labels in the first column
+    Merlin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Just the source
+    Source,
+    /// The address, bytes and base cycle count of each line in columns before the source
+    Listing,
+}
+
+/// How a dialect writes what the disassembler prints
+struct Syntax {
+    upper_case: bool,
+    /// `ASL A` rather than `ASL`
+    accumulator: bool,
+    /// The prefix of cheap local labels, which are valid between two global labels
+    local: Option<&'static str>,
+    colon: bool,
+    org: &'static str,
+    equate: &'static str,
+    byte: &'static str,
+    word: &'static str,
+    /// Appended to the mnemonic and prepended to the operand to force absolute addressing
+    absolute: (&'static str, &'static str),
+    /// The line selecting the 65C02 or the undocumented NMOS opcodes
+    cmos: Option<&'static str>,
+    nmos: Option<&'static str>,
+    /// Whether the stable undocumented NMOS opcodes assemble, after the `nmos` line if any
+    undocumented: bool,
+}
+
+impl Dialect {
+    fn syntax(&self) -> Syntax {
+        match self {
+            Dialect::Sixty => Syntax {
+                upper_case: false, accumulator: true, local: None, colon: true,
+                org: ".org", equate: "=", byte: ".byte", word: ".word", absolute: ("", "a:"),
+                cmos: None, nmos: None, undocumented: true,
+            },
+            Dialect::Ca65 => Syntax {
+                upper_case: false, accumulator: true, local: Some("@"), colon: true,
+                org: ".org", equate: "=", byte: ".byte", word: ".word", absolute: ("", "a:"),
+                cmos: Some(".setcpu \"65C02\""), nmos: Some(".setcpu \"6502X\""),
+                undocumented: true,
+            },
+            Dialect::Acme => Syntax {
+                upper_case: false, accumulator: false, local: Some("@"), colon: false,
+                org: "* =", equate: "=", byte: "!byte", word: "!word", absolute: ("+2", ""),
+                cmos: Some("!cpu w65c02"), nmos: Some("!cpu 6510"), undocumented: true,
+            },
+            Dialect::Tass64 => Syntax {
+                upper_case: false, accumulator: true, local: Some("_"), colon: false,
+                org: "* =", equate: "=", byte: ".byte", word: ".word", absolute: ("", "@w "),
+                cmos: Some(".cpu \"w65c02\""), nmos: Some(".cpu \"6502i\""),
+                undocumented: true,
+            },
+            Dialect::Merlin => Syntax {
+                upper_case: true, accumulator: false, local: Some(":"), colon: false,
+                org: "ORG", equate: "EQU", byte: "DFB", word: "DA", absolute: (":", ""),
+                cmos: Some("XC"), nmos: None, undocumented: false,
+            },
+        }
+    }
+}
+
+/// What a byte of the program is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteKind {
+    /// The opcode of an instruction
+    Opcode,
+    /// An operand byte of an instruction
+    Operand,
+    Data,
+}
+
+pub struct Disassembly {
+    origin: usize,
+    bytes: Vec<u8>,
+    variant: CpuVariant,
+    kinds: Vec<ByteKind>,
+    labels: BTreeMap<usize, String>,
+    /// The labels that can be local
+    locals: BTreeSet<usize>,
+    /// What the code refers to other than with branches, and the branches with their target
+    references: BTreeSet<usize>,
+    branches: Vec<(usize, usize)>,
+    /// Names for the addresses outside of the program
+    symbols: Symbols,
+}
+
+/// Data bytes per `.byte` line
+const BYTES_PER_LINE: usize = 8;
+
+/// Disassemble `bytes`, loaded at `origin`. The code is traced from `entry_points` and from
+/// the NMI, reset and IRQ vectors if the program covers them. The program has to fit in the
+/// first 64K, and the 65C816 isn't supported.
+pub fn disassemble(bytes: &[u8], origin: usize, variant: CpuVariant, entry_points: &[usize])
+        -> Result<Disassembly, SixtyError> {
+    if variant == CpuVariant::W65C816 {
+        return Err(SixtyError::Unsupported { variant, feature: "The disassembler" });
+    }
+    if origin + bytes.len() > 0x10000 {
+        return Err(SixtyError::AddressOutOfRange(origin.max(0x10000)));
+    }
+    let mut result = Disassembly {
+        origin,
+        bytes: bytes.to_vec(),
+        variant,
+        kinds: vec![ByteKind::Data; bytes.len()],
+        labels: BTreeMap::new(),
+        locals: BTreeSet::new(),
+        references: BTreeSet::new(),
+        branches: Vec::new(),
+        symbols: Symbols::new(),
+    };
+    let mut to_visit: Vec<usize> = entry_points.to_vec();
+    let mut named = Vec::new();
+    for (vector, name) in [(NMI_VECTOR_L, "nmi"), (RESET_VECTOR_L, "reset"), (IRQ_VECTOR_L, "irq")].iter() {
+        if let Some(target) = result.word(*vector) {
+            to_visit.push(target);
+            named.push((target, *name));
+        }
+    }
+    let (mut references, branches) = result.trace(to_visit);
+    references.extend(named.iter().map(|(address, _)| *address));
+    for address in references.iter().chain(branches.iter().map(|(_, target)| target)) {
+        if result.is_line_start(*address) {
+            result.labels.insert(*address, format!("L{:04X}", address));
+        }
+    }
+    // The vectors name their handler, the first one wins if they share it
+    for (address, name) in named.iter().rev() {
+        if result.is_line_start(*address) {
+            result.labels.insert(*address, name.to_string());
+        }
+    }
+    result.references = references;
+    result.branches = branches;
+    result.find_locals();
+    Ok(result)
+}
+
+impl Disassembly {
+    pub fn kind(&self, address: usize) -> Option<ByteKind> {
+        address.checked_sub(self.origin).and_then(|i| self.kinds.get(i)).copied()
+    }
+
+    /// The labels the disassembler generated, by address
+    pub fn labels(&self) -> &BTreeMap<usize, String> {
+        &self.labels
+    }
+
+    /// Follow the code from `to_visit`. Returns the addresses the code refers to other than
+    /// with a branch, including `to_visit`, and the branches with their target.
+    fn trace(&mut self, mut to_visit: Vec<usize>) -> (BTreeSet<usize>, Vec<(usize, usize)>) {
+        let mut references: BTreeSet<usize> = to_visit.iter().copied().collect();
+        let mut branches = Vec::new();
+        while let Some(mut pc) = to_visit.pop() {
+            while let Some((opcode, size)) = self.decode(pc) {
+                if self.kind(pc) == Some(ByteKind::Opcode) {
+                    break;
+                }
+                // Overlapping an instruction that was already found, keep the first one
+                if (pc..pc + size).any(|a| self.kind(a) != Some(ByteKind::Data)) {
+                    break;
+                }
+                let index = pc - self.origin;
+                self.kinds[index] = ByteKind::Opcode;
+                self.kinds[index + 1..index + size].iter_mut().for_each(|k| *k = ByteKind::Operand);
+                let name = self.variant.opcode_names()[opcode as usize];
+                let mode = self.variant.addressing_types()[opcode as usize];
+                let next = pc + size;
+                match mode {
+                    AddressingType::RELATIVE => {
+                        let target = self.branch_target(pc, size);
+                        branches.push((pc, target));
+                        to_visit.push(target);
+                    },
+                    AddressingType::ABSOLUTE | AddressingType::ABSOLUTE_X
+                        | AddressingType::ABSOLUTE_Y | AddressingType::INDIRECT
+                        | AddressingType::AIX => {
+                        let address = self.operand_word(pc);
+                        references.insert(address);
+                        if mode == AddressingType::ABSOLUTE && (name == "JMP" || name == "JSR") {
+                            to_visit.push(address);
+                        }
+                    },
+                    _ => {},
+                }
+                match name {
+                    "JMP" | "RTS" | "RTI" | "BRK" | "BRA" => break,
+                    _ => pc = next,
+                }
+            }
+        }
+        (references, branches)
+    }
+
+    /// A label only branched to from between the same two other labels can be local. Each
+    /// label that can't be local splits the scopes, so this goes on until nothing changes.
+    fn find_locals(&mut self) {
+        self.locals = self.labels.iter()
+            .filter(|(address, name)| ! self.references.contains(address)
+                && **name == format!("L{:04X}", address))
+            .map(|(address, _)| *address)
+            .collect();
+        loop {
+            let global = |address: usize, locals: &BTreeSet<usize>| self.labels.range(..=address)
+                .rev()
+                .map(|(a, _)| *a)
+                .find(|a| ! locals.contains(a));
+            let outside: Vec<usize> = self.branches.iter()
+                .filter(|(from, target)| self.locals.contains(target)
+                    && (global(*target, &self.locals).is_none()
+                        || global(*from, &self.locals) != global(*target, &self.locals)))
+                .map(|(_, target)| *target)
+                .collect();
+            if outside.is_empty() {
+                break;
+            }
+            for target in outside {
+                self.locals.remove(&target);
+            }
+        }
+    }
+
+    /// The opcode at `pc` and its size, if it's one that can be code and fits in the program
+    fn decode(&self, pc: usize) -> Option<(u8, usize)> {
+        let opcode = self.byte(pc)?;
+        let name = self.variant.opcode_names()[opcode as usize];
+        let mode = self.variant.addressing_types()[opcode as usize];
+        let size = self.variant.sizes()[opcode as usize];
+        if self.variant.is_illegal(opcode) || assembler::opcode(self.variant, name, mode) != Some(opcode)
+                || self.byte(pc + size - 1).is_none() {
+            return None;
+        }
+        Some((opcode, size))
+    }
+
+    fn byte(&self, address: usize) -> Option<u8> {
+        address.checked_sub(self.origin).and_then(|i| self.bytes.get(i)).copied()
+    }
+
+    fn word(&self, address: usize) -> Option<usize> {
+        Some(self.byte(address)? as usize | (self.byte(address + 1)? as usize) << 8)
+    }
+
+    fn operand_word(&self, pc: usize) -> usize {
+        self.word(pc + 1).unwrap_or(0)
+    }
+
+    fn branch_target(&self, pc: usize, size: usize) -> usize {
+        let offset = self.byte(pc + size - 1).unwrap_or(0) as i8;
+        ((pc + size) as u16).wrapping_add(offset as u16) as usize
+    }
+
+    /// True if a line of the source starts at `address`, which can then have a label
+    fn is_line_start(&self, address: usize) -> bool {
+        matches!(self.kind(address), Some(ByteKind::Opcode) | Some(ByteKind::Data))
+    }
+
+    /// Use the names of `symbols` for the labels at their address, and for the addresses
+    /// outside of the program the code refers to. `print()` defines the latter at the top.
+    pub fn set_symbols(&mut self, symbols: &Symbols) {
+        for (address, label) in self.labels.iter_mut() {
+            if let Some(name) = symbols.name(*address) {
+                *label = name.to_string();
+            }
+        }
+        self.symbols = symbols.clone();
+        self.find_locals();
+    }
+
+    /// The name in `symbols` of `address` if it's outside of the program
+    fn external(&self, address: usize) -> Option<&str> {
+        if self.kind(address).is_some() {
+            None
+        } else {
+            self.symbols.name(address)
+        }
+    }
+
+    /// A label or a symbol for `address` if it has one, its value with `digits` otherwise
+    fn name(&self, address: usize, digits: usize, syntax: &Syntax) -> String {
+        match self.labels.get(&address) {
+            Some(label) => match syntax.local {
+                Some(prefix) if self.locals.contains(&address) => format!("{}{}", prefix, label),
+                _ => label.clone(),
+            },
+            None => match self.external(address) {
+                Some(name) => name.to_string(),
+                None => format!("${:0width$X}", address, width = digits),
+            },
+        }
+    }
+
+    /// The addresses in the operand of the instruction at `pc`
+    fn operand_addresses(&self, pc: usize) -> Vec<usize> {
+        let opcode = self.byte(pc).unwrap_or(0) as usize;
+        let size = self.variant.sizes()[opcode];
+        let byte = self.byte(pc + 1).unwrap_or(0) as usize;
+        match self.variant.addressing_types()[opcode] {
+            AddressingType::ZP | AddressingType::ZP_X | AddressingType::ZP_Y
+                | AddressingType::INDIRECT_X | AddressingType::INDIRECT_Y
+                | AddressingType::ZPI => vec![byte],
+            AddressingType::ABSOLUTE | AddressingType::ABSOLUTE_X | AddressingType::ABSOLUTE_Y
+                | AddressingType::INDIRECT | AddressingType::AIX => vec![self.operand_word(pc)],
+            AddressingType::RELATIVE if size == 3 => vec![byte, self.branch_target(pc, size)],
+            AddressingType::RELATIVE => vec![self.branch_target(pc, size)],
+            _ => vec![],
+        }
+    }
+
+    /// The source of the program, for `assembler::assemble()`
+    pub fn source(&self) -> String {
+        self.print(Dialect::Sixty, Layout::Source)
+    }
+
+    /// The program written for `dialect`
+    pub fn print(&self, dialect: Dialect, layout: Layout) -> String {
+        let syntax = dialect.syntax();
+        let mut result = String::new();
+        let cpu = if self.variant.is_cmos() { syntax.cmos } else { syntax.nmos };
+        if let Some(cpu) = cpu {
+            line(&mut result, layout, None, "", cpu);
+        }
+        let end = self.origin + self.bytes.len();
+        // The symbols used outside of the program
+        let mut used: Vec<usize> = (self.origin..end)
+            .filter(|a| self.kind(*a) == Some(ByteKind::Opcode))
+            .flat_map(|a| self.operand_addresses(a))
+            .collect();
+        if end == 0x10000 {
+            let vectors = [NMI_VECTOR_L, RESET_VECTOR_L, IRQ_VECTOR_L];
+            used.extend(vectors.iter().filter_map(|v| self.word(*v)));
+        }
+        let equates: BTreeMap<usize, &str> = used.into_iter()
+            .filter_map(|a| self.external(a).map(|name| (a, name)))
+            .collect();
+        for (address, name) in equates {
+            let indent = if layout == Layout::Listing { " ".repeat(20) } else { String::new() };
+            let digits = if address < 0x100 { 2 } else { 4 };
+            writeln!(result, "{}{} {} ${:0width$X}", indent, name, syntax.equate, address,
+                width = digits).unwrap();
+        }
+        line(&mut result, layout, None, "", &format!("{} ${:04X}", syntax.org, self.origin));
+        let mut address = self.origin;
+        while address < end {
+            let label = match self.labels.get(&address) {
+                Some(_) if syntax.colon => format!("{}:", self.name(address, 4, &syntax)),
+                Some(_) => self.name(address, 4, &syntax),
+                None => String::new(),
+            };
+            let (text, size) = if self.kind(address) == Some(ByteKind::Opcode) {
+                self.instruction(address, &syntax)
+            } else {
+                self.data(address, end, &syntax)
+            };
+            let columns = if self.kind(address) == Some(ByteKind::Opcode) {
+                let bytes: Vec<String> = self.bytes[address - self.origin..address - self.origin + size]
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect();
+                let cycles = self.variant.timings()[self.bytes[address - self.origin] as usize];
+                (address, bytes.join(" "), cycles.to_string())
+            } else {
+                (address, String::new(), String::new())
+            };
+            line(&mut result, layout, Some(columns), &label, &text);
+            address += size;
+        }
+        result
+    }
+
+    fn instruction(&self, pc: usize, syntax: &Syntax) -> (String, usize) {
+        use Mnemonic::*;
+        let opcode = self.byte(pc).unwrap_or(0) as usize;
+        let mut name = self.variant.opcode_names()[opcode].to_string();
+        let mode = self.variant.addressing_types()[opcode];
+        let size = self.variant.sizes()[opcode];
+        if ! syntax.undocumented && matches!(self.variant.mnemonics()[opcode],
+                ALR | ANC | ARR | DCP | ISC | LAX | RLA | RRA | SAX | SBX | SLO | SRE) {
+            let bytes: Vec<String> = (pc..pc + size)
+                .map(|a| format!("${:02X}", self.byte(a).unwrap_or(0)))
+                .collect();
+            return (format!("{} {}", syntax.byte, bytes.join(&format!(",{}", syntax.space()))), size);
+        }
+        let byte = self.byte(pc + 1).unwrap_or(0);
+        let word = self.operand_word(pc);
+        let register = |r: &str| if syntax.upper_case { r.to_ascii_uppercase() } else { r.to_string() };
+        let (x, y) = (register(",x"), register(",y"));
+        // A label or a value for an absolute operand. Zero page addresses have to be forced
+        // to stay absolute.
+        let absolute = self.name(word, 4, syntax);
+        let absolute = if word < 0x100 && matches!(mode, AddressingType::ABSOLUTE
+                | AddressingType::ABSOLUTE_X | AddressingType::ABSOLUTE_Y) {
+            name.push_str(syntax.absolute.0);
+            format!("{}{}", syntax.absolute.1, absolute)
+        } else {
+            absolute
+        };
+        // The assemblers take a label defined further down for an absolute address, so a zero
+        // page operand only uses the labels above it
+        let zp = if byte as usize > pc && self.labels.contains_key(&(byte as usize)) {
+            format!("${:02X}", byte)
+        } else {
+            self.name(byte as usize, 2, syntax)
+        };
+        let operand = match mode {
+            AddressingType::NONE => String::new(),
+            AddressingType::REGISTER_A if syntax.accumulator => register("a"),
+            AddressingType::REGISTER_A => String::new(),
+            AddressingType::IMMEDIATE => format!("#${:02X}", byte),
+            AddressingType::ZP => zp,
+            AddressingType::ZP_X => format!("{}{}", zp, x),
+            AddressingType::ZP_Y => format!("{}{}", zp, y),
+            AddressingType::ABSOLUTE => absolute,
+            AddressingType::ABSOLUTE_X => format!("{}{}", absolute, x),
+            AddressingType::ABSOLUTE_Y => format!("{}{}", absolute, y),
+            AddressingType::INDIRECT_X => format!("({}{})", zp, x),
+            AddressingType::INDIRECT_Y => format!("({}){}", zp, y),
+            AddressingType::ZPI => format!("({})", zp),
+            AddressingType::INDIRECT => format!("({})", absolute),
+            AddressingType::AIX => format!("({}{})", absolute, x),
+            AddressingType::RELATIVE => {
+                let target = self.name(self.branch_target(pc, size), 4, syntax);
+                if size == 3 { format!("{},{}{}", zp, syntax.space(), target) } else { target }
+            },
+            _ => unreachable!("{:?} is a 65C816 addressing type", mode),
+        };
+        let name = if syntax.upper_case { name } else { name.to_ascii_lowercase() };
+        let text = if operand.is_empty() { name } else { format!("{} {}", name, operand) };
+        (text, size)
+    }
+
+    /// A line of data from `address`, up to the next label or instruction. The vectors
+    /// are printed as words.
+    fn data(&self, address: usize, end: usize, syntax: &Syntax) -> (String, usize) {
+        let separator = format!(",{}", syntax.space());
+        if address == NMI_VECTOR_L && end == 0x10000
+                && (address..end).all(|a| self.kind(a) == Some(ByteKind::Data))
+                && (address + 1..end).all(|a| ! self.labels.contains_key(&a)) {
+            let words: Vec<String> = [NMI_VECTOR_L, RESET_VECTOR_L, IRQ_VECTOR_L].iter()
+                .map(|v| self.name(self.word(*v).unwrap_or(0), 4, syntax))
+                .collect();
+            return (format!("{} {}", syntax.word, words.join(&separator)), 6);
+        }
+        let mut bytes = Vec::new();
+        let mut a = address;
+        while a < end && bytes.len() < BYTES_PER_LINE && self.kind(a) == Some(ByteKind::Data)
+                && (a == address || ! self.labels.contains_key(&a))
+                && (a == address || a != NMI_VECTOR_L) {
+            bytes.push(format!("${:02X}", self.byte(a).unwrap_or(0)));
+            a += 1;
+        }
+        (format!("{} {}", syntax.byte, bytes.join(&separator)), bytes.len())
+    }
+}
+
+impl Syntax {
+    /// After a comma: Merlin ends the operand at the first space
+    fn space(&self) -> &'static str {
+        if self.upper_case { "" } else { " " }
+    }
+}
+
+/// Add a line with `label` in the first column and `text` in the second one, after the
+/// address, bytes and cycles in `columns` for a listing. Labels too long for their column
+/// get a line of their own.
+fn line(result: &mut String, layout: Layout, columns: Option<(usize, String, String)>, label: &str,
+        text: &str) {
+    let columns = match (layout, columns) {
+        (Layout::Source, _) => String::new(),
+        (Layout::Listing, Some((address, bytes, cycles))) =>
+            format!("{:04X}  {:<8}  {:>2}  ", address, bytes, cycles),
+        (Layout::Listing, None) => " ".repeat(20),
+    };
+    if label.len() >= 8 {
+        writeln!(result, "{}{}", columns, label).unwrap();
+        writeln!(result, "{}        {}", " ".repeat(columns.len()), text).unwrap();
+    } else {
+        writeln!(result, "{}{:<8}{}", columns, label, text).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::constants::*;
+    use crate::decoder::decode;
+    use crate::image::Format;
+    use crate::memory::Memory;
+    use crate::test::memory;
+
+    #[test]
+    fn disassembly_reassembles() {
+        let source = "
+                    .org $F000
+            reset:  ldx #0
+            loop:   lda table,x
+                    beq done
+                    sta $0200,x
+                    jsr delay
+                    inx
+                    bne loop
+            done:   jmp (vector)
+            delay:  bbr0 $10, skip
+                    rmb0 $10
+                    lda a:$0012
+            skip:   rts
+            nmi:    asl a
+            irq:    rti
+            vector: .word reset
+            table:  .byte $48, $49, 0, $EA, $4C, $EB
+                    .res $FFFA - *
+                    .word nmi, reset, irq
+        ";
+        let program = assemble(source, CpuVariant::Cmos65C02).unwrap();
+        let bytes = program.to_vec();
+        let code = &bytes[0xf000..];
+        let disassembly = disassemble(code, 0xf000, CpuVariant::Cmos65C02, &[]).unwrap();
+        let reassembled = assemble(&disassembly.source(), CpuVariant::Cmos65C02).unwrap();
+        assert_eq!(reassembled.to_vec(), bytes);
+
+        let labels = disassembly.labels();
+        for name in ["reset", "nmi", "irq"].iter() {
+            assert_eq!(labels[&program.labels[*name]], *name);
+            assert_eq!(disassembly.kind(program.labels[*name]), Some(ByteKind::Opcode));
+        }
+        let table = program.labels["table"];
+        assert_eq!(labels[&table], format!("L{:04X}", table));
+        assert_eq!(labels[&program.labels["vector"]], format!("L{:04X}", program.labels["vector"]));
+        assert!((table..table + 6).all(|a| disassembly.kind(a) == Some(ByteKind::Data)));
+        assert_eq!(disassembly.kind(program.labels["skip"]), Some(ByteKind::Opcode));
+        assert_eq!(disassembly.kind(program.labels["loop"] + 1), Some(ByteKind::Operand));
+        assert!(disassembly.source().contains("lda a:$0012"));
+        assert!(disassembly.source().contains(".word nmi, reset, irq"));
+        // A zero page operand that points further down in the program
+        let bytes = [LDA_ZP, 0x05, JMP, 0x05, 0x00, RTS];
+        let disassembly = disassemble(&bytes, 0, CpuVariant::Cmos65C02, &[0]).unwrap();
+        assert!(disassembly.source().contains("lda $05"));
+        assert_eq!(assemble(&disassembly.source(), CpuVariant::Cmos65C02).unwrap().to_vec(), bytes);
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        // Not the 65C816, which the assembler doesn't support: decoder_returns_instructions
+        // checks its names and sizes against the tables
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Cmos65C02].iter() {
+            for opcode in 0..=0xffu8 {
+                let m = memory(&[(0x300, &[opcode, 0x34, 0x12])]);
+                let instruction = decode(&m, 0x300, *variant);
+                let source = format!(".org $0300\n{}", instruction);
+                let program = assemble(&source, *variant)
+                    .unwrap_or_else(|e| panic!("{:02X} {}: {}", opcode, instruction, e));
+                let bytes = &program.to_vec()[0x300..];
+                let expected = &m.dump(0x300..=0x300 + instruction.size - 1, Format::Raw).unwrap();
+                let name = instruction.mnemonic.name();
+                if assembler::opcode(*variant, name, instruction.mode) == Some(opcode) {
+                    assert_eq!(bytes, &expected[..], "{:02X} {}", opcode, instruction);
+                } else {
+                    // Undocumented duplicates assemble to the first opcode with the same
+                    // mnemonic and addressing mode, followed by the same operand bytes
+                    let canonical = assembler::opcode(*variant, name, instruction.mode);
+                    assert_eq!((Some(bytes[0]), &bytes[1..]), (canonical, &expected[1..]),
+                        "{:02X} {}", opcode, instruction);
+                    let m = Memory::new_with_vec(program.to_vec(), None);
+                    let again = decode(&m, 0x300, *variant);
+                    assert_eq!((again.mnemonic, again.mode, again.operand),
+                        (instruction.mnemonic, instruction.mode, instruction.operand),
+                        "{:02X} {}", opcode, instruction);
+                }
+            }
+        }
+
+        let text = |bytes: &[u8], variant: CpuVariant| {
+            memory(&[(0x300, bytes)]).disassemble(0x300, variant).0
+        };
+        assert_eq!(text(&[BNE, 0x7f], CpuVariant::Cmos65C02), "0300: D0 7F      BNE $0381");
+        assert_eq!(text(&[BNE, 0x80], CpuVariant::Cmos65C02), "0300: D0 80      BNE $0282");
+        assert_eq!(text(&[0x0f, 0x12, 0xfd], CpuVariant::Cmos65C02), "0300: 0F 12 FD   BBR0 $12,$0300");
+        assert_eq!(text(&[0x0f, 0x12, 0xfd], CpuVariant::Nmos6502), "0300: 0F 12 FD   SLO $FD12");
+        assert_eq!(text(&[LDA_IND_Y, 0x12], CpuVariant::Cmos65C02), "0300: B1 12      LDA ($12),Y");
+        assert_eq!(text(&[JMP_IND_X, 0x34, 0x12], CpuVariant::Cmos65C02), "0300: 7C 34 12   JMP ($1234,X)");
+        assert_eq!(text(&[0x0a], CpuVariant::Cmos65C02), "0300: 0A         ASL A");
+        assert_eq!(text(&[0xbf, 0x56, 0x34, 0x12], CpuVariant::W65C816), "0300: BF 56 34 12 LDA $123456,X");
+        assert_eq!(text(&[0xb3, 0x05], CpuVariant::W65C816), "0300: B3 05      LDA ($05,S),Y");
+        assert_eq!(text(&[0xb7, 0x05], CpuVariant::W65C816), "0300: B7 05      LDA [$05],Y");
+        assert_eq!(text(&[0x54, 0x01, 0x02], CpuVariant::W65C816), "0300: 54 01 02   MVN $02,$01");
+        assert_eq!(text(&[0x82, 0x00, 0x80], CpuVariant::W65C816), "0300: 82 00 80   BRL $8303");
+    }
+
+    #[test]
+    fn disassembly_dialects() {
+        let program = assemble("
+                    .org $0300
+                    ldx #0
+            loop:   dex
+                    bne loop
+                    lda a:$0012
+                    asl a
+                    jmp $0300
+        ", CpuVariant::Nmos6502).unwrap();
+        let disassembly = disassemble(&program.to_vec()[0x300..], 0x300, CpuVariant::Nmos6502, &[0x300]).unwrap();
+        assert_eq!(disassembly.print(Dialect::Ca65, Layout::Source), [
+            "        .setcpu \"6502X\"",
+            "        .org $0300",
+            "L0300:  ldx #$00",
+            "@L0302: dex",
+            "        bne @L0302",
+            "        lda a:$0012",
+            "        asl a",
+            "        jmp L0300",
+            "",
+        ].join("\n"));
+        assert_eq!(disassembly.print(Dialect::Merlin, Layout::Source), [
+            "        ORG $0300",
+            "L0300   LDX #$00",
+            ":L0302  DEX",
+            "        BNE :L0302",
+            "        LDA: $0012",
+            "        ASL",
+            "        JMP L0300",
+            "",
+        ].join("\n"));
+        let listing = disassembly.print(Dialect::Acme, Layout::Listing);
+        assert_eq!(listing.lines().nth(3), Some("0302  CA         2  @L0302  dex"));
+        assert_eq!(listing.lines().nth(5), Some("0305  AD 12 00   4          lda+2 $0012"));
+        assert!(disassembly.print(Dialect::Tass64, Layout::Source).contains("        bne _L0302\n"));
+
+        // Merlin doesn't know the undocumented opcodes
+        let program = assemble(".org $0300\nlax $10\nrts", CpuVariant::Nmos6502).unwrap();
+        let disassembly = disassemble(&program.to_vec()[0x300..], 0x300, CpuVariant::Nmos6502, &[0x300])
+            .unwrap();
+        assert_eq!(disassembly.print(Dialect::Merlin, Layout::Source),
+            "        ORG $0300\nL0300   DFB $A7,$10\n        RTS\n");
+        assert!(disassembly.print(Dialect::Ca65, Layout::Source).contains("L0300:  lax $10\n"));
+        assert!(matches!(disassemble(&[0], 0x300, CpuVariant::W65C816, &[]),
+            Err(SixtyError::Unsupported { .. })));
+        assert!(matches!(disassemble(&[0; 2], 0xffff, CpuVariant::Nmos6502, &[]),
+            Err(SixtyError::AddressOutOfRange(0x10000))));
+    }
+}
@@ -0,0 +1,65 @@
+//! The errors of the crate. Loading files and running the CPU return a `SixtyError` instead
+//! of panicking on bad input. The few functions that panic on misuse, like
+//! `Banks::map_read()` with a mapping past the end of its bank, say so under `# Panics`.
+
+use crate::cpu::CpuVariant;
+use crate::image::Format;
+use crate::symbols::SymbolFormat;
+use std::fmt;
+
+/// Everything that can go wrong while loading or running a program
+#[derive(Debug)]
+pub enum SixtyError {
+    /// A file couldn't be opened or read
+    Io { file_name: String, error: std::io::Error },
+    /// The opcode at `pc` can't be executed: JAM, which halts the NMOS 6502, or one of its
+    /// unstable undocumented opcodes. The CPU is left on that instruction.
+    IllegalOpcode { pc: usize, opcode: u8 },
+    /// The CPU accessed an address past the end of the memory buffer. It's reported at the
+    /// end of the instruction, whose other effects on the registers and memory are kept.
+    /// Also the first address past $FFFF of a program given to the disassembler.
+    AddressOutOfRange(usize),
+    /// The CPU wrote `value` to a ROM region added with `RomPolicy::Error`. The ROM is left
+    /// unchanged.
+    RomWrite { address: usize, value: u8 },
+    /// A program image isn't valid in its format, or doesn't fit in memory
+    BadImage { format: Format, message: String },
+    /// A symbol file isn't valid in its format
+    BadSymbols { format: SymbolFormat, message: String },
+    /// The assembler couldn't assemble `line`, counted from 1
+    Assembly { line: usize, message: String },
+    /// `feature` isn't implemented for `variant`
+    Unsupported { variant: CpuVariant, feature: &'static str },
+}
+
+impl fmt::Display for SixtyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SixtyError::Io { file_name, error } =>
+                write!(f, "Couldn't read {}: {}", file_name, error),
+            SixtyError::IllegalOpcode { pc, opcode } =>
+                write!(f, "Illegal opcode {:02X} at PC={:04X}", opcode, pc),
+            SixtyError::AddressOutOfRange(address) =>
+                write!(f, "Address {:04X} is outside of the memory", address),
+            SixtyError::RomWrite { address, value } =>
+                write!(f, "Write of {:02X} to the ROM at {:04X}", value, address),
+            SixtyError::BadImage { format, message } =>
+                write!(f, "Invalid {}: {}", format, message),
+            SixtyError::BadSymbols { format, message } =>
+                write!(f, "Invalid {}: {}", format, message),
+            SixtyError::Assembly { line, message } =>
+                write!(f, "Line {}: {}", line, message),
+            SixtyError::Unsupported { variant, feature } =>
+                write!(f, "{} isn't supported on the {:?}", feature, variant),
+        }
+    }
+}
+
+impl std::error::Error for SixtyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SixtyError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
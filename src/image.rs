@@ -0,0 +1,371 @@
+//! Program images in the formats that assemblers and emulators exchange: Intel HEX, Motorola
+//! S-records, Commodore PRG files, Apple DOS 3.3 binaries and raw memory dumps. `Image`
+//! parses them into segments that `Memory::load_image()` copies at their address, and
+//! `dump()` goes the other way.
+
+use crate::error::SixtyError;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The bytes as they are, loaded at address 0
+    Raw,
+    /// Text records like `:10080000A9...`, with 32 bit addresses
+    IntelHex,
+    /// Motorola text records like `S1130800A9...`
+    SRecord,
+    /// A C64 program: the load address, then the bytes
+    Prg,
+    /// An Apple DOS 3.3 B file: the load address and the length, then the bytes
+    DosBinary,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Raw => "raw image",
+            Format::IntelHex => "Intel HEX",
+            Format::SRecord => "S-record",
+            Format::Prg => "PRG",
+            Format::DosBinary => "DOS binary",
+        })
+    }
+}
+
+/// Images can't go past the 24 bit address space of the 65C816
+const MAX_ADDRESS: usize = 0xff_ffff;
+
+/// Bytes per record when dumping the text formats
+const RECORD_SIZE: usize = 16;
+
+/// A parsed program
+#[derive(Debug, PartialEq, Eq)]
+pub struct Image {
+    pub format: Format,
+    /// The bytes to load and their address, in the order of the file
+    pub segments: Vec<(usize, Vec<u8>)>,
+    /// The entry point, for the formats that record one
+    pub start: Option<usize>,
+}
+
+impl Image {
+    /// Parse `data` as `format`, or as the format `detect()` finds if `format` is `None`
+    pub fn parse(data: &[u8], format: Option<Format>) -> Result<Image, SixtyError> {
+        let format = format.unwrap_or_else(|| detect(data));
+        let error = |message: String| SixtyError::BadImage { format, message };
+        let mut image = Image { format, segments: Vec::new(), start: None };
+        match format {
+            Format::Raw => image.segments.push((0, data.to_vec())),
+            Format::IntelHex => image.parse_records(data, parse_intel_hex_record).map_err(error)?,
+            Format::SRecord => image.parse_records(data, parse_s_record).map_err(error)?,
+            Format::Prg => {
+                if data.len() < 2 {
+                    return Err(error("missing the load address".to_string()));
+                }
+                image.segments.push((word(data, 0), data[2..].to_vec()));
+            },
+            Format::DosBinary => {
+                if data.len() < 4 {
+                    return Err(error("missing the address and length header".to_string()));
+                }
+                let length = word(data, 2);
+                if data.len() < 4 + length {
+                    return Err(error(format!("{} bytes announced but only {} present",
+                        length, data.len() - 4)));
+                }
+                image.segments.push((word(data, 0), data[4..4 + length].to_vec()));
+            },
+        }
+        if let Some((address, bytes)) = image.segments.iter()
+                .find(|(address, bytes)| address + bytes.len() > MAX_ADDRESS + 1) {
+            return Err(error(format!("{} bytes at {:X} go past the end of the address space",
+                bytes.len(), address)));
+        }
+        Ok(image)
+    }
+
+    fn parse_records(&mut self, data: &[u8],
+            parse: fn(&str, &mut Image, &mut usize) -> Result<bool, String>)
+            -> Result<(), String> {
+        let text = std::str::from_utf8(data).map_err(|_| "not a text file".to_string())?;
+        // The upper bits of the addresses, for the Intel HEX extended address records
+        let mut base = 0;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let done = parse(line, self, &mut base)
+                .map_err(|message| format!("line {}: {}", number + 1, message))?;
+            if done {
+                return Ok(());
+            }
+        }
+        Err("missing the end of file record".to_string())
+    }
+}
+
+/// Guess the format of `data`: text starting with `:` or `S` is Intel HEX or S-records, a
+/// DOS 3.3 header whose length matches the file (give or take the padding to the next
+/// sector) is a DOS binary. A PRG needs a plausible load address, past the zero page and
+/// the stack (the C64 BASIC start $0801 is one), and has to fit in 64K after it. Anything
+/// else is a raw image.
+pub fn detect(data: &[u8]) -> Format {
+    let text = data.iter().position(|b| ! b.is_ascii_whitespace()).map(|i| &data[i..]);
+    match text {
+        Some([b':', ..]) => return Format::IntelHex,
+        Some([b'S', b'0'..=b'9', ..]) => return Format::SRecord,
+        _ => {},
+    }
+    if data.len() >= 4 {
+        let end = 4 + word(data, 2);
+        if end <= data.len() && data.len() - end < 0x100 && word(data, 0) + end - 4 <= 0x10000 {
+            return Format::DosBinary;
+        }
+    }
+    if data.len() >= 2 && word(data, 0) >= 0x200 && word(data, 0) + data.len() - 2 <= 0x10000 {
+        Format::Prg
+    } else {
+        Format::Raw
+    }
+}
+
+/// `bytes`, found at `address`, in `format`. The text formats end with a start address
+/// record pointing at `address`. PRG and DOS binaries only have 16 bit addresses, so the
+/// bytes have to fit in the first 64K, and a DOS binary holds at most $FFFF bytes.
+pub fn dump(address: usize, bytes: &[u8], format: Format) -> Result<Vec<u8>, SixtyError> {
+    let error = |message: String| SixtyError::BadImage { format, message };
+    let end = address + bytes.len();
+    Ok(match format {
+        Format::Raw => bytes.to_vec(),
+        Format::IntelHex => dump_intel_hex(address, bytes).into_bytes(),
+        Format::SRecord => dump_s_records(address, bytes).into_bytes(),
+        Format::Prg | Format::DosBinary if end > 0x10000 =>
+            return Err(error(format!("{} bytes at {:X} go past the first 64K", bytes.len(), address))),
+        Format::Prg => {
+            let mut result = vec![address as u8, (address >> 8) as u8];
+            result.extend_from_slice(bytes);
+            result
+        },
+        Format::DosBinary if bytes.len() > 0xffff =>
+            return Err(error(format!("{} bytes don't fit in its 16 bit length", bytes.len()))),
+        Format::DosBinary => {
+            let length = bytes.len();
+            let mut result = vec![address as u8, (address >> 8) as u8, length as u8,
+                (length >> 8) as u8];
+            result.extend_from_slice(bytes);
+            result
+        },
+    })
+}
+
+fn word(data: &[u8], index: usize) -> usize {
+    data[index] as usize | (data[index + 1] as usize) << 8
+}
+
+fn hex_bytes(digits: &str) -> Result<Vec<u8>, String> {
+    if ! digits.len().is_multiple_of(2) || ! digits.is_ascii() {
+        return Err("invalid hex digits".to_string());
+    }
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16)
+            .map_err(|_| "invalid hex digits".to_string()))
+        .collect()
+}
+
+/// Big endian, like the addresses of both text formats
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |result, b| result << 8 | *b as usize)
+}
+
+/// A colon, then the length, address, type, data and a checksum that makes the sum of the
+/// bytes 0. Returns true on the end of file record.
+fn parse_intel_hex_record(line: &str, image: &mut Image, base: &mut usize)
+        -> Result<bool, String> {
+    let digits = line.strip_prefix(':').ok_or_else(|| "missing the colon".to_string())?;
+    let bytes = hex_bytes(digits)?;
+    if bytes.len() < 5 || bytes.len() != 5 + bytes[0] as usize {
+        return Err("wrong record length".to_string());
+    }
+    if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+        return Err("bad checksum".to_string());
+    }
+    let address = big_endian(&bytes[1..3]);
+    let data = &bytes[4..bytes.len() - 1];
+    match bytes[3] {
+        0x00 => image.segments.push((*base + address, data.to_vec())),
+        0x01 => return Ok(true),
+        // Extended segment address, in paragraphs
+        0x02 if data.len() == 2 => *base = big_endian(data) << 4,
+        // Start segment address, CS:IP
+        0x03 if data.len() == 4 => image.start = Some((big_endian(&data[..2]) << 4) + big_endian(&data[2..])),
+        0x04 if data.len() == 2 => *base = big_endian(data) << 16,
+        0x05 if data.len() == 4 => image.start = Some(big_endian(data)),
+        t => return Err(format!("invalid record of type {:02X}", t)),
+    }
+    Ok(false)
+}
+
+/// `S`, the type digit, then the count, address, data and a checksum that makes the sum of
+/// the bytes $FF. S0 (header) and S5/S6 (record count) are skipped, S7 to S9 end the file.
+fn parse_s_record(line: &str, image: &mut Image, _base: &mut usize) -> Result<bool, String> {
+    let kind = match line.as_bytes() {
+        [b'S', kind @ b'0'..=b'9', ..] => kind - b'0',
+        _ => return Err("not an S-record".to_string()),
+    };
+    let bytes = hex_bytes(&line[2..])?;
+    let address_size = match kind {
+        0 | 1 | 5 | 9 => 2,
+        2 | 6 | 8 => 3,
+        3 | 7 => 4,
+        _ => return Err(format!("invalid record type S{}", kind)),
+    };
+    if bytes.len() < address_size + 2 || bytes.len() != 1 + bytes[0] as usize {
+        return Err("wrong record length".to_string());
+    }
+    if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xff {
+        return Err("bad checksum".to_string());
+    }
+    let address = big_endian(&bytes[1..1 + address_size]);
+    match kind {
+        1..=3 => image.segments.push((address, bytes[1 + address_size..bytes.len() - 1].to_vec())),
+        7..=9 => {
+            image.start = Some(address);
+            return Ok(true);
+        },
+        _ => {},
+    }
+    Ok(false)
+}
+
+fn dump_intel_hex(address: usize, bytes: &[u8]) -> String {
+    fn record(kind: u8, address: usize, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8, (address >> 8) as u8, address as u8, kind];
+        bytes.extend_from_slice(data);
+        let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        bytes.push(sum.wrapping_neg());
+        format!(":{}\n", hex_string(&bytes))
+    }
+    let mut result = String::new();
+    let mut base = 0;
+    for (i, chunk) in bytes.chunks(RECORD_SIZE).enumerate() {
+        let a = address + i * RECORD_SIZE;
+        // A record can't cross a 64K boundary, the chunks are aligned on one if `address` is
+        let upper = a >> 16;
+        if upper != base {
+            base = upper;
+            result.push_str(&record(0x04, 0, &[(upper >> 8) as u8, upper as u8]));
+        }
+        if (a & 0xffff) + chunk.len() > 0x10000 {
+            let split = 0x10000 - (a & 0xffff);
+            result.push_str(&record(0x00, a, &chunk[..split]));
+            base = upper + 1;
+            result.push_str(&record(0x04, 0, &[(base >> 8) as u8, base as u8]));
+            result.push_str(&record(0x00, 0, &chunk[split..]));
+        } else {
+            result.push_str(&record(0x00, a, chunk));
+        }
+    }
+    let start = [(address >> 24) as u8, (address >> 16) as u8, (address >> 8) as u8, address as u8];
+    result.push_str(&record(0x05, 0, &start));
+    result.push_str(&record(0x01, 0, &[]));
+    result
+}
+
+fn dump_s_records(address: usize, bytes: &[u8]) -> String {
+    fn record(kind: u8, address: usize, address_size: usize, data: &[u8]) -> String {
+        let mut bytes = vec![(address_size + data.len() + 1) as u8];
+        bytes.extend((0..address_size).rev().map(|i| (address >> (8 * i)) as u8));
+        bytes.extend_from_slice(data);
+        let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        bytes.push(! sum);
+        format!("S{}{}\n", kind, hex_string(&bytes))
+    }
+    // S1/S9 with 16 bit addresses, S2/S8 with 24 bit ones
+    let end = address + bytes.len();
+    let (data_kind, end_kind, address_size) = if end > 0x10000 { (2, 8, 3) } else { (1, 9, 2) };
+    let mut result = record(0, 0, 2, &[]);
+    for (i, chunk) in bytes.chunks(RECORD_SIZE).enumerate() {
+        result.push_str(&record(data_kind, address + i * RECORD_SIZE, address_size, chunk));
+    }
+    result.push_str(&record(end_kind, address, address_size, &[]));
+    result
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    #[test]
+    fn image_formats_round_trip() {
+        let bytes: Vec<u8> = (0..40).map(|i| (i * 7) as u8).collect();
+        let formats = [Format::IntelHex, Format::SRecord, Format::Prg, Format::DosBinary];
+        for (address, formats) in [(0x0801, &formats[..]), (0x1fff8, &formats[..2])].iter() {
+            let mut buffer = vec![0; address + bytes.len()];
+            buffer[*address..].copy_from_slice(&bytes);
+            let memory = Memory::new_with_vec(buffer, None);
+            for format in formats.iter() {
+                let data = memory.dump(*address..=address + bytes.len() - 1, *format).unwrap();
+                assert_eq!(detect(&data), *format);
+                let mut loaded = Memory::new_with_vec(vec![], None);
+                let image = Image::parse(&data, None).unwrap();
+                loaded.load_image(&image);
+                assert_eq!(loaded.dump(*address..=address + bytes.len() - 1, Format::Raw).unwrap(),
+                    bytes);
+            }
+        }
+        // Past the 16 bit addresses of PRG and DOS binaries, or the 16 bit length of the latter
+        let memory = Memory::new_with_vec(vec![0; 0x20000], None);
+        assert!(memory.dump(0xfff0..=0x1000f, Format::Prg).is_err());
+        assert!(memory.dump(0x10000..=0x10001, Format::DosBinary).is_err());
+        assert!(memory.dump(0..=0xffff, Format::DosBinary).is_err());
+        assert_eq!(memory.dump(0..=0xfffe, Format::DosBinary).unwrap().len(), 0xffff + 4);
+        assert_eq!(memory.dump(0..=0xffff, Format::Prg).unwrap().len(), 0x10000 + 2);
+        // Too large for a PRG at its load address
+        assert_eq!(detect(&[0xff, 0xff, 1, 2, 3]), Format::Raw);
+        // A load address in the zero page or the stack is more likely the start of a raw image
+        assert_eq!(detect(&[0x00, 0x01, 1, 2, 3]), Format::Raw);
+        assert_eq!(detect(&std::fs::read("6502_functional_test.bin").unwrap()), Format::Raw);
+    }
+
+    #[test]
+    fn image_formats_parse() {
+        let s_records = "S00F000068656C6C6F202020202000003C\n\
+            S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026\n\
+            S11F001C4BFFFFE5398000007D83637880010014382100107C0803A64E800020E9\n\
+            S111003848656C6C6F20776F726C642E0A0042\n\
+            S5030003F9\n\
+            S9030000FC\n";
+        let image = Image::parse(s_records.as_bytes(), None).unwrap();
+        assert_eq!(image.format, Format::SRecord);
+        assert_eq!(image.segments.iter().map(|(a, b)| (*a, b.len())).collect::<Vec<_>>(),
+            vec![(0x00, 0x1c), (0x1c, 0x1c), (0x38, 0x0e)]);
+        assert_eq!(image.start, Some(0));
+
+        let intel_hex = ":020000040001F9\n:0300300002337A1E\n:0400000500010800EE\n:00000001FF\n";
+        let image = Image::parse(intel_hex.as_bytes(), None).unwrap();
+        assert_eq!(image.segments, vec![(0x10030, vec![0x02, 0x33, 0x7a])]);
+        assert_eq!(image.start, Some(0x10800));
+
+        let dos = [0x00, 0x03, 0x02, 0x00, 0xa9, 0x00];
+        let image = Image::parse(&dos, None).unwrap();
+        assert_eq!((image.format, image.segments), (Format::DosBinary, vec![(0x300, vec![0xa9, 0x00])]));
+    }
+
+    #[test]
+    fn image_errors() {
+        let error = Image::parse(b":0300300002337A1E\n:0300300002337A1F\n", None).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid Intel HEX: line 2: bad checksum");
+        let error = Image::parse(b":0300300002337A1E\n", None).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid Intel HEX: missing the end of file record");
+        let error = Image::parse(b"S1137AF00A0A\n", None).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid S-record: line 1: wrong record length");
+        let error = Image::parse(&[0x00, 0x03, 0x10, 0x00, 0xa9], Some(Format::DosBinary)).unwrap_err();
+        assert!(matches!(error, SixtyError::BadImage { format: Format::DosBinary, .. }));
+    }
+}
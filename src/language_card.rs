@@ -0,0 +1,147 @@
+//! The Apple ][ 16K language card, the reference `BankSwitch`. It puts 16K of RAM over the ROM
+//! at $D000-$FFFF: two 4K banks share $D000-$DFFF and one 8K bank covers $E000-$FFFF. The
+//! soft switches at $C080-$C08F select, from the low bits of the address:
+//!
+//! - bit 3: bank 1 of $D000-$DFFF when set, bank 2 otherwise
+//! - bits 0 and 1: %00 reads RAM, %01 and %10 read ROM, %11 reads RAM
+//! - bit 0: writes go to the RAM when set, but only after two reads in a row of an odd
+//!   address. An even address write protects the RAM again.
+//!
+//! The ROM is whatever the buffer of the `Memory` holds at $D000-$FFFF.
+
+use crate::bank::{BankId, BankSwitch, Banks, Mapping};
+use crate::memory::Memory;
+use std::cell::Cell;
+
+pub struct LanguageCard {
+    bank: BankId,
+    bank_1: Cell<bool>,
+    read_ram: Cell<bool>,
+    write_ram: Cell<bool>,
+    /// Set by the first read of an odd address, the second one enables writes
+    pre_write: Cell<bool>,
+}
+
+/// Where each part lives in the bank
+const BANK_1: usize = 0x00;
+const BANK_2: usize = 0x10;
+const HIGH_RAM: usize = 0x20;
+
+impl LanguageCard {
+    /// Add the RAM of the card to `memory` and its soft switches at $C080-$C08F. The card
+    /// powers up reading ROM and writing bank 2, like the original card. The returned bank
+    /// holds bank 1, bank 2 and then $E000-$FFFF.
+    pub fn install(memory: &mut Memory) -> BankId {
+        let bank = memory.add_bank(vec![0; 0x4000]);
+        let card = LanguageCard {
+            bank,
+            bank_1: Cell::new(false),
+            read_ram: Cell::new(false),
+            write_ram: Cell::new(true),
+            pre_write: Cell::new(false),
+        };
+        card.map(memory.banks());
+        memory.add_switch(0xc080..=0xc08f, Box::new(card));
+        bank
+    }
+
+    fn switch(&self, address: usize, read: bool, banks: &Banks) {
+        self.bank_1.set(address & 8 != 0);
+        self.read_ram.set(matches!(address & 3, 0 | 3));
+        if address & 1 == 0 {
+            self.pre_write.set(false);
+            self.write_ram.set(false);
+        } else if read {
+            if self.pre_write.get() {
+                self.write_ram.set(true);
+            }
+            self.pre_write.set(true);
+        } else {
+            self.pre_write.set(false);
+        }
+        self.map(banks);
+    }
+
+    fn map(&self, banks: &Banks) {
+        let d000 = Mapping::Bank {
+            bank: self.bank,
+            page: if self.bank_1.get() { BANK_1 } else { BANK_2 },
+        };
+        let e000 = Mapping::Bank { bank: self.bank, page: HIGH_RAM };
+        if self.read_ram.get() {
+            banks.map_read(0xd0..0xe0, d000);
+            banks.map_read(0xe0..0x100, e000);
+        } else {
+            banks.map_read(0xd0..0x100, Mapping::Buffer);
+        }
+        if self.write_ram.get() {
+            banks.map_write(0xd0..0xe0, d000);
+            banks.map_write(0xe0..0x100, e000);
+        } else {
+            // Writes hit the ROM
+            banks.map_write(0xd0..0x100, Mapping::Nothing);
+        }
+    }
+}
+
+impl BankSwitch for LanguageCard {
+    fn read(&self, address: usize, banks: &Banks) -> u8 {
+        self.switch(address, true, banks);
+        0
+    }
+
+    fn write(&mut self, address: usize, _value: u8, banks: &Banks) {
+        self.switch(address, false, banks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::constants::*;
+    use crate::cpu::{Cpu, CpuVariant};
+    use crate::decoder::decode;
+    use crate::test::{memory, EndListener};
+
+    #[test]
+    fn language_card_switches_banks() {
+        let program = vec!(
+            // Read and write RAM, bank 2
+            LDA_ABS, 0x83, 0xc0, LDA_ABS, 0x83, 0xc0, LDA_IMM, 0x22, STA_ABS, 0x00, 0xd0,
+            // Bank 1
+            LDA_ABS, 0x8b, 0xc0, LDA_ABS, 0x8b, 0xc0, LDA_IMM, 0x33, STA_ABS, 0x00, 0xd0,
+            LDA_ABS, 0x00, 0xd0, STA_ZP, 0x80,
+            // Read bank 2, write protected
+            LDA_ABS, 0x80, 0xc0, LDA_ABS, 0x00, 0xd0, STA_ZP, 0x81, STA_ABS, 0x00, 0xe0,
+            // A single read of an odd address doesn't enable writes
+            LDA_ABS, 0x81, 0xc0, LDA_IMM, 0x44, STA_ABS, 0x01, 0xd0,
+            // Read ROM
+            LDA_ABS, 0x82, 0xc0, LDA_ABS, 0x00, 0xd0, STA_ZP, 0x82);
+        let end = program.len();
+        let mut m = memory(&[(0, &program), (0xd000, &[0x11])]);
+        let bank = LanguageCard::install(&mut m);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, Some(Box::new(EndListener { end })));
+        cpu.run(0).unwrap();
+        assert_eq!(cpu.memory.read(0x80), 0x33);
+        assert_eq!(cpu.memory.read(0x81), 0x22);
+        assert_eq!(cpu.memory.read(0x82), 0x11);
+        let ram = cpu.memory.banks().bank(bank);
+        assert_eq!((ram[0], ram[1], ram[0x1000], ram[0x2000]), (0x33, 0, 0x22, 0));
+        assert_eq!((cpu.memory.read(0xd001), cpu.memory.read(0xe000)), (0, 0));
+    }
+
+    #[test]
+    fn inspecting_memory_leaves_the_soft_switches_alone() {
+        let mut m = memory(&[(0xd000, &[0x11])]);
+        LanguageCard::install(&mut m);
+        m.disassemble(0xc082, CpuVariant::Cmos65C02);
+        decode(&m, 0xc083, CpuVariant::Cmos65C02);
+        let cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+        let _ = cpu.to_string();
+        assert_eq!(cpu.memory.read(0xd000), 0x11);
+        // A real read switches to the RAM
+        cpu.memory.read(0xc083);
+        assert_eq!(cpu.memory.peek(0xd000), 0);
+    }
+}
@@ -0,0 +1,37 @@
+//! A 6502 emulator. `cpu::Cpu` runs programs on a `bus::Bus`, by default the flat RAM of
+//! `memory::Memory`, as one of the `cpu::CpuVariant`s: the NMOS 6502, the 65C02, the 65C816
+//! and the NMOS cores of the NES, the Atari 2600 and the Commodore 64.
+//!
+//! Around it:
+//!
+//! - `assembler` and `disassembler` turn source into bytes and back, `decoder` turns an
+//!   instruction into data for debuggers
+//! - `image` reads and writes the file formats of programs, `symbols` and `debug_info` put
+//!   names and source lines on their addresses
+//! - `bank` and `language_card` do bank switching
+//!
+//! The `sixty-asm` crate of the workspace assembles inline source at compile time with its
+//! `sixty_asm!{}` macro, which is handy to write test programs.
+
+// Lets `sixty_asm!` expand to `::sixty::...` paths in the tests of this crate too
+extern crate self as sixty;
+
+mod alu;
+pub mod assembler;
+pub mod bank;
+pub mod bus;
+pub mod constants;
+pub mod cpu;
+mod cycle;
+pub mod debug_info;
+pub mod decoder;
+pub mod disassembler;
+pub mod error;
+pub mod image;
+pub mod language_card;
+pub mod memory;
+pub mod port;
+pub mod symbols;
+#[cfg(test)]
+mod test;
+mod w65c816;
@@ -1,19 +1,11 @@
-use crate::constants::{LDA_IMM, RTS};
-use crate::memory::Memory;
-use crate::cpu::Cpu;
-
-mod constants;
-mod cpu;
-mod memory;
-mod test;
-
-// use crate::cpu::{Cpu, CpuListener};
-// use crate::memory::Memory;
-
-fn main() {
-    let vec = vec!(LDA_IMM, 0x42, RTS);
-    let m = Memory::new_with_vec(vec, None);
-    Cpu::new(m, None).run(0x0);
+use sixty::assembler::assemble;
+use sixty::error::SixtyError;
+use sixty::memory::Memory;
+use sixty::cpu::{Cpu, CpuVariant};
+
+fn main() -> Result<(), SixtyError> {
+    let program = assemble("lda #$42\nrts", CpuVariant::Cmos65C02)?;
+    let m = Memory::new_with_vec(program.to_vec(), None);
+    Cpu::new(m, CpuVariant::Cmos65C02, None).run(0x0)?;
+    Ok(())
 }
-
-
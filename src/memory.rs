@@ -1,30 +1,129 @@
-use crate::constants::ADDRESSING_TYPES;
+//! `Memory`, the default `Bus`: a flat buffer of RAM, with ROM, devices and bank switching
+//! on top of it.
+//!
+//! - `new_with_file()` loads a raw image at address 0. `load_file()` also reads the formats
+//!   of `image`, detected from the contents unless one is given, and `dump()` writes a
+//!   range back in any of them.
+//! - `add_device()` maps a `Device` on an address range, whose accesses go to the device
+//!   instead of the RAM. `add_switch()` maps a `BankSwitch` the same way, to map the banks
+//!   of `add_bank()` over the first 64K.
+//! - `add_rom()` makes a range read only. Its `RomPolicy` drops the writes like the real
+//!   hardware, reports them to a `RomListener` or stops the CPU with `SixtyError::RomWrite`
+//!   to catch wild stores in tests.
+//! - A `MemoryListener` sees every bus access with its `AccessKind` and its cycle, for
+//!   traces, watchpoints or to keep other hardware in sync. `Cpu::new()` turns on
+//!   `cycle_accurate` for it, since only that mode reports the accesses one by one.
+
+use crate::bank::{BankId, BankSwitch, Banks};
+use crate::bus::Bus;
+use crate::cpu::CpuVariant;
+use crate::decoder::decode;
+use crate::error::SixtyError;
+use crate::image::{self, Format, Image};
+use crate::symbols::Symbols;
 use std::fs::File;
 use std::io::Read;
-use std::cmp::max;
-
-const STACK_ADDRESS: usize = 0x100;
+use std::cell::Cell;
+use std::ops::RangeInclusive;
 
 pub struct Memory {
     buffer: Vec<u8>,
-    _listener: Option<Box<dyn MemoryListener>>,
-    pub(crate) stack_pointer: usize,
+    listener: Option<Box<dyn MemoryListener>>,
+    /// The first fault since the last `take_fault()`
+    fault: Cell<Option<SixtyError>>,
+    /// Memory mapped devices and bank switches, in the order they were added
+    devices: Vec<(RangeInclusive<usize>, Attached)>,
+    banks: Banks,
+    /// Read only ranges of `buffer`, the first one containing an address applies
+    roms: Vec<(RangeInclusive<usize>, RomPolicy)>,
+}
+
+enum Attached {
+    Device(Box<dyn Device>),
+    Switch(Box<dyn BankSwitch>),
+}
+
+/// What the CPU used a bus cycle for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    OpcodeFetch,
+    OperandFetch,
+    Read,
+    Write,
+    StackPush,
+    StackPull,
+    /// A read whose value is thrown away, e.g. while the CPU fixes the high byte of an
+    /// indexed address. It still reaches the bus, and memory mapped hardware reacts to it.
+    DummyRead,
+    /// The NMOS 6502 writes the unmodified value back before the result in read-modify-write
+    /// instructions
+    DummyWrite,
+}
+
+impl AccessKind {
+    pub fn is_write(&self) -> bool {
+        matches!(self, AccessKind::Write | AccessKind::StackPush | AccessKind::DummyWrite)
+    }
+}
+
+/// One bus cycle issued by the CPU in cycle accurate mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusCycle {
+    pub address: usize,
+    /// The value read or written
+    pub value: u8,
+    pub kind: AccessKind,
 }
 
+/// Called for every access of the CPU, see `Bus::on_bus_cycle()`
 pub trait MemoryListener {
-    fn on_read_or_wrote(&mut self, address: usize, value: u8);
+    fn on_read_or_wrote(&mut self, access: BusCycle, cycle: u64);
+}
+
+/// Told about the writes to a ROM region added with `RomPolicy::Report`
+pub trait RomListener {
+    fn on_rom_write(&mut self, address: usize, value: u8);
+}
+
+/// What happens when the CPU writes to a ROM region. The ROM is never modified.
+pub enum RomPolicy {
+    /// The write is dropped, like on real hardware
+    Ignore,
+    /// The write is dropped and reported to the listener
+    Report(Box<dyn RomListener>),
+    /// The write is dropped and `Cpu::step()` returns `SixtyError::RomWrite`
+    Error,
+}
+
+/// Memory mapped hardware, added to a range of addresses with `Memory::add_device()`. It
+/// receives the full address of each access to its range, which then never reaches RAM.
+pub trait Device {
+    /// Like `Bus::read()`, a device whose state changes when it's read, e.g. a soft switch,
+    /// keeps that state in a `Cell` or a `RefCell`
+    fn read(&self, address: usize) -> u8;
+
+    /// `Bus::peek()` of the device, the value of `read()` without its side effects
+    fn peek(&self, address: usize) -> u8 {
+        self.read(address)
+    }
+
+    fn write(&mut self, address: usize, value: u8);
 }
 
 impl Memory {
 
-    pub fn new_with_file(file_name: &str, listener: Option<Box<dyn MemoryListener>>) -> Memory {
+    pub fn new_with_file(file_name: &str, listener: Option<Box<dyn MemoryListener>>)
+            -> Result<Memory, SixtyError> {
         let mut result = Memory {
             buffer: Vec::new(),
-            _listener: listener,
-            stack_pointer: 0xff
+            listener,
+            fault: Cell::new(None),
+            devices: Vec::new(),
+            banks: Banks::default(),
+            roms: Vec::new(),
         };
-        result.load(file_name);
-        result
+        result.load(file_name)?;
+        Ok(result)
     }
 
     pub fn new_with_vec(buffer: Vec<u8>, listener: Option<Box<dyn MemoryListener>>) -> Memory {
@@ -43,144 +142,294 @@ impl Memory {
             };
         Memory {
             buffer: actual_buffer,
-            _listener: listener,
-            stack_pointer: 0xff
+            listener,
+            fault: Cell::new(None),
+            devices: Vec::new(),
+            banks: Banks::default(),
+            roms: Vec::new(),
         }
     }
 
-    pub(crate) fn get(&self, index: usize) -> u8 {
-        self.buffer[index]
+    /// Map `device` on `range`. A device added later takes precedence where the ranges
+    /// overlap, e.g. to take over one address inside a larger I/O area.
+    pub fn add_device(&mut self, range: RangeInclusive<usize>, device: Box<dyn Device>) {
+        self.devices.push((range, Attached::Device(device)));
     }
 
-    pub(crate) fn set(&mut self, index: usize, value: u8) {
-        self.buffer[index] = value
+    /// Map a bank switch on `range`, with the same precedence as `add_device()`
+    pub fn add_switch(&mut self, range: RangeInclusive<usize>, switch: Box<dyn BankSwitch>) {
+        self.devices.push((range, Attached::Switch(switch)));
     }
 
-    fn load(&mut self, file_name: &str) {
-        let mut f = File::open(file_name).expect("Couldn't find the file");
-        f.read_to_end(&mut self.buffer).expect("Could not find file {}");
+    /// Add a bank of memory. It's not visible until pages are mapped to it with
+    /// `Banks::map_read()` and `Banks::map_write()`.
+    pub fn add_bank(&mut self, data: Vec<u8>) -> BankId {
+        self.banks.add(data)
     }
 
-    pub(crate) fn word(&self, address: usize) -> u16 {
-        self.get(address) as u16 | ((self.get(address + 1) as u16) << 8)
+    pub fn banks(&self) -> &Banks {
+        &self.banks
     }
-    // fn disassemble(&mut self, index: usize) -> (String, usize);
 
-    fn inc(&mut self) {
-        if self.stack_pointer == 0xff {
-            self.stack_pointer = 0
-        } else {
-            self.stack_pointer = self.stack_pointer + 1;
-        }
+    pub fn banks_mut(&mut self) -> &mut Banks {
+        &mut self.banks
+    }
+
+    fn device_index(&self, address: usize) -> Option<usize> {
+        self.devices.iter().rposition(|(range, _)| range.contains(&address))
+    }
+
+    fn load(&mut self, file_name: &str) -> Result<(), SixtyError> {
+        self.buffer = read_file(file_name)?;
+        Ok(())
     }
 
-    fn dec(&mut self) {
-        if self.stack_pointer == 0 {
-            self.stack_pointer = 0xff as usize
-        } else {
-            self.stack_pointer = self.stack_pointer - 1;
+    /// Load a program in `format`, or in the format detected from its contents if `format`
+    /// is `None`, see `image::detect()`. Returns the parsed image, whose `start` is the entry
+    /// point if the file has one.
+    pub fn load_file(&mut self, file_name: &str, format: Option<Format>)
+            -> Result<Image, SixtyError> {
+        let image = Image::parse(&read_file(file_name)?, format)?;
+        self.load_image(&image);
+        Ok(image)
+    }
+
+    /// Copy the segments of `image` at their address, growing the buffer if they go past its
+    /// end. Like the ROM regions they're loaded in, devices and banks are bypassed.
+    pub fn load_image(&mut self, image: &Image) {
+        for (address, bytes) in &image.segments {
+            let end = address + bytes.len();
+            if end > self.buffer.len() {
+                self.buffer.resize(end, 0);
+            }
+            self.buffer[*address..end].copy_from_slice(bytes);
         }
     }
 
-    pub(crate) fn push_byte(&mut self, a: u8) {
-        self.set(STACK_ADDRESS + self.stack_pointer, a);
-        self.dec();
+    /// The bytes of `range` in `format`, read from the buffer. Fails with
+    /// `SixtyError::BadImage` when the format can't hold the range.
+    pub fn dump(&self, range: RangeInclusive<usize>, format: Format)
+            -> Result<Vec<u8>, SixtyError> {
+        let (start, end) = (*range.start(), (range.end() + 1).min(self.buffer.len()));
+        image::dump(start, &self.buffer[start.min(end)..end], format)
     }
 
-    pub(crate) fn pop_byte(&mut self,) -> u8 {
-        self.inc();
-        self.get(STACK_ADDRESS + self.stack_pointer)
+    /// Make `range` of the buffer read only. Devices and banks mapped over it are unaffected.
+    pub fn add_rom(&mut self, range: RangeInclusive<usize>, policy: RomPolicy) {
+        self.roms.push((range, policy));
     }
 
-    pub(crate) fn push_word(&mut self, a: u16) {
-        self.set(STACK_ADDRESS + self.stack_pointer, ((a & 0xff00) >> 8) as u8);
-        self.dec();
-        self.set(STACK_ADDRESS + self.stack_pointer, (a & 0xff) as u8);
-        self.dec();
+    fn fault(&self, error: SixtyError) {
+        let previous = self.fault.take();
+        self.fault.set(previous.or(Some(error)));
     }
 
-    pub(crate) fn pop_word(&mut self) -> usize {
-        self.inc();
-        let low = self.get(STACK_ADDRESS + self.stack_pointer) as usize;
-        self.inc();
-        let high = self.get(STACK_ADDRESS + self.stack_pointer) as usize;
-        low | high << 8
+    pub(crate) fn word(&self, address: usize) -> u16 {
+        self.peek(address) as u16 | ((self.peek((address + 1) & 0xffff) as u16) << 8)
     }
 
-    pub fn disassemble(&self, index: usize) -> (String, usize) {
-        let opcode = self.get(index) as usize;
-        let size: usize = crate::constants::SIZES[opcode];
-        let mut bytes = Vec::new();
-        bytes.push(opcode as u8);
-        if size >= 2 {
-            bytes.push(self.get(index + 1));
+    /// `read()` or, when `peek` is true, `peek()`
+    fn read_or_peek(&self, address: usize, peek: bool) -> u8 {
+        if let Some(index) = self.device_index(address) {
+            return match &self.devices[index].1 {
+                Attached::Device(device) if peek => device.peek(address),
+                Attached::Device(device) => device.read(address),
+                Attached::Switch(switch) if peek => switch.peek(address, &self.banks),
+                Attached::Switch(switch) => switch.read(address, &self.banks),
+            };
         }
-        if size >= 3 {
-            bytes.push(self.get(index + 2));
+        if let Some(value) = self.banks.read(address) {
+            return value;
         }
-        return disassemble3(index, bytes);
+        match self.buffer.get(address) {
+            Some(value) => *value,
+            None => {
+                if ! peek {
+                    self.fault(SixtyError::AddressOutOfRange(address));
+                }
+                0
+            },
+        }
+    }
+
+    pub fn disassemble(&self, index: usize, variant: CpuVariant) -> (String, usize) {
+        disassemble(self, index, variant, &Symbols::new())
+    }
+
+    /// Like `disassemble()`, with the addresses named in `symbols` replaced by their name
+    pub fn disassemble_with_symbols(&self, index: usize, variant: CpuVariant, symbols: &Symbols)
+            -> (String, usize) {
+        disassemble(self, index, variant, symbols)
     }
+}
 
-    pub(crate) fn format_stack(&self) -> String {
-        let mut result = Vec::new();
-        result.push(std::format!("SP={{${:2X} stack:[", self.stack_pointer));
-        let down = max(self.stack_pointer + 1, 0xf8);
-        let mut i = 0xff;
-        if self.stack_pointer < 0xff {
-            loop {
-                let v = self.get(STACK_ADDRESS + i);
-                result.push(std::format!("{:02X}={:02X}", i, v));
-                i = i - 1;
-                if i < down { break; }
+/// Reads past the end of the buffer return 0 and writes are dropped, the address is kept
+/// for `take_fault()`
+impl Bus for Memory {
+    fn read(&self, address: usize) -> u8 {
+        self.read_or_peek(address, false)
+    }
+
+    /// Past the end of the buffer, 0 without a fault
+    fn peek(&self, address: usize) -> u8 {
+        self.read_or_peek(address, true)
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        if let Some(index) = self.device_index(address) {
+            return match &mut self.devices[index].1 {
+                Attached::Device(device) => device.write(address, value),
+                Attached::Switch(switch) => switch.write(address, value, &self.banks),
+            };
+        }
+        if self.banks.write(address, value) {
+            return;
+        }
+        if let Some((_, policy)) = self.roms.iter_mut().find(|(range, _)| range.contains(&address)) {
+            match policy {
+                RomPolicy::Ignore => {},
+                RomPolicy::Report(listener) => listener.on_rom_write(address, value),
+                RomPolicy::Error => self.fault(SixtyError::RomWrite { address, value }),
             }
+            return;
+        }
+        match self.buffer.get_mut(address) {
+            Some(byte) => *byte = value,
+            None => self.fault(SixtyError::AddressOutOfRange(address)),
         }
-        result.push("]}}".to_string());
-        result.join(" ")
     }
-}
 
-fn _word(buffer: &Vec<u8>, index: usize) -> u16 {
-    return buffer[index + 1] as u16 | ((buffer[index + 2] as u16) << 8);
+    fn take_fault(&self) -> Option<SixtyError> {
+        self.fault.take()
+    }
+
+    fn observes_bus_cycles(&self) -> bool {
+        self.listener.is_some()
+    }
+
+    fn on_bus_cycle(&mut self, access: BusCycle, cycle: u64) {
+        if let Some(listener) = self.listener.as_mut() {
+            listener.on_read_or_wrote(access, cycle);
+        }
+    }
 }
 
-fn word2(b0: u8, b1: u8) -> u16 {
-    return b0 as u16 | ((b1 as u16) << 8);
+pub(crate) fn read_file(file_name: &str) -> Result<Vec<u8>, SixtyError> {
+    let mut result = Vec::new();
+    File::open(file_name)
+        .and_then(|mut f| f.read_to_end(&mut result))
+        .map(|_| result)
+        .map_err(|error| SixtyError::Io { file_name: file_name.to_string(), error })
 }
 
-fn disassemble3(index: usize, bytes: Vec<u8>) -> (String, usize) {
-    let opcode = bytes[0] as usize;
-    let name = crate::constants::OPCODE_NAMES[opcode];
-    let addressing_type = &ADDRESSING_TYPES[opcode];
-    let s = match bytes.len() {
-        1 => format!("{:04X}: {:02X}         {}", index,
-                     opcode,
-                     name),
-        2 => format!("{:04X}: {:02X} {:02X}      {} {}", index,
-                     opcode, bytes[1],
-                     name,
-                     addressing_type.to_string(index, bytes[1], 0)),
-        _ => format!("{:04X}: {:02X} {:02X} {:02X}   {} {}", index,
-                     opcode, bytes[1], bytes[2],
-                     name,
-                     addressing_type.to_string(index, bytes[1], word2(bytes[1], bytes[2])))
-    };
-
-    let mut result = String::new();
-    result.push_str(& s);
-    return (result, bytes.len());
+/// The instruction at `index` as a line like `0300: A9 42      LDA #$42`, and its size
+pub(crate) fn disassemble<B: Bus + ?Sized>(bus: &B, index: usize, variant: CpuVariant,
+        symbols: &Symbols) -> (String, usize) {
+    let instruction = decode(bus, index, variant);
+    let bytes: Vec<String> = std::iter::once(instruction.opcode)
+        .chain(instruction.operand_bytes())
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    (format!("{:04X}: {:<10} {}", index, bytes.join(" "), instruction.to_string_with(symbols)),
+        instruction.size)
 }
 
-// fn disassemble(buffer: &Vec<u8>, index: usize) -> (String, usize) {
-//     let opcode = buffer[index] as usize;
-//     let size: usize = crate::constants::SIZES[opcode];
-//     let mut bytes = Vec::new();
-//     bytes.push(opcode as u8);
-//     if size >= 2 {
-//         bytes.push(buffer[index + 1]);
-//     }
-//     if size >= 3 {
-//         bytes.push(buffer[index + 2]);
-//     }
-//
-//     return disassemble3(index, bytes);
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::*;
+    use crate::cpu::Cpu;
+    use crate::test::{memory, EndListener};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// The Apple ][ keyboard: $C000 has the last key with bit 7 set until $C010 is accessed
+    struct Keyboard {
+        key: Cell<u8>,
+    }
+
+    impl Device for Keyboard {
+        fn read(&self, address: usize) -> u8 {
+            if address == 0xc010 {
+                self.key.set(self.key.get() & 0x7f);
+            }
+            self.key.get()
+        }
+
+        fn write(&mut self, _address: usize, _value: u8) {
+            self.key.set(self.key.get() & 0x7f);
+        }
+    }
+
+    /// Records the writes to its range and reads as $A0
+    struct Recorder {
+        writes: Rc<RefCell<Vec<(usize, u8)>>>,
+    }
+
+    impl Device for Recorder {
+        fn read(&self, _address: usize) -> u8 {
+            0xa0
+        }
+
+        fn write(&mut self, address: usize, value: u8) {
+            self.writes.borrow_mut().push((address, value));
+        }
+    }
+
+    struct RomWrites {
+        writes: Rc<RefCell<Vec<(usize, u8)>>>,
+    }
+
+    impl RomListener for RomWrites {
+        fn on_rom_write(&mut self, address: usize, value: u8) {
+            self.writes.borrow_mut().push((address, value));
+        }
+    }
+
+    #[test]
+    fn devices_take_over_their_range() {
+        let program = vec!(
+            LDA_ABS, 0x00, 0xc0, STA_ZP, 0x80, BIT_ABS, 0x10, 0xc0, LDA_ABS, 0x00, 0xc0, STA_ZP, 0x81,
+            LDA_ABS, 0x50, 0xc0, STA_ZP, 0x82, STA_ABS, 0x50, 0xc0);
+        let end = program.len();
+        let mut m = memory(&[(0, &program), (0xc050, &[0x42])]);
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        m.add_device(0xc000..=0xc0ff, Box::new(Recorder { writes: writes.clone() }));
+        // Added last, so it wins over the I/O area for $C000-$C01F
+        m.add_device(0xc000..=0xc01f, Box::new(Keyboard { key: Cell::new(0xc1) }));
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, Some(Box::new(EndListener { end })));
+        cpu.run(0).unwrap();
+        assert_eq!(cpu.memory.read(0x80), 0xc1);
+        // The strobe was cleared by the BIT
+        assert_eq!(cpu.memory.read(0x81), 0x41);
+        // The RAM at $C050 is hidden
+        assert_eq!(cpu.memory.read(0x82), 0xa0);
+        assert_eq!(*writes.borrow(), vec![(0xc050, 0xa0)]);
+    }
+
+    #[test]
+    fn rom_write_policies() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let policies = vec!(RomPolicy::Ignore,
+            RomPolicy::Report(Box::new(RomWrites { writes: writes.clone() })), RomPolicy::Error);
+        for policy in policies {
+            let stops = matches!(policy, RomPolicy::Error);
+            let mut buffer = vec![0xff; 0x1000];
+            buffer[..8].copy_from_slice(&[LDA_IMM, 0x55, STA_ABS, 0x00, 0x08, STA_ABS, 0x00, 0x07]);
+            let mut m = Memory::new_with_vec(buffer, None);
+            m.add_rom(0x800..=0xfff, policy);
+            let mut cpu = Cpu::new(m, CpuVariant::Nmos6502, None);
+            cpu.pc = 0;
+            let results: Vec<_> = (0..3).map(|_| cpu.step()).collect();
+            if stops {
+                assert!(matches!(results[1], Err(SixtyError::RomWrite { address: 0x800, value: 0x55 })));
+            } else {
+                assert!(results.iter().all(Result::is_ok));
+            }
+            assert_eq!(cpu.memory.read(0x800), 0xff);
+            // Outside of the ROM
+            assert_eq!(cpu.memory.read(0x700), 0x55);
+        }
+        assert_eq!(*writes.borrow(), vec![(0x800, 0x55)]);
+    }
+}
@@ -0,0 +1,84 @@
+//! The processor port of the 6510, the CPU of the Commodore 64. Address $00 is the data
+//! direction register and $01 the data register of six I/O pins (eight here, the chip only
+//! bonds out six) that the C64 uses to bank BASIC, the KERNAL, the character ROM and the I/O
+//! area in and out, and to drive the cassette.
+//!
+//! Both registers live in the CPU: reads of $00 and $01 return them without reaching the
+//! bus, writes go to the bus as well, so the RAM underneath gets the same values.
+
+/// Called when the CPU changes the level of the port pins
+pub trait PortListener {
+    fn on_pins_changed(&mut self, pins: u8);
+}
+
+pub struct ProcessorPort {
+    direction: u8,
+    data: u8,
+    /// The levels the outside world drives on the pins configured as inputs
+    inputs: u8,
+    listener: Option<Box<dyn PortListener>>,
+}
+
+pub(crate) const DIRECTION_ADDRESS: usize = 0x00;
+pub(crate) const DATA_ADDRESS: usize = 0x01;
+
+impl ProcessorPort {
+    pub(crate) fn new() -> ProcessorPort {
+        ProcessorPort {
+            direction: 0,
+            data: 0,
+            // Pins left floating read as 1, like with the pull-up resistors of the C64
+            inputs: 0xff,
+            listener: None,
+        }
+    }
+
+    /// 1 bits are outputs, 0 bits inputs
+    pub fn direction(&self) -> u8 { self.direction }
+
+    pub fn data(&self) -> u8 { self.data }
+
+    /// The level of every pin: the data register for the outputs, the inputs for the others
+    pub fn pins(&self) -> u8 {
+        (self.data & self.direction) | (self.inputs & ! self.direction)
+    }
+
+    /// Drive the pins configured as inputs, e.g. the cassette sense line on bit 4
+    pub fn set_inputs(&mut self, inputs: u8) {
+        self.inputs = inputs;
+    }
+
+    pub fn set_listener(&mut self, listener: Option<Box<dyn PortListener>>) {
+        self.listener = listener;
+    }
+
+    pub(crate) fn read(&self, address: usize) -> u8 {
+        if address == DIRECTION_ADDRESS { self.direction } else { self.pins() }
+    }
+
+    pub(crate) fn write(&mut self, address: usize, value: u8) {
+        let pins = self.pins();
+        if address == DIRECTION_ADDRESS {
+            self.direction = value;
+        } else {
+            self.data = value;
+        }
+        self.notify(pins);
+    }
+
+    /// RESET turns every pin into an input
+    pub(crate) fn reset(&mut self) {
+        let pins = self.pins();
+        self.direction = 0;
+        self.notify(pins);
+    }
+
+    fn notify(&mut self, previous_pins: u8) {
+        let pins = self.pins();
+        if pins != previous_pins {
+            if let Some(listener) = self.listener.as_mut() {
+                listener.on_pins_changed(pins);
+            }
+        }
+    }
+}
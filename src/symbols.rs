@@ -0,0 +1,333 @@
+//! Names for addresses, loaded from the symbol files of assemblers and emulators, for the
+//! disassembler and the traces to print `JSR COUT` rather than `JSR $FDED`. `apple2_monitor()`
+//! and `apple2_soft_switches()` are built in.
+
+use crate::error::SixtyError;
+use crate::memory::read_file;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolFormat {
+    /// VICE label files: `al C:fded .cout`
+    Vice,
+    /// The debug info that ca65 and ld65 write with `-g` and `--dbgfile`. Only the labels
+    /// are read, not the constants.
+    Ca65Debug,
+    /// The exports of an ld65 map file
+    Ca65Map,
+    /// The label dumps of ACME (`--symbollist`) and 64tass (`--labels`): `cout = $fded`.
+    /// The values that aren't numbers are skipped.
+    LabelDump,
+    /// One address in hex and a name per line: `FDED COUT`
+    Plain,
+}
+
+impl fmt::Display for SymbolFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SymbolFormat::Vice => "VICE label file",
+            SymbolFormat::Ca65Debug => "ca65 debug file",
+            SymbolFormat::Ca65Map => "ld65 map file",
+            SymbolFormat::LabelDump => "label dump",
+            SymbolFormat::Plain => "symbol file",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    names: BTreeMap<usize, String>,
+    addresses: HashMap<String, usize>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// Parse `text` as `format`, or as the format `detect()` finds if `format` is `None`
+    pub fn parse(text: &str, format: Option<SymbolFormat>) -> Result<Symbols, SixtyError> {
+        let format = format.unwrap_or_else(|| detect(text));
+        let mut result = Symbols::new();
+        let mut exports = false;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = |message: &str| SixtyError::BadSymbols {
+                format,
+                message: format!("line {}: {}", index + 1, message),
+            };
+            match format {
+                SymbolFormat::Vice => {
+                    let mut words = line.split_whitespace();
+                    if words.next() != Some("al") {
+                        continue;
+                    }
+                    let (address, name) = match (words.next(), words.next()) {
+                        (Some(address), Some(name)) => (address, name),
+                        _ => return Err(error("expected an address and a name")),
+                    };
+                    // The address can be prefixed by a memory space like C:
+                    let address = address.rsplit(':').next().unwrap_or(address);
+                    let address = hex(address).ok_or_else(|| error("invalid address"))?;
+                    result.insert(address, name.trim_start_matches('.'));
+                },
+                SymbolFormat::Ca65Debug => {
+                    let (kind, fields) = debug_record(line);
+                    if kind == "sym" && fields.get("type") == Some(&"lab") {
+                        let name = fields.get("name").ok_or_else(|| error("symbol without a name"))?;
+                        let value = fields.get("val").ok_or_else(|| error("label without a value"))?;
+                        let value = number(value).ok_or_else(|| error("invalid value"))?;
+                        result.insert(value, name);
+                    }
+                },
+                SymbolFormat::Ca65Map => {
+                    // Name, value and flags, one or two exports per line
+                    if line.starts_with("Exports list") {
+                        exports = true;
+                    } else if line.ends_with(':') || (line.is_empty() && exports) {
+                        exports = false;
+                    } else if exports && ! line.starts_with('-') {
+                        let words: Vec<&str> = line.split_whitespace().collect();
+                        for export in words.chunks(3) {
+                            let address = export.get(1).and_then(|a| hex(a))
+                                .ok_or_else(|| error("invalid export"))?;
+                            result.insert(address, export[0]);
+                        }
+                    }
+                },
+                SymbolFormat::LabelDump => {
+                    let line = line.split(';').next().unwrap_or("");
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let (name, value) = match line.split_once(":=").or_else(|| line.split_once('=')) {
+                        Some((name, value)) => (name.trim(), value.trim()),
+                        None => return Err(error("expected name = value")),
+                    };
+                    // 64tass also dumps strings, lists and floats, which aren't addresses
+                    if let Some(value) = number(value) {
+                        result.insert(value, name.trim_start_matches('.'));
+                    }
+                },
+                SymbolFormat::Plain => {
+                    let mut words = line.split_whitespace();
+                    if let Some(address) = words.next() {
+                        let address = hex(address.trim_start_matches('$'))
+                            .ok_or_else(|| error("invalid address"))?;
+                        let name = words.next().ok_or_else(|| error("missing the name"))?;
+                        result.insert(address, name);
+                    }
+                },
+            }
+        }
+        Ok(result)
+    }
+
+    /// Load a symbol file in `format`, or in the format detected from its contents
+    pub fn load_file(file_name: &str, format: Option<SymbolFormat>) -> Result<Symbols, SixtyError> {
+        let data = read_file(file_name)?;
+        let text = String::from_utf8_lossy(&data);
+        Symbols::parse(&text, format)
+    }
+
+    /// Name `address`. An address keeps the first name it was given, but all the names can
+    /// be looked up with `address()`.
+    pub fn insert(&mut self, address: usize, name: &str) {
+        self.names.entry(address).or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    /// Add the symbols of `other`, the names already in `self` win
+    pub fn extend(&mut self, other: &Symbols) {
+        for (address, name) in other.iter() {
+            self.insert(address, name);
+        }
+    }
+
+    pub fn name(&self, address: usize) -> Option<&str> {
+        self.names.get(&address).map(|name| name.as_str())
+    }
+
+    pub fn address(&self, name: &str) -> Option<usize> {
+        self.addresses.get(name).copied()
+    }
+
+    /// The name of each address, by address
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.names.iter().map(|(address, name)| (*address, name.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The entry points of the Apple ][ monitor ROM, with their names from the Apple ][
+    /// Reference Manual
+    pub fn apple2_monitor() -> Symbols {
+        Symbols::from_table(&[
+            (0xf800, "PLOT"), (0xf819, "HLINE"), (0xf828, "VLINE"), (0xf832, "CLRSCR"),
+            (0xf836, "CLRTOP"), (0xf864, "SETCOL"), (0xf871, "SCRN"), (0xf941, "PRNTAX"),
+            (0xf94a, "PRBL2"), (0xfa62, "RESET"), (0xfb1e, "PREAD"), (0xfb2f, "INIT"),
+            (0xfb39, "SETTXT"), (0xfb40, "SETGR"), (0xfbc1, "BASCALC"), (0xfbdd, "BELL1"),
+            (0xfc22, "VTAB"), (0xfc42, "CLREOP"), (0xfc58, "HOME"), (0xfc9c, "CLREOL"),
+            (0xfca8, "WAIT"), (0xfd0c, "RDKEY"), (0xfd1b, "KEYIN"), (0xfd35, "RDCHAR"),
+            (0xfd6a, "GETLN"), (0xfd8b, "CROUT1"), (0xfd8e, "CROUT"), (0xfdda, "PRBYTE"),
+            (0xfde3, "PRHEX"), (0xfded, "COUT"), (0xfdf0, "COUT1"), (0xfe80, "SETINV"),
+            (0xfe84, "SETNORM"), (0xfe89, "SETKBD"), (0xfe8b, "INPORT"), (0xfe93, "SETVID"),
+            (0xfe95, "OUTPORT"), (0xff2d, "PRERR"), (0xff3a, "BELL"), (0xff3f, "IOREST"),
+            (0xff4a, "IOSAVE"), (0xff65, "MON"), (0xff69, "MONZ"),
+        ])
+    }
+
+    /// The keyboard, speaker and video soft switches of the Apple ][
+    pub fn apple2_soft_switches() -> Symbols {
+        Symbols::from_table(&[
+            (0xc000, "KBD"), (0xc010, "KBDSTRB"), (0xc030, "SPKR"), (0xc050, "TXTCLR"),
+            (0xc051, "TXTSET"), (0xc052, "MIXCLR"), (0xc053, "MIXSET"), (0xc054, "LOWSCR"),
+            (0xc055, "HISCR"), (0xc056, "LORES"), (0xc057, "HIRES"),
+        ])
+    }
+
+    fn from_table(table: &[(usize, &str)]) -> Symbols {
+        let mut result = Symbols::new();
+        for (address, name) in table {
+            result.insert(*address, name);
+        }
+        result
+    }
+}
+
+/// Guess the format of `text` from its first line that isn't blank
+pub fn detect(text: &str) -> SymbolFormat {
+    let first = text.lines().map(str::trim).find(|line| ! line.is_empty()).unwrap_or("");
+    if first.starts_with("al ") {
+        SymbolFormat::Vice
+    } else if first.starts_with("version\tmajor=") || first.starts_with("version major=") {
+        SymbolFormat::Ca65Debug
+    } else if text.contains("Exports list by") {
+        SymbolFormat::Ca65Map
+    } else if first.contains('=') {
+        SymbolFormat::LabelDump
+    } else {
+        SymbolFormat::Plain
+    }
+}
+
+/// A line of a ca65 debug file, like `sym id=0,name="main",val=0x800`: its kind and its
+/// fields, with the quotes removed. Quoted values can contain commas.
+pub(crate) fn debug_record(line: &str) -> (&str, HashMap<&str, &str>) {
+    let (kind, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let mut fields = HashMap::new();
+    let (mut start, mut quoted) = (0, false);
+    for (i, c) in rest.char_indices().chain(std::iter::once((rest.len(), ','))) {
+        match c {
+            '"' => quoted = ! quoted,
+            ',' if ! quoted => {
+                if let Some((key, value)) = rest[start..i].split_once('=') {
+                    let value = value.trim();
+                    let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+                    fields.insert(key.trim(), value);
+                }
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    (kind, fields)
+}
+
+fn hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// `$fded`, `0xfded` or a decimal number
+pub(crate) fn number(text: &str) -> Option<usize> {
+    if let Some(digits) = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        hex(digits)
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::constants::*;
+    use crate::cpu::CpuVariant;
+    use crate::disassembler::{disassemble, Dialect, Layout};
+    use crate::memory::Memory;
+
+    #[test]
+    fn symbols_name_addresses() {
+        let parse = |text: &str| {
+            let symbols = Symbols::parse(text, None).unwrap();
+            (detect(text), symbols.iter().map(|(a, n)| (a, n.to_string())).collect::<Vec<_>>())
+        };
+        let expected = vec!((0x0800, "main".to_string()), (0x0810, "print".to_string()));
+        assert_eq!(parse("al C:0800 .main\nal 0810 .print\n"), (SymbolFormat::Vice, expected.clone()));
+        assert_eq!(parse(concat!(
+            "version\tmajor=2,minor=0\n",
+            "sym\tid=0,name=\"main\",addrsize=absolute,scope=0,def=0,val=0x800,seg=0,type=lab\n",
+            "sym\tid=1,name=\"count\",addrsize=zeropage,scope=0,def=1,val=0x8,type=equ\n",
+            "sym\tid=2,name=\"print\",addrsize=absolute,scope=0,def=2,val=0x810,seg=0,type=lab\n",
+        )), (SymbolFormat::Ca65Debug, expected.clone()));
+        assert_eq!(parse(concat!(
+            "Modules list:\n-------------\nmain.o:\n\n",
+            "Exports list by name:\n---------------------\n",
+            "main                      000800 RLA    print                     000810 RLA    \n\n",
+            "Imports list:\n",
+        )), (SymbolFormat::Ca65Map, expected.clone()));
+        assert_eq!(parse("main = $0800\n\tprint\t= $810 ; ?\n"), (SymbolFormat::LabelDump, expected.clone()));
+        // 64tass dumps the values that aren't addresses too
+        assert_eq!(parse("main\t= $0800\ntitle\t= \"a = b\"\nsizes\t= [1, 2]\nprint\t= $0810\nratio\t= 1.5\n"),
+            (SymbolFormat::LabelDump, expected.clone()));
+        assert_eq!(parse("0800 main\n$0810 print\n"), (SymbolFormat::Plain, expected));
+        assert_eq!(Symbols::parse("al xyz .main", None).unwrap_err().to_string(),
+            "Invalid VICE label file: line 1: invalid address");
+
+        let program = assemble("
+                    .org $0300
+                    jsr $FDED
+                    sta $24
+                    lda $C000
+                    jmp $0300
+        ", CpuVariant::Cmos65C02).unwrap();
+        let m = Memory::new_with_vec(program.to_vec(), None);
+        let mut symbols = Symbols::apple2_monitor();
+        symbols.extend(&Symbols::apple2_soft_switches());
+        symbols.insert(0x24, "CH");
+        symbols.insert(0x0300, "start");
+        assert_eq!(m.disassemble_with_symbols(0x300, CpuVariant::Cmos65C02, &symbols).0,
+            "0300: 20 ED FD   JSR COUT");
+        assert_eq!(m.disassemble_with_symbols(0x303, CpuVariant::Cmos65C02, &symbols).0,
+            "0303: 85 24      STA CH");
+        // The absolute form of the same address keeps its marker
+        let absolute = Memory::new_with_vec(vec!(STA_ABS, 0x24, 0x00, LDA_ABS_X, 0x24, 0x00), None);
+        assert_eq!(absolute.disassemble_with_symbols(0, CpuVariant::Cmos65C02, &symbols).0,
+            "0000: 8D 24 00   STA a:CH");
+        assert_eq!(absolute.disassemble(3, CpuVariant::Cmos65C02).0, "0003: BD 24 00   LDA a:$0024,X");
+
+        let mut disassembly = disassemble(&program.to_vec()[0x300..], 0x300, CpuVariant::Cmos65C02, &[0x300]).unwrap();
+        disassembly.set_symbols(&symbols);
+        let source = disassembly.source();
+        assert_eq!(source, [
+            "CH = $24",
+            "KBD = $C000",
+            "COUT = $FDED",
+            "        .org $0300",
+            "start:  jsr COUT",
+            "        sta CH",
+            "        lda KBD",
+            "        jmp start",
+            "",
+        ].join("\n"));
+        assert_eq!(assemble(&source, CpuVariant::Cmos65C02).unwrap().to_vec(), program.to_vec());
+        assert!(disassembly.print(Dialect::Merlin, Layout::Source).starts_with("        XC\nCH EQU $24\n"));
+    }
+}
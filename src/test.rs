@@ -1,22 +1,69 @@
-#[cfg(test)]
+//! The tests of the CPU, and the fixtures that the tests of the other modules share
+
+use crate::cpu::{Cpu, CpuListener, CpuVariant, RunStatus};
+use crate::memory::Memory;
+
+/// Stop as soon as the PC reaches the end of the program
+pub(crate) struct EndListener {
+    pub(crate) end: usize
+}
+
+impl CpuListener for EndListener {
+    fn on_pc_changed(&mut self, cpu: &Cpu) -> RunStatus {
+        if cpu.pc == self.end {
+            RunStatus::Stop(true, String::from("End of program"))
+        } else {
+            RunStatus::Continue
+        }
+    }
+}
+
+/// 64K of memory, more if `bytes` go past it, with each of `bytes` at its address
+pub(crate) fn memory(bytes: &[(usize, &[u8])]) -> Memory {
+    let size = bytes.iter().map(|(address, b)| address + b.len()).fold(0x10000, usize::max);
+    let mut buffer = vec![0; size];
+    for (address, b) in bytes {
+        buffer[*address..*address + b.len()].copy_from_slice(b);
+    }
+    Memory::new_with_vec(buffer, None)
+}
+
+/// Run `program` from $0000, in `memory()` with the other `bytes`, until the PC reaches its end
+pub(crate) fn run(variant: CpuVariant, program: &[u8], bytes: &[(usize, &[u8])]) -> Cpu {
+    let mut all = vec![(0, program)];
+    all.extend_from_slice(bytes);
+    let mut cpu = Cpu::new(memory(&all), variant, Some(Box::new(EndListener { end: program.len() })));
+    cpu.run(0).unwrap();
+    cpu
+}
+
 mod tests {
-    use crate::memory::Memory;
-    use crate::cpu::{Cpu, CpuListener, RunStatus};
+    use super::{memory, run, EndListener};
+    use crate::assembler::assemble;
+    use crate::bus::Bus;
+    use crate::memory::{AccessKind, BusCycle, Memory, MemoryListener};
+    use crate::cpu::{Cpu, CpuListener, CpuVariant, Interrupt, Operand, RunStatus};
+    use crate::constants::*;
+    use crate::error::SixtyError;
+    use crate::port::PortListener;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     struct Listener {
-        previous_pc: usize
+        previous_pc: usize,
+        success_pcs: Vec<usize>,
     }
 
     impl CpuListener for Listener {
         fn on_pc_changed(&mut self, cpu: &Cpu) -> RunStatus {
             let result =
-            if cpu.pc == 0x346c || cpu.pc == 0x3469 {
+            if self.success_pcs.contains(&cpu.pc) {
                 RunStatus::Stop(true, String::from("All tests passed"))
             } else {
                 if self.previous_pc != 0 && self.previous_pc == cpu.pc {
                     RunStatus::Stop(false,
-                                    format!("Infinite loop at PC={:2X} cycles={:04X} {}",
-                                            cpu.pc, cpu.cycles, cpu))
+                                    format!("Infinite loop at {} cycles={:04X} {}",
+                                            cpu.location(), cpu.cycles, cpu))
                 } else {
                     self.previous_pc = cpu.pc;
                     RunStatus::Continue
@@ -26,19 +73,1034 @@ mod tests {
         }
     }
 
-    #[test]
-    fn functional_tests() {
-        let m = Memory::new_with_file("6502_functional_test.bin", None);
-        let status = Cpu::new(m, Some(Box::new(Listener{ previous_pc: 0}))).run(0x400);
+    fn run_klaus_test(file_name: &str, variant: CpuVariant, cycle_accurate: bool,
+            success_pcs: Vec<usize>) {
+        let m = Memory::new_with_file(file_name, None).unwrap();
+        let listener = Listener { previous_pc: 0, success_pcs };
+        let mut cpu = Cpu::new(m, variant, Some(Box::new(listener)));
+        cpu.cycle_accurate = cycle_accurate;
+        let status = cpu.run(0x400).unwrap();
         match status {
             RunStatus::Stop(success, reason) => {
                 if success {
                     println!("SUCCESS: {}", reason);
                 } else {
-                    assert!(success, reason)
+                    assert!(success, "{}", reason)
                 }
             },
             _ => { unimplemented!("Should never happen"); }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn functional_tests() {
+        run_klaus_test("6502_functional_test.bin", CpuVariant::Cmos65C02, false, vec![0x346c, 0x3469]);
+    }
+
+    #[test]
+    fn functional_tests_nmos() {
+        run_klaus_test("6502_functional_test.bin", CpuVariant::Nmos6502, false, vec![0x346c, 0x3469]);
+    }
+
+    #[test]
+    fn functional_tests_cycle_accurate() {
+        run_klaus_test("6502_functional_test.bin", CpuVariant::Nmos6502, true, vec![0x346c, 0x3469]);
+    }
+
+    #[test]
+    #[ignore = "65C02_extended_opcodes_test.bin isn't in the repository yet, \
+        extended_opcodes_65c02 covers the same instructions"]
+    fn extended_opcodes_65c02_tests() {
+        run_klaus_test("65C02_extended_opcodes_test.bin", CpuVariant::Cmos65C02, false, vec![0x24f1]);
+    }
+
+    #[test]
+    fn stack_65c02() {
+        let cpu = run(CpuVariant::Cmos65C02, &[
+            LDX_IMM, 0x12, LDY_IMM, 0x34, PHX, PHY, LDX_IMM, 0, LDY_IMM, 0, PLX, PLY], &[]);
+        assert_eq!(cpu.x, 0x34);
+        assert_eq!(cpu.y, 0x12);
+        assert!(! cpu.p.z());
+    }
+
+    #[test]
+    fn stz_tsb_trb() {
+        let cpu = run(CpuVariant::Cmos65C02, &[
+            LDA_IMM, 0xff, STA_ZP, 0x80, STZ_ZP, 0x80,
+            LDA_IMM, 0x0f, STA_ZP, 0x81, LDA_IMM, 0x30, TSB_ZP, 0x81,
+            LDA_IMM, 0xf3, STA_ZP, 0x82, LDA_IMM, 0x03, TRB_ZP, 0x82], &[]);
+        assert_eq!(cpu.memory.read(0x80), 0);
+        assert_eq!(cpu.memory.read(0x81), 0x3f);
+        assert_eq!(cpu.memory.read(0x82), 0xf0);
+        // The last TRB found bits in common with A
+        assert!(! cpu.p.z());
+    }
+
+    #[test]
+    fn bit_immediate_only_changes_z() {
+        let cpu = run(CpuVariant::Cmos65C02, &[LDA_IMM, 0x01, BIT_IMM, 0xc0], &[]);
+        assert!(cpu.p.z());
+        assert!(! cpu.p.n());
+        assert!(! cpu.p.v());
+    }
+
+    #[test]
+    fn zero_page_indirect() {
+        let cpu = run(CpuVariant::Cmos65C02, &[
+            LDA_IMM, 0x00, STA_ZP, 0x80, LDA_IMM, 0x01, STA_ZP, 0x81,
+            LDA_IMM, 0x42, STA_ZPI, 0x80, LDA_IMM, 0, LDA_ZPI, 0x80, INC, INC, DEC], &[]);
+        assert_eq!(cpu.memory.read(0x100), 0x42);
+        assert_eq!(cpu.a, 0x43);
+    }
+
+    #[test]
+    fn jmp_absolute_indexed_indirect() {
+        // JMP ($0010,X) with X=2 reads the pointer at $12 and lands on the last INX
+        let cpu = run(CpuVariant::Cmos65C02, &[
+            LDA_IMM, 0x0e, STA_ZP, 0x12, LDX_IMM, 2, JMP_IND_X, 0x10, 0x00, INX, INX, INX, NOP,
+            NOP, INX], &[]);
+        assert_eq!(cpu.x, 3);
+    }
+
+    #[test]
+    fn bit_branches_and_bit_manipulation() {
+        let cpu = run(CpuVariant::Cmos65C02, &[
+            SMB3, 0x80, RMB0, 0x81, BBS3, 0x80, 0x02, LDX_IMM, 0xff, BBR0, 0x81, 0x02, LDY_IMM, 0xff,
+            BRA, 0x02, LDA_IMM, 0xff], &[]);
+        assert_eq!(cpu.memory.read(0x80), 0x08);
+        assert_eq!(cpu.x, 0);
+        assert_eq!(cpu.y, 0);
+        assert_eq!(cpu.a, 0);
+    }
+
+    /// Every instruction and addressing mode the 65C02 added to the 6502, each one run from
+    /// A=$81, X=$02, Y=$03 and P=$20, with what it leaves behind and its cycles
+    #[test]
+    fn extended_opcodes_65c02() {
+        // Source, A X Y P afterwards, memory afterwards, PC afterwards, cycles
+        type Case<'a> = (&'a str, [u8; 4], &'a [(usize, u8)], usize, u64);
+        let cases: &[Case] = &[
+            ("bra *+4", [0x81, 2, 3, 0x20], &[], 0x204, 3),
+            ("bra *-$10", [0x81, 2, 3, 0x20], &[], 0x1f0, 4),
+            ("phx", [0x81, 2, 3, 0x20], &[(0x1ff, 2)], 0x201, 3),
+            ("phy", [0x81, 2, 3, 0x20], &[(0x1ff, 3)], 0x201, 3),
+            ("plx", [0x81, 0x80, 3, 0xa0], &[], 0x201, 4),
+            ("ply", [0x81, 2, 0x80, 0xa0], &[], 0x201, 4),
+            ("stz $80", [0x81, 2, 3, 0x20], &[(0x80, 0)], 0x202, 3),
+            ("stz $7e,x", [0x81, 2, 3, 0x20], &[(0x80, 0)], 0x202, 4),
+            ("stz $1234", [0x81, 2, 3, 0x20], &[(0x1234, 0)], 0x203, 4),
+            ("stz $1232,x", [0x81, 2, 3, 0x20], &[(0x1234, 0)], 0x203, 5),
+            ("tsb $80", [0x81, 2, 3, 0x22], &[(0x80, 0xdb)], 0x202, 5),
+            ("tsb $1234", [0x81, 2, 3, 0x20], &[(0x1234, 0x8f)], 0x203, 6),
+            ("trb $80", [0x81, 2, 3, 0x22], &[(0x80, 0x5a)], 0x202, 5),
+            ("trb $1234", [0x81, 2, 3, 0x20], &[(0x1234, 0x0e)], 0x203, 6),
+            ("inc a", [0x82, 2, 3, 0xa0], &[], 0x201, 2),
+            ("dec a", [0x80, 2, 3, 0xa0], &[], 0x201, 2),
+            // BIT # only changes Z
+            ("bit #$7e", [0x81, 2, 3, 0x22], &[], 0x202, 2),
+            ("bit $7e,x", [0x81, 2, 3, 0x62], &[], 0x202, 4),
+            ("bit $1232,x", [0x81, 2, 3, 0x20], &[], 0x203, 4),
+            ("ora ($10)", [0x8f, 2, 3, 0xa0], &[], 0x202, 5),
+            ("and ($10)", [0x01, 2, 3, 0x20], &[], 0x202, 5),
+            ("eor ($10)", [0x8e, 2, 3, 0xa0], &[], 0x202, 5),
+            ("adc ($10)", [0x90, 2, 3, 0xa0], &[], 0x202, 5),
+            ("sta ($10)", [0x81, 2, 3, 0x20], &[(0x1234, 0x81)], 0x202, 5),
+            ("lda ($10)", [0x0f, 2, 3, 0x20], &[], 0x202, 5),
+            ("cmp ($10)", [0x81, 2, 3, 0x21], &[], 0x202, 5),
+            ("sbc ($10)", [0x71, 2, 3, 0x61], &[], 0x202, 5),
+            ("jmp ($0010,x)", [0x81, 2, 3, 0x20], &[], 0x1240, 6),
+            // The pointer doesn't wrap inside its page like on the NMOS 6502
+            ("jmp ($10ff)", [0x81, 2, 3, 0x20], &[], 0x300, 6),
+            ("rmb6 $80", [0x81, 2, 3, 0x20], &[(0x80, 0x1a)], 0x202, 5),
+            ("smb0 $80", [0x81, 2, 3, 0x20], &[(0x80, 0x5b)], 0x202, 5),
+            ("bbs1 $80,*+$10", [0x81, 2, 3, 0x20], &[], 0x210, 6),
+            ("bbr1 $80,*+$10", [0x81, 2, 3, 0x20], &[], 0x203, 5),
+            // Decimal ADC and SBC take one more cycle and set N and Z from the result
+            ("sed\nadc #$19", [0x00, 2, 3, 0x2b], &[], 0x203, 5),
+            ("sed\nsbc #$01", [0x79, 2, 3, 0x29], &[], 0x203, 5),
+        ];
+        for (source, registers, memory, pc, cycles) in cases {
+            let program = assemble(&format!(".org $200\n{}", source), CpuVariant::Cmos65C02).unwrap();
+            let mut buffer = program.to_vec();
+            buffer.resize(0x10000, 0);
+            for (address, value) in [(0x10, 0x34), (0x11, 0x12), (0x12, 0x40), (0x13, 0x12),
+                    (0x80, 0x5a), (0x100, 0x80), (0x1234, 0x0f), (0x10ff, 0x00), (0x1100, 0x03)] {
+                buffer[address] = value;
+            }
+            let mut cpu = Cpu::new(Memory::new_with_vec(buffer, None), CpuVariant::Cmos65C02, None);
+            cpu.pc = 0x200;
+            cpu.a = 0x81;
+            cpu.x = 2;
+            cpu.y = 3;
+            let steps = source.lines().count();
+            let taken: u64 = (0..steps).map(|_| cpu.step().unwrap().cycles).sum();
+            assert_eq!([cpu.a, cpu.x, cpu.y, cpu.p.value()], *registers, "{}", source);
+            for (address, value) in memory.iter() {
+                assert_eq!(cpu.memory.read(*address), *value, "{}", source);
+            }
+            assert_eq!((cpu.pc, taken), (*pc, *cycles), "{}", source);
+        }
+        // The other NOPs of the opcode table are the reserved ones
+        let nops: Vec<_> = (0..=0xffu8)
+            .filter(|&opcode| OPCODE_NAMES[opcode as usize] == "NOP" && opcode != NOP)
+            .collect();
+        let mut reserved = CMOS_RESERVED_NOPS.to_vec();
+        reserved.sort_unstable();
+        assert_eq!(nops, reserved);
+        // They skip their operand and change nothing, in both modes. Size and cycles:
+        for opcode in CMOS_RESERVED_NOPS.iter() {
+            let expected = match opcode {
+                0x44 => (2, 3),
+                0x54 | 0xd4 | 0xf4 => (2, 4),
+                0x5c => (3, 8),
+                0xdc | 0xfc => (3, 4),
+                _ if opcode & 3 == 2 => (2, 2),
+                _ => (1, 1),
+            };
+            for cycle_accurate in [false, true] {
+                let m = memory(&[(0x200, &[*opcode, 0x80, 0x00])]);
+                let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+                cpu.cycle_accurate = cycle_accurate;
+                cpu.pc = 0x200;
+                let (a, p) = (cpu.a, cpu.p.value());
+                let result = cpu.step().unwrap();
+                assert_eq!((cpu.pc - 0x200, result.cycles), expected, "{:02X}", opcode);
+                assert_eq!((cpu.a, cpu.p.value(), result.writes.len()), (a, p, 0), "{:02X}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn undocumented_lax_sax_dcp_isc() {
+        let cpu = run(CpuVariant::Nmos6502, &[
+            LDA_IMM, 0x5a, STA_ZP, 0x80, LAX_ZP, 0x80,
+            LDA_IMM, 0x0f, SAX_ZP, 0x81,
+            LDA_IMM, 0x10, STA_ZP, 0x82, LDA_IMM, 0x0f, DCP_ZP, 0x82,
+            LDA_IMM, 0xff, STA_ZP, 0x83, SEC, LDA_IMM, 0x05, ISC_ZP, 0x83], &[]);
+        assert_eq!(cpu.x, 0x5a);
+        assert_eq!(cpu.memory.read(0x81), 0x0a);
+        assert_eq!(cpu.memory.read(0x82), 0x0f);
+        assert_eq!(cpu.memory.read(0x83), 0x00);
+        assert_eq!(cpu.a, 0x05);
+    }
+
+    #[test]
+    fn undocumented_arr_sbx() {
+        let cpu = run(CpuVariant::Nmos6502, &[
+            SEC, LDA_IMM, 0xff, ARR_IMM, 0xc0, STA_ZP, 0x80,
+            LDA_IMM, 0xf0, LDX_IMM, 0x3c, SBX_IMM, 0x10], &[]);
+        // ARR: ($FF & $C0) >> 1 with the carry in bit 7 gives $E0, C is bit 6, V is bit 6 ^ bit 5
+        assert_eq!(cpu.memory.read(0x80), 0xe0);
+        assert!(! cpu.p.v());
+        // SBX: X = ($F0 & $3C) - $10
+        assert_eq!(cpu.x, 0x20);
+        assert!(cpu.p.c());
+    }
+
+    #[test]
+    fn ricoh_2a03_ignores_decimal_flag() {
+        let program = vec!(SED, CLC, LDA_IMM, 0x09, ADC_IMM, 0x01, TAX, SEC, SBC_IMM, 0x01);
+        let cpu = run(CpuVariant::Ricoh2A03, &program, &[]);
+        assert_eq!(cpu.x, 0x0a);
+        assert_eq!(cpu.a, 0x09);
+        // D can still be set and pushed, it just doesn't change the arithmetic
+        assert!(cpu.p.d());
+        let cpu = run(CpuVariant::Nmos6502, &program, &[]);
+        assert_eq!(cpu.x, 0x10);
+        assert_eq!(cpu.a, 0x09);
+    }
+
+    #[test]
+    fn mos_6507_mirrors_addresses() {
+        // 8K is all the 6507 can address: a 2600 cartridge at $1000 also runs from $F000,
+        // and $2080 is the same byte as $0080
+        let program = vec!(LDA_IMM, 0x42, STA_ABS, 0x80, 0x20, LDX_ABS, 0x80, 0x00, NOP);
+        let mut buffer = vec![0; 0x2000];
+        buffer[0x1000..0x1000 + program.len()].copy_from_slice(&program);
+        let m = Memory::new_with_vec(buffer, None);
+        let end = 0xf000 + program.len();
+        let mut cpu = Cpu::new(m, CpuVariant::Mos6507, Some(Box::new(EndListener { end })));
+        cpu.run(0xf000).unwrap();
+        assert_eq!(cpu.x, 0x42);
+        assert_eq!(cpu.memory.read(0x80), 0x42);
+    }
+
+    struct PinsListener {
+        pins: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl PortListener for PinsListener {
+        fn on_pins_changed(&mut self, pins: u8) {
+            self.pins.borrow_mut().push(pins);
+        }
+    }
+
+    #[test]
+    fn mos_6510_processor_port() {
+        // What the C64 KERNAL does at reset, then read the port back
+        let program = [LDA_IMM, 0x2f, STA_ZP, 0x00, LDA_IMM, 0x37, STA_ZP, 0x01, LDX_ZP, 0x01, NOP];
+        let m = memory(&[(0x100, &program)]);
+        let end = 0x100 + program.len();
+        let mut cpu = Cpu::new(m, CpuVariant::Mos6510, Some(Box::new(EndListener { end })));
+        let pins = Rc::new(RefCell::new(Vec::new()));
+        let port = cpu.port_mut().unwrap();
+        port.set_listener(Some(Box::new(PinsListener { pins: pins.clone() })));
+        // The cassette sense line pulls bit 4 low
+        port.set_inputs(0xef);
+        cpu.run(0x100).unwrap();
+        let port = cpu.port().unwrap();
+        assert_eq!(port.direction(), 0x2f);
+        assert_eq!(port.data(), 0x37);
+        assert_eq!(cpu.x, 0x27 | 0xc0);
+        assert_eq!(*pins.borrow(), vec![0xc0, 0xe7]);
+
+        // RESET turns the pins back into inputs
+        cpu.reset();
+        assert_eq!(cpu.port().unwrap().pins(), 0xef);
+        assert_eq!(pins.borrow().len(), 3);
+        // Other variants see plain RAM
+        assert!(run(CpuVariant::Nmos6502, &[NOP], &[]).port().is_none());
+    }
+
+    #[test]
+    fn mos_6510_port_writes_reach_ram() {
+        let program = [LDA_IMM, 0x2f, STA_ZP, 0x00, LDA_IMM, 0x35, STA_ZP, 0x01, LDA_ZP, 0x01];
+        let end = 0x100 + program.len();
+        let m = memory(&[(0x100, &program)]);
+        let mut cpu = Cpu::new(m, CpuVariant::Mos6510, Some(Box::new(EndListener { end })));
+        cpu.run(0x100).unwrap();
+        assert_eq!(cpu.memory.read(0x00), 0x2f);
+        assert_eq!(cpu.memory.read(0x01), 0x35);
+        assert_eq!(cpu.port().unwrap().data(), 0x35);
+        // Reads still come from the port, whose input pins float high
+        assert_eq!(cpu.a, 0x25 | 0xd0);
+    }
+
+    #[test]
+    fn jmp_indirect_page_wrap() {
+        // The pointer is at $01FF and the NMOS 6502 reads its high byte from $0100
+        let cpu = run(CpuVariant::Nmos6502, &[
+            LDA_IMM, 0x10, STA_ABS, 0xff, 0x01, LDA_IMM, 0x00, STA_ABS, 0x00, 0x01,
+            JMP_IND, 0xff, 0x01, 0, 0, 0, INX], &[]);
+        assert_eq!(cpu.x, 1);
+    }
+
+    #[test]
+    fn decimal_flags_nmos() {
+        // $99 + $01 = $00 in decimal but the NMOS 6502 takes N and Z from the binary result
+        let cpu = run(CpuVariant::Nmos6502, &[SED, CLC, LDA_IMM, 0x99, ADC_IMM, 0x01], &[]);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.p.c());
+        assert!(! cpu.p.z());
+        assert!(cpu.p.n());
+
+        let cpu = run(CpuVariant::Cmos65C02, &[SED, CLC, LDA_IMM, 0x99, ADC_IMM, 0x01], &[]);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.p.z());
+        assert!(! cpu.p.n());
+    }
+
+    /// The reset vector points at $0000, the NMI and IRQ vectors at a handler at $0300
+    const INTERRUPT_VECTORS: (usize, &[u8]) = (0xfffa, &[0x00, 0x03, 0x00, 0x00, 0x00, 0x03]);
+
+    #[test]
+    fn irq_honours_i_flag_and_pushes_b_clear() {
+        let m = memory(&[(0, &[NOP, CLI, NOP]), (0x300, &[LDX_IMM, 0x42]), INTERRUPT_VECTORS]);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, Some(Box::new(EndListener { end: 0x302 })));
+        // RESET sets the I flag so the IRQ has to wait for the CLI
+        cpu.reset();
+        cpu.set_irq(1 << 0, true);
+        cpu.set_irq(1 << 3, true);
+        cpu.set_irq(1 << 0, false);
+        assert!(cpu.irq_asserted());
+        cpu.run(cpu.pc).unwrap();
+        assert_eq!(cpu.x, 0x42);
+        // CLI delays the interrupt by one instruction, so the return address is past the
+        // last NOP
+        assert_eq!(cpu.memory.word(0x1fb), 0x0003);
+        let pushed_p = cpu.memory.read(0x1fa);
+        assert_eq!(pushed_p & 0x10, 0);
+        assert_eq!(pushed_p & 0x20, 0x20);
+        assert!(cpu.p.i());
+        // RESET: 7 cycles, NOP, CLI and NOP: 6, then 7 for the IRQ and 2 for the LDX
+        assert_eq!(cpu.cycles, 22);
+    }
+
+    #[test]
+    fn sei_and_plp_mask_the_irq_one_instruction_late() {
+        for cycle_accurate in [false, true] {
+            for program in [vec!(SEI, NOP), vec!(PLP, NOP)] {
+                let mut m = memory(&[(0, &program), (0x300, &[LDX_IMM, 0x42]), INTERRUPT_VECTORS]);
+                // PLP pulls P with I set from $0100
+                m.write(0x100, 0x04);
+                let mut cpu = Cpu::new(m, CpuVariant::Nmos6502, None);
+                cpu.cycle_accurate = cycle_accurate;
+                cpu.step().unwrap();
+                assert!(cpu.p.i());
+                // The IRQ was polled with I still clear, so it's taken after the instruction
+                cpu.set_irq(1, true);
+                let step = cpu.step().unwrap();
+                assert_eq!(step.interrupt, Some(Interrupt::Irq));
+                assert_eq!(cpu.x, 0x42);
+                assert_eq!(cpu.memory.read(0x100 + cpu.stack_pointer + 2), 0x01);
+                // Now it's masked
+                cpu.pc = 1;
+                assert_eq!(cpu.step().unwrap().interrupt, None);
+            }
+        }
+    }
+
+    #[test]
+    fn brk_pushes_b_set() {
+        let m = memory(&[(0, &[BRK, 0xff]), (0x300, &[PLA]), INTERRUPT_VECTORS]);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, Some(Box::new(EndListener { end: 0x301 })));
+        cpu.run(0).unwrap();
+        assert_eq!(cpu.a & 0x10, 0x10);
+        assert_eq!(cpu.memory.word(0x1fe), 0x0002);
+    }
+
+    #[test]
+    fn nmi_is_edge_triggered() {
+        let m = memory(&[(0, &[NOP, NOP]), (0x300, &[INX, RTI]), INTERRUPT_VECTORS]);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, Some(Box::new(EndListener { end: 2 })));
+        cpu.reset();
+        cpu.set_nmi(true);
+        cpu.set_nmi(true);
+        cpu.run(cpu.pc).unwrap();
+        // The NMI ignores the I flag and only fires once while the line is held
+        assert_eq!(cpu.x, 1);
+    }
+
+    #[test]
+    fn reset_uses_its_vector() {
+        let mut m = memory(&[INTERRUPT_VECTORS]);
+        m.write(0xfffc, 0x34);
+        m.write(0xfffd, 0x12);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+        cpu.reset();
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.stack_pointer, 0xfc);
+        assert!(cpu.p.i());
+        assert_eq!(cpu.cycles, 7);
+    }
+
+    #[test]
+    fn step_reports_each_instruction() {
+        let m = memory(&[(0, &[LDX_IMM, 0xff, STA_ABS_X, 0x01, 0x01, BNE, 0xfe, JSR, 0x00])]);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+
+        let step = cpu.step().unwrap();
+        assert_eq!((step.pc, step.opcode, step.operand), (0, LDX_IMM, Operand::Byte(0xff)));
+        assert_eq!(step.cycles, 2);
+        assert!(step.writes.is_empty());
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.operand, Operand::Word(0x0101));
+        assert_eq!(step.writes, vec![(0x200, 0)]);
+        // Stores always take the extra cycle, whether the page is crossed or not
+        assert_eq!(step.cycles, 5);
+
+        let step = cpu.step().unwrap();
+        assert!(step.branch_taken);
+        assert!(! step.page_crossed);
+        assert_eq!(step.cycles, 3);
+        assert_eq!(cpu.pc, 5);
+        assert_eq!(cpu.cycles, 10);
+    }
+
+    #[test]
+    fn step_services_interrupts_first() {
+        let m = memory(&[(0, &[NOP]), (0x300, &[PHA]), INTERRUPT_VECTORS]);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+        cpu.set_nmi(true);
+        let step = cpu.step().unwrap();
+        assert_eq!(step.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(step.pc, 0x300);
+        assert_eq!(step.cycles, 10);
+        assert_eq!(step.writes, vec![(0x1ff, 0x00), (0x1fe, 0x00), (0x1fd, 0x20), (0x1fc, 0x00)]);
+        assert_eq!(cpu.step().unwrap().interrupt, None);
+    }
+
+    #[test]
+    fn illegal_opcode_error() {
+        for cycle_accurate in [false, true].iter() {
+            let m = Memory::new_with_vec(vec!(NOP, 0x02), None);
+            let mut cpu = Cpu::new(m, CpuVariant::Nmos6502, None);
+            cpu.cycle_accurate = *cycle_accurate;
+            match cpu.run(0) {
+                Err(SixtyError::IllegalOpcode { pc, opcode }) => assert_eq!((pc, opcode), (1, 0x02)),
+                _ => panic!("JAM should stop the CPU"),
+            }
+            // The CPU stays on the illegal instruction
+            assert_eq!(cpu.pc, 1);
+            assert_eq!(cpu.cycles, 2);
+        }
+        // It's a NOP on the 65C02
+        let m = Memory::new_with_vec(vec!(NOP, 0x02), None);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+        assert!(cpu.run_cycles(4).is_ok());
+    }
+
+    #[test]
+    fn access_past_the_buffer_error() {
+        // The memory is only $200 bytes long
+        let m = Memory::new_with_vec(vec!(LDA_ABS, 0x00, 0x80), None);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+        assert!(matches!(cpu.step(), Err(SixtyError::AddressOutOfRange(0x8000))));
+        let m = Memory::new_with_vec(vec!(NOP, STA_ABS, 0x00, 0x80), None);
+        let mut cpu = Cpu::new(m, CpuVariant::Nmos6502, None);
+        cpu.cycle_accurate = true;
+        assert!(cpu.tick().is_ok());
+        assert!(cpu.tick().unwrap().is_some());
+        for _ in 0..3 {
+            assert!(cpu.tick().is_ok());
+        }
+        assert!(matches!(cpu.tick(), Err(SixtyError::AddressOutOfRange(0x8000))));
+        assert!(! cpu.in_instruction());
+    }
+
+    #[test]
+    fn missing_file_error() {
+        match Memory::new_with_file("does_not_exist.bin", None) {
+            Err(SixtyError::Io { file_name, .. }) => assert_eq!(file_name, "does_not_exist.bin"),
+            _ => panic!("The file doesn't exist"),
+        }
+    }
+
+    /// A homebrew board: RAM, a serial port at $6000 and a 32K ROM at $8000
+    struct Board {
+        ram: Vec<u8>,
+        rom: Vec<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Bus for Board {
+        fn read(&self, address: usize) -> u8 {
+            match address {
+                0..=0x5fff => self.ram[address],
+                0x6000..=0x7fff => 0,
+                _ => self.rom[address - 0x8000],
+            }
+        }
+
+        fn write(&mut self, address: usize, value: u8) {
+            match address {
+                0..=0x5fff => self.ram[address] = value,
+                0x6000 => self.output.push(value),
+                _ => {},
+            }
+        }
+    }
+
+    #[test]
+    fn custom_bus() {
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            LDX_IMM, 0x00, LDA_ABS_X, 0x20, 0x80, BEQ, 0x07, STA_ABS, 0x00, 0x60, INX, BNE, 0xf5,
+            NOP, STA_ABS, 0x00, 0x80];
+        rom[..program.len()].copy_from_slice(&program);
+        rom[0x20..0x23].copy_from_slice(b"HI\0");
+        rom[0x7ffc] = 0x00;
+        rom[0x7ffd] = 0x80;
+        let board = Board { ram: vec![0; 0x6000], rom, output: Vec::new() };
+        let mut cpu = Cpu::new(board, CpuVariant::Cmos65C02, None);
+        cpu.reset();
+        assert!(cpu.run_until(1000, |cpu| cpu.pc == 0x8011).unwrap());
+        assert_eq!(cpu.memory.output, b"HI");
+        // The ROM ignored the last write
+        assert_eq!(cpu.memory.rom[0], LDX_IMM);
+    }
+
+    struct Tracer {
+        accesses: Rc<RefCell<Vec<(BusCycle, u64)>>>,
+    }
+
+    impl MemoryListener for Tracer {
+        fn on_read_or_wrote(&mut self, access: BusCycle, cycle: u64) {
+            self.accesses.borrow_mut().push((access, cycle));
+        }
+    }
+
+    #[test]
+    fn memory_listener_sees_every_access() {
+        use AccessKind::*;
+        let mut buffer = vec![0; 0x2200];
+        buffer[..6].copy_from_slice(&[LDX_IMM, 0x01, LDA_ABS_X, 0xff, 0x20, PHA]);
+        buffer[0x2100] = 0x42;
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let m = Memory::new_with_vec(buffer, Some(Box::new(Tracer { accesses: accesses.clone() })));
+        let mut cpu = Cpu::new(m, CpuVariant::Nmos6502, None);
+        cpu.pc = 0;
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        let accesses: Vec<_> = accesses.borrow().iter()
+            .map(|(a, cycle)| (a.address, a.value, a.kind, *cycle))
+            .collect();
+        assert_eq!(accesses, vec![
+            (0x0000, LDX_IMM, OpcodeFetch, 0),
+            (0x0001, 0x01, OperandFetch, 1),
+            (0x0002, LDA_ABS_X, OpcodeFetch, 2),
+            (0x0003, 0xff, OperandFetch, 3),
+            (0x0004, 0x20, OperandFetch, 4),
+            // The page crossing reads $2000 before fixing the high byte
+            (0x2000, 0x00, DummyRead, 5),
+            (0x2100, 0x42, Read, 6),
+            (0x0005, PHA, OpcodeFetch, 7),
+            (0x0006, 0x00, DummyRead, 8),
+            (0x01ff, 0x42, StackPush, 9),
+        ]);
+        assert_eq!(cpu.cycles, 10);
+    }
+
+    #[test]
+    fn memory_listener_sees_reset_and_the_6510_port() {
+        use AccessKind::*;
+        let mut buffer = vec![0; 0x10000];
+        buffer[0x200..0x202].copy_from_slice(&[LDA_ZP, 0x01]);
+        buffer[0xfffc..0xfffe].copy_from_slice(&[0x00, 0x02]);
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let m = Memory::new_with_vec(buffer, Some(Box::new(Tracer { accesses: accesses.clone() })));
+        let mut cpu = Cpu::new(m, CpuVariant::Mos6510, None);
+        assert!(cpu.cycle_accurate);
+        // Away from the port, so that the dummy reads of the PC reach the bus
+        cpu.pc = 0x1234;
+        cpu.reset();
+        cpu.step().unwrap();
+        let accesses: Vec<_> = accesses.borrow().iter()
+            .map(|(a, cycle)| (a.address, a.value, a.kind, *cycle))
+            .collect();
+        assert_eq!(accesses, vec![
+            (0x1234, 0x00, DummyRead, 0),
+            (0x1234, 0x00, DummyRead, 1),
+            (0x01ff, 0x00, DummyRead, 2),
+            (0x01fe, 0x00, DummyRead, 3),
+            (0x01fd, 0x00, DummyRead, 4),
+            (0xfffc, 0x00, Read, 5),
+            (0xfffd, 0x02, Read, 6),
+            (0x0200, LDA_ZP, OpcodeFetch, 7),
+            (0x0201, 0x01, OperandFetch, 8),
+            // The port, whose pins are all inputs after reset
+            (0x0001, 0xff, Read, 9),
+        ]);
+        assert_eq!(cpu.stack_pointer, 0xfc);
+    }
+
+    /// Run `steps` instructions from `pc` with both engines, after poking `bytes` in a 64K
+    /// memory filled with $EA (NOP)
+    fn run_wrapping(variant: CpuVariant, pc: usize, bytes: &[(usize, &[u8])], steps: usize)
+            -> Vec<Cpu> {
+        [false, true].iter().map(|cycle_accurate| {
+            let mut all = vec![(0, &[NOP; 0x10000][..])];
+            all.extend_from_slice(bytes);
+            let mut cpu = Cpu::new(memory(&all), variant, None);
+            cpu.cycle_accurate = *cycle_accurate;
+            cpu.pc = pc;
+            for _ in 0..steps {
+                cpu.step().unwrap();
+            }
+            cpu
+        }).collect()
+    }
+
+    #[test]
+    fn pc_wraps_at_64k() {
+        for cpu in run_wrapping(CpuVariant::Nmos6502, 0xffff, &[], 1) {
+            assert_eq!(cpu.pc, 0);
+        }
+        // The operand of LDA # is at $0000
+        for cpu in run_wrapping(CpuVariant::Nmos6502, 0xffff, &[(0xffff, &[LDA_IMM]), (0, &[0x42])], 1) {
+            assert_eq!((cpu.pc, cpu.a), (1, 0x42));
+        }
+        // JSR at $FFFE pushes $0000, RTS returns to $0001
+        let bytes: &[(usize, &[u8])] = &[(0xfffe, &[JSR, 0x00]), (0, &[0x10]), (0x1000, &[RTS])];
+        for cpu in run_wrapping(CpuVariant::Cmos65C02, 0xfffe, bytes, 2) {
+            assert_eq!(cpu.memory.word(0x1fe), 0x0000);
+            assert_eq!(cpu.pc, 1);
+        }
+    }
+
+    #[test]
+    fn branches_wrap_across_zero() {
+        // Forward from $FFFC to $0002
+        for cpu in run_wrapping(CpuVariant::Nmos6502, 0xfffc, &[(0xfffc, &[BNE, 0x04])], 1) {
+            assert_eq!(cpu.pc, 0x0002);
+            // Taken and crossing a page
+            assert_eq!(cpu.cycles, 4);
+        }
+        // Backward from $0002 to $FFF8
+        for cpu in run_wrapping(CpuVariant::Nmos6502, 0x0002, &[(0x0002, &[BNE, 0xf4])], 1) {
+            assert_eq!(cpu.pc, 0xfff8);
+        }
+    }
+
+    #[test]
+    fn jmp_indirect_ffff() {
+        let bytes: &[(usize, &[u8])] = &[(0x1000, &[JMP_IND, 0xff, 0xff]), (0xffff, &[0x34]),
+            (0x0000, &[0x12]), (0xff00, &[0x56])];
+        // The NMOS 6502 reads the high byte from $FF00, the 65C02 from $0000
+        for cpu in run_wrapping(CpuVariant::Nmos6502, 0x1000, bytes, 1) {
+            assert_eq!(cpu.pc, 0x5634);
+        }
+        for cpu in run_wrapping(CpuVariant::Cmos65C02, 0x1000, bytes, 1) {
+            assert_eq!(cpu.pc, 0x1234);
+        }
+        // JMP ($FFFF,X) wraps the pointer too
+        let bytes: &[(usize, &[u8])] = &[(0x1000, &[LDX_IMM, 0x02, JMP_IND_X, 0xff, 0xff]),
+            (0x0001, &[0x78, 0x56])];
+        for cpu in run_wrapping(CpuVariant::Cmos65C02, 0x1000, bytes, 2) {
+            assert_eq!(cpu.pc, 0x5678);
+        }
+    }
+
+    #[test]
+    fn zero_page_pointers_wrap() {
+        // LDA ($FF),Y reads its pointer from $FF and $00, and the index wraps past $FFFF
+        let bytes: &[(usize, &[u8])] = &[(0x1000, &[LDY_IMM, 0x03, LDA_IND_Y, 0xff]),
+            (0x00ff, &[0xfe]), (0x0000, &[0xff]), (0x0001, &[0x42])];
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Cmos65C02].iter() {
+            for cpu in run_wrapping(*variant, 0x1000, bytes, 2) {
+                assert_eq!(cpu.a, 0x42);
+            }
+        }
+        // LDA ($80,X) with X=$7F reads its pointer from $FF and $00, LDA ($FF) as well
+        let bytes: &[(usize, &[u8])] = &[(0x1000, &[LDX_IMM, 0x7f, LDA_IND_X, 0x80, TAY, LDA_ZPI, 0xff]),
+            (0x00ff, &[0x34]), (0x0000, &[0x12]), (0x1234, &[0x99])];
+        for cpu in run_wrapping(CpuVariant::Cmos65C02, 0x1000, bytes, 4) {
+            assert_eq!((cpu.y, cpu.a), (0x99, 0x99));
+        }
+        // LDA $FF,X stays in page zero
+        let bytes: &[(usize, &[u8])] = &[(0x1000, &[LDX_IMM, 0x02, LDA_ZP_X, 0xff]), (0x0001, &[0x24])];
+        for cpu in run_wrapping(CpuVariant::Nmos6502, 0x1000, bytes, 2) {
+            assert_eq!(cpu.a, 0x24);
+        }
+    }
+
+    #[test]
+    fn absolute_indexed_wraps() {
+        let bytes: &[(usize, &[u8])] = &[(0x1000, &[LDX_IMM, 0x02, LDA_ABS_X, 0xff, 0xff]),
+            (0x0001, &[0x42])];
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Cmos65C02].iter() {
+            for cpu in run_wrapping(*variant, 0x1000, bytes, 2) {
+                assert_eq!(cpu.a, 0x42);
+            }
+        }
+        assert_eq!(memory(&[(0xffff, &[0x34]), (0, &[0x12])]).word(0xffff), 0x1234);
+    }
+
+    #[test]
+    fn run_cycles_carries_overshoot() {
+        let m = Memory::new_with_vec(vec!(NOP, JMP, 0x00, 0x00), None);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+        // NOP, JMP, NOP: 7 cycles, one more than the budget
+        assert_eq!(cpu.run_cycles(6).unwrap(), 7);
+        assert_eq!(cpu.pc, 1);
+        // Only 5 cycles left in the second budget: JMP, NOP
+        assert_eq!(cpu.run_cycles(6).unwrap(), 5);
+        assert_eq!(cpu.pc, 1);
+        assert_eq!(cpu.cycles, 12);
+    }
+
+    #[test]
+    fn run_until_predicate() {
+        let m = Memory::new_with_vec(vec!(INX, JMP, 0x00, 0x00), None);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+        assert!(cpu.run_until(1000, |cpu| cpu.x == 3).unwrap());
+        assert_eq!(cpu.cycles, 12);
+        // Resumes where it stopped
+        assert!(! cpu.run_until(5, |cpu| cpu.x == 0).unwrap());
+        assert_eq!(cpu.x, 4);
+    }
+
+    #[test]
+    fn page_crossed_by_stores_and_read_modify_writes() {
+        // Opcode, X, page crossed, cycles: only the read pays for the page crossing
+        let cases = [
+            (LDA_ABS_X, 0x0f, false, 4), (LDA_ABS_X, 0x20, true, 5),
+            (STA_ABS_X, 0x0f, false, 5), (STA_ABS_X, 0x20, true, 5),
+            (INC_ABS_X, 0x0f, false, 7), (INC_ABS_X, 0x20, true, 7),
+        ];
+        for cycle_accurate in [false, true] {
+            for (opcode, x, crossed, cycles) in cases {
+                let mut cpu = Cpu::new(memory(&[(0, &[opcode, 0xf0, 0x01])]), CpuVariant::Nmos6502, None);
+                cpu.cycle_accurate = cycle_accurate;
+                cpu.x = x;
+                let step = cpu.step().unwrap();
+                assert_eq!((step.page_crossed, step.cycles), (crossed, cycles), "{:02X} X={:02X}", opcode, x);
+            }
+        }
+    }
+
+    #[test]
+    fn run_until_carries_overshoot_to_run_cycles() {
+        let m = Memory::new_with_vec(vec!(NOP, JMP, 0x00, 0x00), None);
+        let mut cpu = Cpu::new(m, CpuVariant::Cmos65C02, None);
+        // NOP, JMP, NOP: 7 cycles, one more than the budget
+        assert_eq!(cpu.run_cycles(6).unwrap(), 7);
+        // Only 1 cycle left, the JMP goes 2 past it and the predicate stops on it
+        assert!(cpu.run_until(2, |cpu| cpu.pc == 0).unwrap());
+        assert_eq!(cpu.cycles, 10);
+        // 4 cycles left: NOP, JMP
+        assert_eq!(cpu.run_cycles(6).unwrap(), 5);
+        assert!(! cpu.run_until(4, |_| false).unwrap());
+        // 18 cycles budgeted, the last JMP went 2 past them
+        assert_eq!(cpu.cycles, 20);
+    }
+
+    #[test]
+    fn cycle_accurate_matches_instruction_mode() {
+        let mut buffer: Vec<u8> = (0..0x10000).map(|i| (i * 7 + 3) as u8).collect();
+        for variant in [CpuVariant::Nmos6502, CpuVariant::Cmos65C02].iter() {
+            for opcode in 0..=0xffu8 {
+                let name = variant.opcode_names()[opcode as usize];
+                if ["JAM", "ANE", "LXA", "SHA", "SHX", "SHY", "TAS", "LAS"].contains(&name) {
+                    continue;
+                }
+                // Branches cross a page from $02F0 but not from $0280
+                for pc in [0x280, 0x2f0].iter() {
+                    buffer[*pc..*pc + 3].copy_from_slice(&[opcode, 0x40, 0x12]);
+                    for (index, p) in [(0, 0), (0xff, 0xff)].iter() {
+                        let run = |cycle_accurate: bool| {
+                            let m = Memory::new_with_vec(buffer.clone(), None);
+                            let mut cpu = Cpu::new(m, *variant, None);
+                            cpu.cycle_accurate = cycle_accurate;
+                            cpu.pc = *pc;
+                            cpu.x = *index;
+                            cpu.y = *index;
+                            cpu.p.set_value(*p);
+                            let mut step = cpu.step().unwrap();
+                            // Only the cycle accurate mode does the NMOS double write
+                            let bus_cycles = std::mem::take(&mut step.bus_cycles);
+                            if cycle_accurate {
+                                step.writes = bus_cycles.iter()
+                                    .filter(|c| c.kind.is_write() && c.kind != AccessKind::DummyWrite)
+                                    .map(|c| (c.address, c.value))
+                                    .collect();
+                            }
+                            (step, cpu.a, cpu.x, cpu.y, cpu.p.value(), cpu.pc,
+                                cpu.stack_pointer, cpu.cycles)
+                        };
+                        assert_eq!(run(false), run(true), "{:?} opcode {:02X}", variant, opcode);
+                    }
+                }
+                buffer[0x280..0x283].copy_from_slice(&[0x83, 0x8a, 0x91]);
+                buffer[0x2f0..0x2f3].copy_from_slice(&[0x53, 0x5a, 0x61]);
+            }
+        }
+    }
+
+    fn bus_cycles(variant: CpuVariant, program: &[u8], x: u8) -> Vec<(usize, u8, AccessKind)> {
+        let mut cpu = Cpu::new(memory(&[(0, program), (0xc000, &[0x41])]), variant, None);
+        cpu.cycle_accurate = true;
+        cpu.x = x;
+        cpu.step().unwrap().bus_cycles.iter().map(|c| (c.address, c.value, c.kind)).collect()
+    }
+
+    #[test]
+    fn indexed_page_crossing_dummy_read() {
+        use AccessKind::*;
+        let program = [LDA_ABS_X, 0xf0, 0xbf];
+        // The NMOS 6502 reads the address before fixing its high byte...
+        assert_eq!(bus_cycles(CpuVariant::Nmos6502, &program, 0x20), vec![
+            (0, LDA_ABS_X, OpcodeFetch), (1, 0xf0, OperandFetch), (2, 0xbf, OperandFetch),
+            (0xbf10, 0, DummyRead), (0xc010, 0, Read)]);
+        // ... the 65C02 reads the last byte of the instruction again
+        assert_eq!(bus_cycles(CpuVariant::Cmos65C02, &program, 0x20)[3], (2, 0xbf, DummyRead));
+        assert_eq!(bus_cycles(CpuVariant::Nmos6502, &program, 0x0f).len(), 4);
+    }
+
+    #[test]
+    fn read_modify_write_double_write() {
+        use AccessKind::*;
+        let program = [INC_ABS, 0x00, 0xc0];
+        assert_eq!(bus_cycles(CpuVariant::Nmos6502, &program, 0)[3..], [
+            (0xc000, 0x41, Read), (0xc000, 0x41, DummyWrite), (0xc000, 0x42, Write)]);
+        assert_eq!(bus_cycles(CpuVariant::Cmos65C02, &program, 0)[3..], [
+            (0xc000, 0x41, Read), (0xc000, 0x41, DummyRead), (0xc000, 0x42, Write)]);
+    }
+
+    #[test]
+    fn tick_advances_one_cycle() {
+        let m = Memory::new_with_vec(vec!(LDA_IMM, 0x12, STA_ABS, 0x00, 0x01, INC_ABS, 0x00, 0x01), None);
+        let mut cpu = Cpu::new(m, CpuVariant::Nmos6502, None);
+        assert_eq!(cpu.tick().unwrap(), None);
+        assert!(cpu.in_instruction());
+        assert_eq!(cpu.tick().unwrap().map(|s| s.opcode), Some(LDA_IMM));
+        assert_eq!(cpu.a, 0x12);
+        // STA $0100 only writes on its fourth cycle
+        for _ in 0..3 {
+            assert_eq!(cpu.tick().unwrap(), None);
+            assert_eq!(cpu.memory.read(0x100), 0);
+        }
+        let step = cpu.tick().unwrap().unwrap();
+        assert_eq!(step.writes, vec![(0x100, 0x12)]);
+        assert_eq!(cpu.cycles, 6);
+        // INC $0100: the unmodified value is written back on the fifth cycle, then step()
+        // finishes the instruction
+        for _ in 0..5 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.memory.read(0x100), 0x12);
+        assert_eq!(cpu.cycles, 11);
+        let step = cpu.step().unwrap();
+        assert_eq!(step.cycles, 6);
+        assert_eq!(step.writes, vec![(0x100, 0x12), (0x100, 0x13)]);
+        assert_eq!(cpu.memory.read(0x100), 0x13);
+        assert_eq!(cpu.cycles, 12);
+        assert!(! cpu.in_instruction());
+    }
+
+    #[test]
+    fn functional_tests_65c816() {
+        // Emulation mode has to behave like a 6502
+        run_klaus_test("6502_functional_test.bin", CpuVariant::W65C816, false, vec![0x346c, 0x3469]);
+    }
+
+    #[test]
+    fn native_mode_register_widths() {
+        let cpu = run(CpuVariant::W65C816, &[
+            CLC, XCE, REP, 0x30, LDA_IMM, 0x34, 0x12, LDX_IMM, 0x78, 0x56, TAY,
+            SEP, 0x20, LDA_IMM, 0xff, XBA], &[]);
+        assert!(! cpu.e);
+        assert_eq!(cpu.x_register(), 0x5678);
+        assert_eq!(cpu.y_register(), 0x1234);
+        // XBA swapped the 8 bit A with the high byte left by the 16 bit LDA
+        assert_eq!(cpu.accumulator(), 0xff12);
+        assert!(! cpu.p.n());
+        assert!(cpu.p.m());
+        assert!(! cpu.p.x());
+
+        // Setting X clears the high bytes of the index registers
+        let cpu = run(CpuVariant::W65C816, &[CLC, XCE, REP, 0x10, LDX_IMM, 0x78, 0x56, SEP, 0x10],
+            &[]);
+        assert_eq!(cpu.x_register(), 0x78);
+    }
+
+    #[test]
+    fn native_mode_cycles() {
+        let m = Memory::new_with_vec(vec!(CLC, XCE, REP, 0x20, LDA_IMM, 0x34, 0x12, STA_ZP, 0x80), None);
+        let mut cpu = Cpu::new(m, CpuVariant::W65C816, None);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        let step = cpu.step().unwrap();
+        assert_eq!(step.operand, Operand::Word(0x1234));
+        assert_eq!(step.cycles, 3);
+        let step = cpu.step().unwrap();
+        assert_eq!(step.cycles, 4);
+        assert_eq!(step.writes, vec![(0x80, 0x34), (0x81, 0x12)]);
+    }
+
+    #[test]
+    fn long_addressing_and_block_move() {
+        let cpu = run(CpuVariant::W65C816, &[
+            CLC, XCE, REP, 0x30, LDA_IMM, 0xcd, 0xab, STA_LONG, 0x00, 0x20, 0x01,
+            LDA_IMM, 0x01, 0x00, LDX_IMM, 0x00, 0x20, LDY_IMM, 0x00, 0x30, MVN, 0x00, 0x01],
+            // Room for the word that STA long writes and MVN copies
+            &[(0x12000, &[0; 2])]);
+        assert_eq!(cpu.memory.read(0x12000), 0xcd);
+        assert_eq!(cpu.memory.read(0x3000), 0xcd);
+        assert_eq!(cpu.memory.read(0x3001), 0xab);
+        assert_eq!(cpu.accumulator(), 0xffff);
+        assert_eq!(cpu.x_register(), 0x2002);
+        assert_eq!(cpu.y_register(), 0x3002);
+        assert_eq!(cpu.dbr, 0);
+    }
+
+    #[test]
+    fn jsl_rtl() {
+        let cpu = run(CpuVariant::W65C816, &[JSL, 0x00, 0x00, 0x01],
+            &[(0x10000, &[LDA_IMM, 0x42, RTL])]);
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.pbr, 0);
+        assert_eq!(cpu.stack_register(), 0x1ff);
+    }
+
+    #[test]
+    fn data_bank_and_direct_page() {
+        let cpu = run(CpuVariant::W65C816, &[
+            CLC, XCE, PEA, 0x34, 0x12, PLD, LDA_IMM, 0x01, PHA, PLB, LDA_ABS, 0x00, 0x20],
+            &[(0x12000, &[0x99])]);
+        assert_eq!(cpu.d, 0x1234);
+        assert_eq!(cpu.dbr, 1);
+        assert_eq!(cpu.a, 0x99);
+    }
+
+    #[test]
+    fn decimal_16_bit() {
+        let cpu = run(CpuVariant::W65C816, &[
+            CLC, XCE, REP, 0x30, SED, CLC, LDA_IMM, 0x99, 0x19, ADC_IMM, 0x01, 0x00], &[]);
+        assert_eq!(cpu.accumulator(), 0x2000);
+        assert!(! cpu.p.c());
+        let cpu = run(CpuVariant::W65C816, &[
+            CLC, XCE, REP, 0x30, SED, SEC, LDA_IMM, 0x00, 0x20, SBC_IMM, 0x01, 0x00], &[]);
+        assert_eq!(cpu.accumulator(), 0x1999);
+        assert!(cpu.p.c());
+    }
+
+    #[test]
+    fn xce_back_to_emulation() {
+        let cpu = run(CpuVariant::W65C816, &[CLC, XCE, REP, 0x10, LDX_IMM, 0x34, 0x12, SEC, XCE], &[]);
+        assert!(cpu.e);
+        // The carry holds the previous E
+        assert!(! cpu.p.c());
+        assert_eq!(cpu.x_register(), 0x34);
+        assert_eq!(cpu.stack_register() & 0xff00, 0x100);
+    }
+
+    #[test]
+    fn native_brk_pushes_program_bank() {
+        let m = memory(&[(0, &[CLC, XCE, JML, 0x00, 0x00, 0x01]), (0x10000, &[BRK, 0xff]),
+            (NATIVE_BRK_VECTOR_L, &[0x00, 0x03])]);
+        let mut cpu = Cpu::new(m, CpuVariant::W65C816, None);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        let step = cpu.step().unwrap();
+        assert_eq!(step.pc, 0x10000);
+        assert_eq!(step.cycles, 8);
+        assert_eq!(cpu.pc, 0x300);
+        assert_eq!(cpu.pbr, 0);
+        // Program bank, return address past the signature byte and P, where bit 4 is X
+        assert_eq!(cpu.memory.read(0x1ff), 0x01);
+        assert_eq!(cpu.memory.word(0x1fd), 0x0002);
+        assert_eq!(cpu.memory.read(0x1fc) & 0x30, 0x30);
+    }
+
+    #[test]
+    fn w65c816_bus_cycles_and_unsupported_modes() {
+        use AccessKind::*;
+        let accesses = Rc::new(RefCell::new(Vec::new()));
+        let mut buffer = vec![0; 0x200];
+        buffer[..4].copy_from_slice(&[LDA_ABS, 0x00, 0x01, PHA]);
+        let m = Memory::new_with_vec(buffer, Some(Box::new(Tracer { accesses: accesses.clone() })));
+        let mut cpu = Cpu::new(m, CpuVariant::W65C816, None);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let accesses: Vec<_> = accesses.borrow().iter()
+            .map(|(a, cycle)| (a.address, a.kind, *cycle))
+            .collect();
+        // PHA has an internal cycle before its push
+        assert_eq!(accesses, vec![
+            (0, OpcodeFetch, 0), (1, OperandFetch, 1), (2, OperandFetch, 2), (0x100, Read, 3),
+            (3, OpcodeFetch, 4), (0x1ff, StackPush, 5)]);
+        assert_eq!(cpu.cycles, 7);
+
+        assert!(matches!(cpu.tick(), Err(SixtyError::Unsupported { variant: CpuVariant::W65C816, .. })));
+        cpu.cycle_accurate = true;
+        assert!(matches!(cpu.step(), Err(SixtyError::Unsupported { .. })));
+    }
+
+    #[test]
+    fn run_stops_with_the_location() {
+        let mut cpu = Cpu::new(memory(&[(0, &[NOP, NOP])]), CpuVariant::Cmos65C02,
+            Some(Box::new(EndListener { end: 2 })));
+        match cpu.run(0).unwrap() {
+            RunStatus::Stop(success, reason) => {
+                assert!(success);
+                assert_eq!(reason, "End of program at PC=0002");
+            },
+            RunStatus::Continue => panic!("run() returned Continue"),
+        }
+    }
+}
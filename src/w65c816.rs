@@ -0,0 +1,606 @@
+//! The WDC 65C816: a 65C02 with 16 bit registers and a 24 bit address bus. It starts in
+//! emulation mode where it behaves like a 65C02 (without the Rockwell bit instructions),
+//! XCE switches it to native mode where the M and X flags select 8 or 16 bit wide
+//! accumulator and index registers.
+//!
+//! The registers are kept in the same fields as the 6502 ones: `a`, `x` and `y` hold the
+//! low bytes and `b`, `xh` and `yh` the high bytes. In native mode `stack_pointer`
+//! holds the whole 16 bit stack pointer, in emulation mode it's the offset in page 1.
+
+use crate::bus::Bus;
+use crate::constants::{self, AddressingType, ADC_IMM, AND_IMM, BIT_IMM, CMP_IMM, COP_VECTOR_L,
+    CPX_IMM, CPY_IMM, EOR_IMM, IRQ_VECTOR_L, LDA_IMM, LDX_IMM, LDY_IMM, NATIVE_BRK_VECTOR_L,
+    NATIVE_COP_VECTOR_L, NATIVE_IRQ_VECTOR_L, NATIVE_NMI_VECTOR_L, NMI_VECTOR_L, ORA_IMM,
+    SBC_IMM, W65C816_SIZES, W65C816_TIMINGS};
+use crate::cpu::{Cpu, Interrupt, Operand, StepResult};
+use crate::cycle::{access, Access};
+use crate::decoder::Mnemonic::*;
+use crate::memory::{AccessKind, BusCycle};
+
+use AddressingType::*;
+
+impl<B: Bus> Cpu<B> {
+    /// P as pulled by PLP and RTI or changed by REP and SEP. M and X don't exist in emulation
+    /// mode, and the high bytes of the index registers are cleared when X gets set.
+    fn set_p(&mut self, value: u8) {
+        if self.e {
+            self.p.set_value(value);
+        } else {
+            self.p.set_native_value(value);
+            if self.p.x() {
+                self.xh = 0;
+                self.yh = 0;
+            }
+        }
+    }
+
+    pub(crate) fn reset_65c816(&mut self) {
+        if ! self.e {
+            self.stack_pointer &= 0xff;
+        }
+        self.e = true;
+        self.p.set_value(self.p.value());
+        self.xh = 0;
+        self.yh = 0;
+        self.d = 0;
+        self.dbr = 0;
+        self.pbr = 0;
+    }
+
+    /// Every access of the 65C816 goes through here and takes the next cycle, see
+    /// `Bus::on_bus_cycle()`. step_65c816() adds the internal cycles at the end of the
+    /// instruction.
+    fn read_byte(&mut self, address: usize, kind: AccessKind) -> u8 {
+        let value = self.get(address);
+        if self.memory.observes_bus_cycles() {
+            self.memory.on_bus_cycle(BusCycle { address, value, kind }, self.cycles);
+        }
+        self.cycles += 1;
+        value
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8, kind: AccessKind) {
+        self.set(address, value);
+        if self.memory.observes_bus_cycles() {
+            self.memory.on_bus_cycle(BusCycle { address, value, kind }, self.cycles);
+        }
+        self.cycles += 1;
+    }
+
+    fn read_value(&mut self, address: usize, wide: bool) -> u16 {
+        let low = self.read_byte(address & 0xff_ffff, AccessKind::Read) as u16;
+        if wide {
+            low | (self.read_byte((address + 1) & 0xff_ffff, AccessKind::Read) as u16) << 8
+        } else {
+            low
+        }
+    }
+
+    fn write_value(&mut self, address: usize, v: u16, wide: bool) {
+        self.write_byte(address & 0xff_ffff, v as u8, AccessKind::Write);
+        if wide {
+            self.write_byte((address + 1) & 0xff_ffff, (v >> 8) as u8, AccessKind::Write);
+        }
+    }
+
+    fn fetch_byte(&mut self) -> u8 {
+        let result = self.read_byte((self.pbr as usize) << 16 | self.pc, AccessKind::OperandFetch);
+        self.pc = (self.pc + 1) & 0xffff;
+        result
+    }
+
+    fn fetch_operand_word(&mut self) -> u16 {
+        let low = self.fetch_byte() as u16;
+        low | (self.fetch_byte() as u16) << 8
+    }
+
+    fn fetch_long(&mut self) -> usize {
+        let word = self.fetch_operand_word() as usize;
+        word | (self.fetch_byte() as usize) << 16
+    }
+
+    fn stack_push(&mut self, v: u8) {
+        let s = self.stack_register();
+        self.write_byte(s as usize, v, AccessKind::StackPush);
+        self.set_stack_register(s.wrapping_sub(1));
+    }
+
+    fn stack_pull(&mut self) -> u8 {
+        self.set_stack_register(self.stack_register().wrapping_add(1));
+        self.read_byte(self.stack_register() as usize, AccessKind::StackPull)
+    }
+
+    fn push_value(&mut self, v: u16, wide: bool) {
+        if wide {
+            self.stack_push((v >> 8) as u8);
+        }
+        self.stack_push(v as u8);
+    }
+
+    fn pull_value(&mut self, wide: bool) -> u16 {
+        let low = self.stack_pull() as u16;
+        if wide { low | (self.stack_pull() as u16) << 8 } else { low }
+    }
+
+    /// In emulation mode, with the direct page aligned on a page, indexing wraps around
+    /// in the page like on the 6502
+    fn page_wrap(&self) -> bool {
+        self.e && self.d & 0xff == 0
+    }
+
+    fn direct(&self, offset: u8, index: u16) -> usize {
+        if self.page_wrap() {
+            (self.d | offset.wrapping_add(index as u8) as u16) as usize
+        } else {
+            self.d.wrapping_add(offset as u16).wrapping_add(index) as usize
+        }
+    }
+
+    /// The 16 bit pointer at `address` in bank 0
+    fn direct_pointer(&mut self, address: usize) -> usize {
+        let next = if self.page_wrap() {
+            (address & 0xff00) | ((address + 1) & 0xff)
+        } else {
+            (address + 1) & 0xffff
+        };
+        let low = self.read_byte(address, AccessKind::Read) as usize;
+        low | (self.read_byte(next, AccessKind::Read) as usize) << 8
+    }
+
+    fn long_pointer(&mut self, address: usize) -> usize {
+        let word = self.direct_pointer(address);
+        word | (self.read_byte((address + 2) & 0xffff, AccessKind::Read) as usize) << 16
+    }
+
+    /// Reads take one more cycle when indexing crosses a page or the index is 16 bit wide
+    fn index_address(&mut self, base: usize, index: u16, read: bool) -> (usize, u64) {
+        let address = (base + index as usize) & 0xff_ffff;
+        let crossed = (base ^ address) & 0xff_ff00 != 0;
+        self.page_crossed |= crossed;
+        (address, (read && (crossed || self.wide_index())) as u64)
+    }
+
+    /// Fetch the operand and return the 24 bit effective address, with the cycles to add
+    /// to the timings table: one when the low byte of D isn't zero, see also `index_address()`
+    fn address_65c816(&mut self, addressing_type: AddressingType, read: bool) -> (usize, u64) {
+        let bank = (self.dbr as usize) << 16;
+        let direct_cycles = (self.d & 0xff != 0) as u64;
+        match addressing_type {
+            ZP | ZP_X | ZP_Y | ZPI | INDIRECT_X | INDIRECT_Y | INDIRECT_LONG | INDIRECT_LONG_Y => {
+                let offset = self.fetch_byte();
+                let (address, cycles) = match addressing_type {
+                    ZP => (self.direct(offset, 0), 0),
+                    ZP_X => (self.direct(offset, self.get_x()), 0),
+                    ZP_Y => (self.direct(offset, self.get_y()), 0),
+                    ZPI => (bank | self.direct_pointer(self.direct(offset, 0)), 0),
+                    INDIRECT_X => (bank | self.direct_pointer(self.direct(offset, self.get_x())), 0),
+                    INDIRECT_Y => {
+                        let base = bank | self.direct_pointer(self.direct(offset, 0));
+                        self.index_address(base, self.get_y(), read)
+                    },
+                    INDIRECT_LONG => (self.long_pointer(self.d.wrapping_add(offset as u16) as usize), 0),
+                    _ => {
+                        let base = self.long_pointer(self.d.wrapping_add(offset as u16) as usize);
+                        ((base + self.get_y() as usize) & 0xff_ffff, 0)
+                    },
+                };
+                (address, cycles + direct_cycles)
+            },
+            ABSOLUTE => (bank | self.fetch_operand_word() as usize, 0),
+            ABSOLUTE_X => {
+                let base = bank | self.fetch_operand_word() as usize;
+                self.index_address(base, self.get_x(), read)
+            },
+            ABSOLUTE_Y => {
+                let base = bank | self.fetch_operand_word() as usize;
+                self.index_address(base, self.get_y(), read)
+            },
+            ABSOLUTE_LONG => (self.fetch_long(), 0),
+            ABSOLUTE_LONG_X => ((self.fetch_long() + self.get_x() as usize) & 0xff_ffff, 0),
+            STACK_RELATIVE => {
+                let offset = self.fetch_byte() as u16;
+                (self.stack_register().wrapping_add(offset) as usize, 0)
+            },
+            STACK_RELATIVE_Y => {
+                let offset = self.fetch_byte() as u16;
+                let pointer = self.stack_register().wrapping_add(offset) as usize;
+                let base = bank | self.read_byte(pointer, AccessKind::Read) as usize
+                    | (self.read_byte((pointer + 1) & 0xffff, AccessKind::Read) as usize) << 8;
+                ((base + self.get_y() as usize) & 0xff_ffff, 0)
+            },
+            _ => unreachable!("{:?} has no effective address", addressing_type),
+        }
+    }
+
+    /// The value of an immediate operand or at the effective address, with the cycles to add
+    fn read_operand(&mut self, addressing_type: AddressingType, wide: bool) -> (u16, u64) {
+        if addressing_type == IMMEDIATE {
+            let v = if wide { self.fetch_operand_word() } else { self.fetch_byte() as u16 };
+            (v, wide as u64)
+        } else {
+            let (address, cycles) = self.address_65c816(addressing_type, true);
+            (self.read_value(address, wide), cycles + wide as u64)
+        }
+    }
+
+    fn store(&mut self, addressing_type: AddressingType, v: u16, wide: bool) -> u64 {
+        let (address, cycles) = self.address_65c816(addressing_type, false);
+        self.write_value(address, v, wide);
+        cycles + wide as u64
+    }
+
+    fn size_65c816(&self, opcode: u8) -> usize {
+        let size = W65C816_SIZES[opcode as usize];
+        match opcode {
+            ORA_IMM | AND_IMM | EOR_IMM | ADC_IMM | BIT_IMM | LDA_IMM | CMP_IMM | SBC_IMM
+                if self.wide_a() => size + 1,
+            LDX_IMM | LDY_IMM | CPX_IMM | CPY_IMM if self.wide_index() => size + 1,
+            _ => size
+        }
+    }
+
+    fn operand_65c816(&self, address: usize, opcode: u8) -> Operand {
+        let byte = |i: usize| self.peek((address + 1 + i) & 0xff_ffff);
+        match self.size_65c816(opcode) {
+            2 => Operand::Byte(byte(0)),
+            3 if opcode == constants::MVN || opcode == constants::MVP => Operand::BlockMove(byte(0), byte(1)),
+            3 => Operand::Word(byte(0) as u16 | (byte(1) as u16) << 8),
+            4 => Operand::Long(byte(0) as u32 | (byte(1) as u32) << 8 | (byte(2) as u32) << 16),
+            _ => Operand::None
+        }
+    }
+
+    /// step() for the 65C816
+    pub(crate) fn step_65c816(&mut self) -> StepResult {
+        self.writes.clear();
+        self.branch_taken = false;
+        self.page_crossed = false;
+        let start = self.cycles;
+
+        let interrupt = self.pending_interrupt(true);
+        let mut cycles = match interrupt {
+            Some(Interrupt::Nmi) => 7 + self.interrupt_65c816(NATIVE_NMI_VECTOR_L, NMI_VECTOR_L, false),
+            Some(Interrupt::Irq) => 7 + self.interrupt_65c816(NATIVE_IRQ_VECTOR_L, IRQ_VECTOR_L, false),
+            None => 0,
+        };
+
+        let pc = (self.pbr as usize) << 16 | self.pc;
+        let i = self.p.i();
+        let opcode = self.read_byte(pc, AccessKind::OpcodeFetch);
+        self.pc = (self.pc + 1) & 0xffff;
+        let operand = self.operand_65c816(pc, opcode);
+        cycles += W65C816_TIMINGS[opcode as usize] as u64 + self.execute_65c816(opcode);
+        let mnemonic = self.variant.mnemonics()[opcode as usize];
+        self.polled_i = if matches!(mnemonic, CLI | SEI | PLP) { Some(i) } else { None };
+        self.cycles = start + cycles;
+
+        StepResult {
+            pc,
+            opcode,
+            operand,
+            cycles,
+            branch_taken: self.branch_taken,
+            page_crossed: self.page_crossed,
+            writes: std::mem::take(&mut self.writes),
+            interrupt,
+            bus_cycles: Vec::new(),
+        }
+    }
+
+    /// Push the return address and P, then jump through the vector. Native mode also pushes
+    /// the program bank and takes one more cycle, which is returned.
+    fn interrupt_65c816(&mut self, native_vector: usize, emulation_vector: usize, brk: bool) -> u64 {
+        if ! self.e {
+            self.stack_push(self.pbr);
+        }
+        self.push_value(self.pc as u16, true);
+        // B only exists in the copy of P pushed in emulation mode
+        self.stack_push(self.p.value() | if brk && self.e { 1 << 4 } else { 0 });
+        self.p.set_i(true);
+        self.p.set_d(false);
+        self.pbr = 0;
+        let vector = if self.e { emulation_vector } else { native_vector };
+        self.pc = self.read_value(vector, true) as usize;
+        (! self.e) as u64
+    }
+
+    fn branch_65c816(&mut self, condition: bool) -> u64 {
+        let offset = self.fetch_byte() as i8 as u16;
+        if ! condition {
+            return 0;
+        }
+        let old = self.pc;
+        self.pc = (self.pc as u16).wrapping_add(offset) as usize;
+        self.branch_taken = true;
+        let crossed = (old ^ self.pc) & 0xff00 != 0;
+        self.page_crossed |= crossed;
+        // Crossing a page only costs a cycle in emulation mode
+        1 + (crossed && self.e) as u64
+    }
+
+    /// Run the instruction whose opcode was just fetched. Returns the cycles to add to the
+    /// timings table.
+    fn execute_65c816(&mut self, opcode: u8) -> u64 {
+        let addressing_type = self.variant.addressing_types()[opcode as usize];
+        let mnemonic = self.variant.mnemonics()[opcode as usize];
+        let (wide_a, wide_index) = (self.wide_a(), self.wide_index());
+        match mnemonic {
+            BPL => self.branch_65c816(! self.p.n()),
+            BMI => self.branch_65c816(self.p.n()),
+            BVC => self.branch_65c816(! self.p.v()),
+            BVS => self.branch_65c816(self.p.v()),
+            BCC => self.branch_65c816(! self.p.c()),
+            BCS => self.branch_65c816(self.p.c()),
+            BNE => self.branch_65c816(! self.p.z()),
+            BEQ => self.branch_65c816(self.p.z()),
+            // The "branch taken" cycle is already included in the timings table
+            BRA => self.branch_65c816(true) - 1,
+            BRL => {
+                let offset = self.fetch_operand_word();
+                self.pc = (self.pc as u16).wrapping_add(offset) as usize;
+                self.branch_taken = true;
+                0
+            },
+
+            REP => {
+                let v = self.fetch_byte();
+                self.set_p(self.p.value() & ! v);
+                0
+            },
+            SEP => {
+                let v = self.fetch_byte();
+                self.set_p(self.p.value() | v);
+                0
+            },
+            XCE => {
+                let carry = self.p.c();
+                self.p.set_c(self.e);
+                self.e = carry;
+                if self.e {
+                    self.xh = 0;
+                    self.yh = 0;
+                    self.stack_pointer &= 0xff;
+                    self.p.set_value(self.p.value());
+                } else {
+                    // Native mode starts with 8 bit registers
+                    self.p.set_native_value(self.p.value() | 0x30);
+                    self.stack_pointer |= 0x100;
+                }
+                0
+            },
+
+            TXY => {
+                let v = self.get_x();
+                self.set_y(v);
+                self.set_nz(v, wide_index);
+                0
+            },
+            TYX => {
+                let v = self.get_y();
+                self.set_x(v);
+                self.set_nz(v, wide_index);
+                0
+            },
+            TCD => {
+                self.d = self.accumulator();
+                self.set_nz(self.d, true);
+                0
+            },
+            TDC => {
+                self.a = self.d as u8;
+                self.b = (self.d >> 8) as u8;
+                self.set_nz(self.d, true);
+                0
+            },
+            TCS => {
+                self.set_stack_register(self.accumulator());
+                0
+            },
+            TSC => {
+                let s = self.stack_register();
+                self.a = s as u8;
+                self.b = (s >> 8) as u8;
+                self.set_nz(s, true);
+                0
+            },
+            XBA => {
+                std::mem::swap(&mut self.a, &mut self.b);
+                self.p.set_nz_flags(self.a);
+                0
+            },
+            PHA => { self.push_value(self.get_a(), wide_a); wide_a as u64 },
+            PHX => { self.push_value(self.get_x(), wide_index); wide_index as u64 },
+            PHY => { self.push_value(self.get_y(), wide_index); wide_index as u64 },
+            PLA => {
+                let v = self.pull_value(wide_a);
+                self.set_a(v);
+                self.set_nz(v, wide_a);
+                wide_a as u64
+            },
+            PLX => {
+                let v = self.pull_value(wide_index);
+                self.set_x(v);
+                self.set_nz(v, wide_index);
+                wide_index as u64
+            },
+            PLY => {
+                let v = self.pull_value(wide_index);
+                self.set_y(v);
+                self.set_nz(v, wide_index);
+                wide_index as u64
+            },
+            PHP => {
+                // In emulation mode, PHP always pushes P with the B bit set
+                self.stack_push(self.p.value() | if self.e { 1 << 4 } else { 0 });
+                0
+            },
+            PLP => {
+                let v = self.stack_pull();
+                self.set_p(v);
+                0
+            },
+            PHB => { self.stack_push(self.dbr); 0 },
+            PHK => { self.stack_push(self.pbr); 0 },
+            PLB => {
+                self.dbr = self.stack_pull();
+                self.p.set_nz_flags(self.dbr);
+                0
+            },
+            PHD => { self.push_value(self.d, true); 0 },
+            PLD => {
+                self.d = self.pull_value(true);
+                self.set_nz(self.d, true);
+                0
+            },
+            PEA => {
+                let v = self.fetch_operand_word();
+                self.push_value(v, true);
+                0
+            },
+            PEI => {
+                let offset = self.fetch_byte();
+                let v = self.direct_pointer(self.d.wrapping_add(offset as u16) as usize);
+                self.push_value(v as u16, true);
+                (self.d & 0xff != 0) as u64
+            },
+            PER => {
+                let offset = self.fetch_operand_word();
+                self.push_value((self.pc as u16).wrapping_add(offset), true);
+                0
+            },
+
+            JMP | JML | JSR if addressing_type == ABSOLUTE && mnemonic != JML => {
+                let target = self.fetch_operand_word();
+                if mnemonic == JSR {
+                    self.push_value(self.pc.wrapping_sub(1) as u16, true);
+                }
+                self.pc = target as usize;
+                0
+            },
+            JMP if addressing_type == INDIRECT => {
+                let pointer = self.fetch_operand_word() as usize;
+                self.pc = self.read_value(pointer, true) as usize;
+                0
+            },
+            JMP | JSR if addressing_type == AIX => {
+                let pointer = self.fetch_operand_word().wrapping_add(self.x_register());
+                if mnemonic == JSR {
+                    self.push_value(self.pc.wrapping_sub(1) as u16, true);
+                }
+                self.pc = self.read_value((self.pbr as usize) << 16 | pointer as usize, true) as usize;
+                0
+            },
+            JML => {
+                let target = if addressing_type == ABSOLUTE_LONG {
+                    self.fetch_long()
+                } else {
+                    let pointer = self.fetch_operand_word() as usize;
+                    let word = self.read_value(pointer, true) as usize;
+                    word | (self.read_byte(pointer + 2, AccessKind::Read) as usize) << 16
+                };
+                self.pbr = (target >> 16) as u8;
+                self.pc = target & 0xffff;
+                0
+            },
+            JSL => {
+                let target = self.fetch_long();
+                self.stack_push(self.pbr);
+                self.push_value(self.pc.wrapping_sub(1) as u16, true);
+                self.pbr = (target >> 16) as u8;
+                self.pc = target & 0xffff;
+                0
+            },
+            RTS => {
+                self.pc = (self.pull_value(true).wrapping_add(1)) as usize;
+                0
+            },
+            RTL => {
+                self.pc = (self.pull_value(true).wrapping_add(1)) as usize;
+                self.pbr = self.stack_pull();
+                0
+            },
+            RTI => {
+                let p = self.stack_pull();
+                self.set_p(p);
+                self.pc = self.pull_value(true) as usize;
+                if ! self.e {
+                    self.pbr = self.stack_pull();
+                }
+                (! self.e) as u64
+            },
+            BRK | COP => {
+                // Both skip their signature byte
+                self.fetch_byte();
+                if mnemonic == BRK {
+                    self.interrupt_65c816(NATIVE_BRK_VECTOR_L, IRQ_VECTOR_L, true)
+                } else {
+                    self.interrupt_65c816(NATIVE_COP_VECTOR_L, COP_VECTOR_L, false)
+                }
+            },
+
+            MVN | MVP => {
+                // One byte is moved per execution, the instruction repeats until C
+                // wraps to $FFFF
+                let destination = self.fetch_byte();
+                let source = self.fetch_byte();
+                let v = self.read_byte((source as usize) << 16 | self.get_x() as usize, AccessKind::Read);
+                self.write_byte((destination as usize) << 16 | self.get_y() as usize, v, AccessKind::Write);
+                let step = if mnemonic == MVN { 1 } else { 0xffff };
+                self.set_x(self.get_x().wrapping_add(step));
+                self.set_y(self.get_y().wrapping_add(step));
+                let count = self.accumulator().wrapping_sub(1);
+                self.a = count as u8;
+                self.b = (count >> 8) as u8;
+                self.dbr = destination;
+                if count != 0xffff {
+                    self.pc = (self.pc + 0x10000 - 3) & 0xffff;
+                }
+                0
+            },
+            WAI => {
+                // Wait by running WAI again until an interrupt line is asserted
+                if ! self.irq_asserted() && ! self.nmi_pending {
+                    self.pc = (self.pc + 0xffff) & 0xffff;
+                }
+                0
+            },
+            STP => {
+                // Stopped until the next reset
+                self.pc = (self.pc + 0xffff) & 0xffff;
+                0
+            },
+            WDM => {
+                self.fetch_byte();
+                0
+            },
+            NOP => 0,
+            _ => match addressing_type {
+                NONE | REGISTER_A => {
+                    self.implied(mnemonic);
+                    0
+                },
+                _ => match access(mnemonic) {
+                    Access::Read => {
+                        let wide = if matches!(mnemonic, LDX | LDY | CPX | CPY) { wide_index } else { wide_a };
+                        let (v, cycles) = self.read_operand(addressing_type, wide);
+                        self.read_operation(mnemonic, addressing_type == IMMEDIATE, v);
+                        cycles
+                    },
+                    Access::Write => match mnemonic {
+                        STA => self.store(addressing_type, self.get_a(), wide_a),
+                        STZ => self.store(addressing_type, 0, wide_a),
+                        STX => self.store(addressing_type, self.get_x(), wide_index),
+                        _ => self.store(addressing_type, self.get_y(), wide_index),
+                    },
+                    Access::Modify => {
+                        let (address, cycles) = self.address_65c816(addressing_type, false);
+                        let v = self.read_value(address, wide_a);
+                        let result = self.modify_operation(mnemonic, opcode, v);
+                        self.write_value(address, result, wide_a);
+                        cycles + 2 * wide_a as u64
+                    },
+                },
+            },
+        }
+    }
+}